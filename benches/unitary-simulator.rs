@@ -1,7 +1,8 @@
 #[macro_use] extern crate bencher;
 extern crate unitary_simulator;
 
-use unitary_simulator::UnitarySimulator;
+use unitary_simulator::circuit::Circuit;
+use unitary_simulator::simulator::Simulator;
 use unitary_simulator::python::QiskitPython;
 
 use bencher::Bencher;
@@ -10,8 +11,9 @@ fn bench_circuit1(b: &mut Bencher){
     let qiskit = QiskitPython::new().unwrap();
     let circuit = qiskit.get_qasm_circuit("example", "example/example.qasm").unwrap();
     let backend_circuit = qiskit.get_backend_circuit(circuit).unwrap();
-    let mut us = UnitarySimulator::new(backend_circuit.to_string()).unwrap();
-    b.iter(|| us.run());
+    let circuit = Circuit::new(backend_circuit.to_string()).unwrap();
+    let mut simulator = Simulator::new();
+    b.iter(|| simulator.run(&circuit));
 }
 
 benchmark_group!(benches, bench_circuit1);
@@ -0,0 +1,379 @@
+//! Building circuits from Rust.
+//!
+//! Qubits and classical bits can only be taken from the registers of a
+//! `CircuitBuilder`, so a gate cannot be applied to a classical bit or to a qubit
+//! that was never declared (or that was declared by another builder):
+//!
+//! ```ignore
+//! let mut builder = CircuitBuilder::new();
+//! let q = builder.qreg("q", 2);
+//! let c = builder.creg("c", 2);
+//! builder.h(q[0]).cx(q[0], q[1]).measure(q[0], c[0]).measure(q[1], c[1]);
+//! let circuit = builder.build()?;
+//! ```
+//!
+//! Gates are written as the U and CX operations the simulators run, the
+//! remaining mistakes (like a CX with the same control and target) make
+//! `build()` fail.
+
+use std::f64::consts::PI;
+use std::ops::Index;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_json;
+
+use circuit::Circuit;
+use expression::Expression;
+
+/// Identifies every `CircuitBuilder`, so that its qubits and classical bits are
+/// only accepted by the builder that declared them.
+static NEXT_BUILDER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A qubit of a `QuantumRegister`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Qubit {
+    builder: usize,
+    index: usize,
+}
+
+/// A classical bit of a `ClassicalRegister`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clbit {
+    builder: usize,
+    index: usize,
+}
+
+impl Qubit {
+    /// Index of the qubit in the circuit.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl Clbit {
+    /// Index of the classical bit in the circuit.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantumRegister {
+    name: String,
+    qubits: Vec<Qubit>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassicalRegister {
+    name: String,
+    clbits: Vec<Clbit>,
+}
+
+impl QuantumRegister {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.qubits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.qubits.is_empty()
+    }
+
+    pub fn qubits(&self) -> &[Qubit] {
+        &self.qubits
+    }
+}
+
+impl ClassicalRegister {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.clbits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clbits.is_empty()
+    }
+
+    pub fn clbits(&self) -> &[Clbit] {
+        &self.clbits
+    }
+}
+
+/// `q[i]` is the i-th qubit of the register, and panics when `i` is out of range.
+impl Index<usize> for QuantumRegister {
+    type Output = Qubit;
+
+    fn index(&self, index: usize) -> &Qubit {
+        &self.qubits[index]
+    }
+}
+
+impl Index<usize> for ClassicalRegister {
+    type Output = Clbit;
+
+    fn index(&self, index: usize) -> &Clbit {
+        &self.clbits[index]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBuilder {
+    id: usize,
+    qubit_labels: Vec<(String, usize)>,
+    clbit_labels: Vec<(String, usize)>,
+    operations: Vec<serde_json::Value>,
+    /// First mistake found, reported by `build()`.
+    error: Option<String>,
+}
+
+impl Default for CircuitBuilder {
+    fn default() -> CircuitBuilder {
+        CircuitBuilder {
+            id: NEXT_BUILDER_ID.fetch_add(1, Ordering::Relaxed),
+            qubit_labels: Vec::new(),
+            clbit_labels: Vec::new(),
+            operations: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+impl CircuitBuilder {
+    pub fn new() -> CircuitBuilder {
+        CircuitBuilder::default()
+    }
+
+    fn fail(&mut self, error: String) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    fn register_declared(&self, name: &str) -> bool {
+        self.qubit_labels.iter().chain(self.clbit_labels.iter()).any(|label| label.0 == name)
+    }
+
+    /// Declares a register of `size` qubits after the ones declared so far.
+    pub fn qreg(&mut self, name: &str, size: usize) -> QuantumRegister {
+        if self.register_declared(name) {
+            self.fail(format!("Error: Register '{}' is already declared!!", name));
+        }
+        let offset = self.qubit_labels.len();
+        self.qubit_labels.extend((0..size).map(|index| (name.to_string(), index)));
+        QuantumRegister {
+            name: name.to_string(),
+            qubits: (offset..offset + size).map(|index| Qubit { builder: self.id, index: index }).collect(),
+        }
+    }
+
+    /// Declares a register of `size` classical bits after the ones declared so far.
+    pub fn creg(&mut self, name: &str, size: usize) -> ClassicalRegister {
+        if self.register_declared(name) {
+            self.fail(format!("Error: Register '{}' is already declared!!", name));
+        }
+        let offset = self.clbit_labels.len();
+        self.clbit_labels.extend((0..size).map(|index| (name.to_string(), index)));
+        ClassicalRegister {
+            name: name.to_string(),
+            clbits: (offset..offset + size).map(|index| Clbit { builder: self.id, index: index }).collect(),
+        }
+    }
+
+    /// Checks that the qubit belongs to this builder, as it could come from the
+    /// register of another one.
+    fn check_qubit(&mut self, qubit: Qubit) {
+        if qubit.builder != self.id {
+            self.fail(format!("Error: Qubit {} is not declared in this circuit!!", qubit.index));
+        }
+    }
+
+    fn check_clbit(&mut self, clbit: Clbit) {
+        if clbit.builder != self.id {
+            self.fail(format!("Error: Classical bit {} is not declared in this circuit!!", clbit.index));
+        }
+    }
+
+    /// Numbers or symbolic expressions like "theta/2", bound when running.
+    fn check_param(&mut self, param: &serde_json::Value) {
+        if let Err(err) = Expression::from_json(param) {
+            self.fail(err);
+        }
+    }
+
+    pub fn u<T, P, L>(&mut self, theta: T, phi: P, lam: L, qubit: Qubit) -> &mut CircuitBuilder
+        where T: Into<serde_json::Value>, P: Into<serde_json::Value>, L: Into<serde_json::Value> {
+        let params = vec![theta.into(), phi.into(), lam.into()];
+        for param in params.iter() {
+            self.check_param(param);
+        }
+        self.check_qubit(qubit);
+        self.operations.push(json!({"name": "U", "qubits": [qubit.index], "params": params}));
+        self
+    }
+
+    pub fn cx(&mut self, control: Qubit, target: Qubit) -> &mut CircuitBuilder {
+        self.check_qubit(control);
+        self.check_qubit(target);
+        if control == target {
+            self.fail(format!("Error: CX with qubit {} as both control and target!!", control.index));
+        }
+        self.operations.push(json!({"name": "CX", "qubits": [control.index, target.index]}));
+        self
+    }
+
+    pub fn h(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(PI / 2f64, 0f64, PI, qubit)
+    }
+
+    pub fn x(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(PI, 0f64, PI, qubit)
+    }
+
+    pub fn y(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(PI, PI / 2f64, PI / 2f64, qubit)
+    }
+
+    pub fn z(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(0f64, 0f64, PI, qubit)
+    }
+
+    pub fn s(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(0f64, 0f64, PI / 2f64, qubit)
+    }
+
+    pub fn sdg(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(0f64, 0f64, -PI / 2f64, qubit)
+    }
+
+    pub fn t(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(0f64, 0f64, PI / 4f64, qubit)
+    }
+
+    pub fn tdg(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(0f64, 0f64, -PI / 4f64, qubit)
+    }
+
+    /// Rx(theta), up to a global phase.
+    pub fn rx<T: Into<serde_json::Value>>(&mut self, theta: T, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(theta, -PI / 2f64, PI / 2f64, qubit)
+    }
+
+    pub fn ry<T: Into<serde_json::Value>>(&mut self, theta: T, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(theta, 0f64, 0f64, qubit)
+    }
+
+    /// Rz(phi), up to a global phase.
+    pub fn rz<T: Into<serde_json::Value>>(&mut self, phi: T, qubit: Qubit) -> &mut CircuitBuilder {
+        self.u(0f64, 0f64, phi, qubit)
+    }
+
+    pub fn cz(&mut self, control: Qubit, target: Qubit) -> &mut CircuitBuilder {
+        self.h(target).cx(control, target).h(target)
+    }
+
+    pub fn swap(&mut self, a: Qubit, b: Qubit) -> &mut CircuitBuilder {
+        self.cx(a, b).cx(b, a).cx(a, b)
+    }
+
+    pub fn measure(&mut self, qubit: Qubit, clbit: Clbit) -> &mut CircuitBuilder {
+        self.check_qubit(qubit);
+        self.check_clbit(clbit);
+        self.operations.push(json!({"name": "measure", "qubits": [qubit.index], "clbits": [clbit.index]}));
+        self
+    }
+
+    pub fn reset(&mut self, qubit: Qubit) -> &mut CircuitBuilder {
+        self.check_qubit(qubit);
+        self.operations.push(json!({"name": "reset", "qubits": [qubit.index]}));
+        self
+    }
+
+    pub fn barrier(&mut self, qubits: &[Qubit]) -> &mut CircuitBuilder {
+        for &qubit in qubits {
+            self.check_qubit(qubit);
+        }
+        let qubits : Vec<usize> = qubits.iter().map(|qubit| qubit.index).collect();
+        self.operations.push(json!({"name": "barrier", "qubits": qubits}));
+        self
+    }
+
+    /// The circuit built so far, or the first mistake made building it.
+    pub fn build(&self) -> Result<Circuit, String> {
+        if let Some(ref error) = self.error {
+            return Err(error.clone());
+        }
+        Ok(Circuit {
+            number_of_qubits: self.qubit_labels.len(),
+            number_of_clbits: self.clbit_labels.len(),
+            operations: self.operations.clone(),
+            qubit_labels: self.qubit_labels.clone(),
+            clbit_labels: self.clbit_labels.clone(),
+            config: serde_json::Value::Null,
+        })
+    }
+}
+
+
+#[test]
+fn circuit_builder_test() {
+    use stabilizer::sample_counts;
+    use UnitarySimulator;
+
+    // Bell pair between two registers
+    let mut builder = CircuitBuilder::new();
+    let q = builder.qreg("q", 1);
+    let r = builder.qreg("r", 1);
+    let c = builder.creg("c", 2);
+    builder.h(q[0]).cx(q[0], r[0]).measure(q[0], c[0]).measure(r[0], c[1]);
+    let circuit = builder.build().unwrap();
+    assert_eq!(2, circuit.number_of_qubits);
+    assert_eq!(vec![("q".to_string(), 0), ("r".to_string(), 0)], circuit.qubit_labels);
+    assert_eq!(json!({"name": "CX", "qubits": [0, 1]}), circuit.operations[1]);
+
+    let mut us = UnitarySimulator::from_circuit(&circuit).unwrap();
+    us.run().unwrap();
+    assert!((us.unitary().get(3, 0).re() - 0.5f64.sqrt()).abs() < 1e-12);
+    let counts = sample_counts(circuit.to_json(), 100, 3).unwrap();
+    assert!(counts.counts().unwrap().keys().all(|key| key == "00" || key == "11"));
+
+    // Symbolic parameters are kept for binding
+    let mut builder = CircuitBuilder::new();
+    let q = builder.qreg("q", 2);
+    builder.ry("theta/2", q[1]).swap(q[0], q[1]);
+    assert_eq!(json!("theta/2"), builder.build().unwrap().operations[0]["params"][0]);
+    assert!(builder.rz("theta +", q[0]).build().is_err());
+
+    let mut builder = CircuitBuilder::new();
+    let q = builder.qreg("q", 2);
+    assert!(builder.cx(q[1], q[1]).build().is_err());
+
+    let mut other = CircuitBuilder::new();
+    let larger = other.qreg("q", 3);
+    let mut builder = CircuitBuilder::new();
+    let q = builder.qreg("q", 2);
+    assert!(builder.cx(q[0], larger[2]).build().is_err());
+    assert!(CircuitBuilder::new().x(larger[0]).build().is_err());
+    // In range of the builder, but still from another one
+    let mut builder = CircuitBuilder::new();
+    let q = builder.qreg("q", 2);
+    let c = builder.creg("c", 2);
+    assert!(builder.h(q[0]).x(larger[0]).build().is_err());
+    let mut builder = CircuitBuilder::new();
+    builder.qreg("q", 2);
+    assert!(builder.cx(larger[0], larger[1]).build().is_err());
+    let mut builder = CircuitBuilder::new();
+    let r = builder.qreg("r", 2);
+    builder.creg("c", 2);
+    assert!(builder.measure(r[0], c[0]).build().is_err());
+
+    let mut builder = CircuitBuilder::new();
+    builder.qreg("q", 1);
+    builder.creg("q", 1);
+    assert!(builder.build().is_err());
+}
@@ -0,0 +1,243 @@
+//! Compiled circuits.
+//!
+//! A `Circuit` is the parsed form of the compiled circuit JSON the simulators
+//! take: the size of the registers and the list of operations, which stay in
+//! their JSON form ({"name": "U", "qubits": [0], "params": [...]}, ...) as that
+//! is what `UnitarySimulator::run()` consumes.
+
+use serde_json;
+
+use expression::Expression;
+use stabilizer::circuit_size;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Circuit {
+    pub number_of_qubits: usize,
+    pub number_of_clbits: usize,
+    pub operations: Vec<serde_json::Value>,
+    /// Register name and index of every qubit, empty when the header has no
+    /// "qubit_labels".
+    pub qubit_labels: Vec<(String, usize)>,
+    pub clbit_labels: Vec<(String, usize)>,
+    /// Simulator options of the compiled circuit, like "endianness" or
+    /// "max_memory".
+    pub config: serde_json::Value,
+}
+
+impl Circuit {
+    pub fn new(compiled_circuit: String) -> Result<Circuit, String> {
+        let circuit : serde_json::Value = match serde_json::from_str(compiled_circuit.as_str()) {
+            Ok(_circuit) => _circuit,
+            Err(err) => return Err(format!("Error: parsing compiled circuit!!: {}", err)),
+        };
+        Circuit::from_json(circuit)
+    }
+
+    /// Same as `new()` but takes an already parsed compiled circuit.
+    pub fn from_json(circuit: serde_json::Value) -> Result<Circuit, String> {
+        let (number_of_qubits, number_of_clbits) = circuit_size(&circuit)?;
+        let operations = match circuit["operations"].as_array() {
+            Some(operations) => operations.clone(),
+            None => return Err("No operations field in the circuit!!".to_string()),
+        };
+        if let Some(operation) = operations.iter().find(|operation| !operation["name"].is_string()) {
+            return Err(format!("Error: Operation without a name: {}!!", operation));
+        }
+        Ok(Circuit {
+            number_of_qubits: number_of_qubits,
+            number_of_clbits: number_of_clbits,
+            operations: operations,
+            qubit_labels: labels(&circuit["header"]["qubit_labels"])?,
+            clbit_labels: labels(&circuit["header"]["clbit_labels"])?,
+            config: circuit["config"].clone(),
+        })
+    }
+
+    /// The compiled circuit JSON, which `from_json()` reads back.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut circuit = json!({
+            "header": {
+                "number_of_qubits": self.number_of_qubits,
+                "number_of_clbits": self.number_of_clbits
+            },
+            "operations": self.operations
+        });
+        if !self.qubit_labels.is_empty() {
+            circuit["header"]["qubit_labels"] = json!(self.qubit_labels);
+        }
+        if !self.clbit_labels.is_empty() {
+            circuit["header"]["clbit_labels"] = json!(self.clbit_labels);
+        }
+        if !self.config.is_null() {
+            circuit["config"] = self.config.clone();
+        }
+        circuit
+    }
+
+    /// OpenQASM 2.0 source of the circuit, declaring the registers of the labels
+    /// (or a "q" and a "c" register without them). Fails for operations and
+    /// unbound parameters OpenQASM 2.0 cannot express.
+    pub fn to_qasm(&self) -> Result<String, String> {
+        let qubits = register_names(&self.qubit_labels, self.number_of_qubits, "q");
+        let clbits = register_names(&self.clbit_labels, self.number_of_clbits, "c");
+        let mut qasm = String::from("OPENQASM 2.0;\ninclude \"qelib1.inc\";\n");
+        for &(ref name, size) in declarations(&qubits).iter() {
+            qasm.push_str(&format!("qreg {}[{}];\n", name, size));
+        }
+        for &(ref name, size) in declarations(&clbits).iter() {
+            qasm.push_str(&format!("creg {}[{}];\n", name, size));
+        }
+
+        for operation in self.operations.iter() {
+            let bit = |labels: &Vec<String>, key: &str, index: usize| -> Result<String, String> {
+                match operation[key][index].as_u64().and_then(|bit| labels.get(bit as usize)) {
+                    Some(label) => Ok(label.clone()),
+                    None => Err(format!("Error: Operation {} out of the circuit registers!!", operation)),
+                }
+            };
+            if !operation["modifiers"].is_null() {
+                return Err(format!("Error: Gate modifiers of {} have no OpenQASM 2.0 equivalent!!", operation));
+            }
+            match operation["name"].as_str().unwrap_or("") {
+                "U" => {
+                    let mut params = Vec::new();
+                    for index in 0..3 {
+                        let param = Expression::from_json(&operation["params"][index])?;
+                        if !param.parameters().is_empty() {
+                            return Err(format!("Error: Unbound parameters {:?} cannot be written to OpenQASM 2.0!!",
+                                               param.parameters()));
+                        }
+                        params.push(param.to_string());
+                    }
+                    qasm.push_str(&format!("U({}) {};\n", params.join(","), bit(&qubits, "qubits", 0)?));
+                },
+                "CX" => qasm.push_str(&format!("CX {},{};\n", bit(&qubits, "qubits", 0)?, bit(&qubits, "qubits", 1)?)),
+                "measure" => qasm.push_str(&format!("measure {} -> {};\n", bit(&qubits, "qubits", 0)?, bit(&clbits, "clbits", 0)?)),
+                "reset" => qasm.push_str(&format!("reset {};\n", bit(&qubits, "qubits", 0)?)),
+                "barrier" => {
+                    let count = operation["qubits"].as_array().map_or(0, |qubits| qubits.len());
+                    let arguments = if count == 0 {
+                        declarations(&qubits).into_iter().map(|(name, _)| name).collect::<Vec<_>>()
+                    } else {
+                        (0..count).map(|index| bit(&qubits, "qubits", index)).collect::<Result<Vec<_>, _>>()?
+                    };
+                    qasm.push_str(&format!("barrier {};\n", arguments.join(",")));
+                },
+                name => return Err(format!("Error: Operation '{}' has no OpenQASM 2.0 equivalent!!", name)),
+            }
+        }
+        Ok(qasm)
+    }
+}
+
+/// "name[index]" of every bit, from the labels if there is one per bit.
+fn register_names(labels: &[(String, usize)], size: usize, default: &str) -> Vec<String> {
+    if labels.len() == size {
+        labels.iter().map(|&(ref name, index)| format!("{}[{}]", name, index)).collect()
+    } else {
+        (0..size).map(|index| format!("{}[{}]", default, index)).collect()
+    }
+}
+
+/// Name and size of the registers of `names`, in order of appearance.
+fn declarations(names: &[String]) -> Vec<(String, usize)> {
+    let mut registers : Vec<(String, usize)> = Vec::new();
+    for name in names {
+        let (register, index) = name.split_at(name.find('[').unwrap());
+        let size = index[1..index.len() - 1].parse::<usize>().unwrap() + 1;
+        match registers.iter_mut().find(|declared| declared.0 == register) {
+            Some(declared) => declared.1 = declared.1.max(size),
+            None => registers.push((register.to_string(), size)),
+        }
+    }
+    registers
+}
+
+/// Reads labels like [["q", 0], ["q", 1], ["r", 0]].
+fn labels(labels: &serde_json::Value) -> Result<Vec<(String, usize)>, String> {
+    match labels.as_array() {
+        None => Ok(Vec::new()),
+        Some(labels) => labels.iter().map(|label| match (label[0].as_str(), label[1].as_u64()) {
+            (Some(name), Some(index)) => Ok((name.to_string(), index as usize)),
+            _ => Err(format!("Error: Wrong register label {}!!", label)),
+        }).collect(),
+    }
+}
+
+
+#[test]
+fn circuit_test() {
+    let json = json!({
+        "header": {
+            "number_of_qubits": 2,
+            "number_of_clbits": 1,
+            "qubit_labels": [["q", 0], ["r", 0]],
+            "clbit_labels": [["c", 0]]
+        },
+        "operations": [
+            {"name": "U", "qubits": [0], "params": ["pi/2", 0, "pi"]},
+            {"name": "CX", "qubits": [0, 1]},
+            {"name": "measure", "qubits": [1], "clbits": [0]}
+        ],
+        "config": {"endianness": "big"}
+    });
+    let circuit = Circuit::from_json(json.clone()).unwrap();
+    assert_eq!(2, circuit.number_of_qubits);
+    assert_eq!(3, circuit.operations.len());
+    assert_eq!(("r".to_string(), 0), circuit.qubit_labels[1]);
+    assert_eq!(json, circuit.to_json());
+    assert_eq!(circuit, Circuit::new(json.to_string()).unwrap());
+
+    assert!(Circuit::from_json(json!({"header": {"number_of_qubits": 1}})).is_err());
+    assert!(Circuit::from_json(json!({"header": {"number_of_qubits": 1}, "operations": [{"qubits": [0]}]})).is_err());
+}
+
+#[test]
+fn to_qasm_test() {
+    use builder::CircuitBuilder;
+
+    let mut builder = CircuitBuilder::new();
+    let q = builder.qreg("q", 2);
+    let anc = builder.qreg("anc", 1);
+    let c = builder.creg("c", 2);
+    builder.h(q[0]).cx(q[0], anc[0]).barrier(&[q[0], anc[0]]).u("pi/4", 0.5, -1f64, q[1])
+        .reset(anc[0]).measure(q[0], c[1]).measure(q[1], c[0]);
+    let circuit = builder.build().unwrap();
+    assert_eq!(format!("OPENQASM 2.0;
+include \"qelib1.inc\";
+qreg q[2];
+qreg anc[1];
+creg c[2];
+U({},0,{}) q[0];
+CX q[0],anc[0];
+barrier q[0],anc[0];
+U((pi / 4),0.5,-1) q[1];
+reset anc[0];
+measure q[0] -> c[1];
+measure q[1] -> c[0];
+", ::std::f64::consts::PI / 2f64, ::std::f64::consts::PI), circuit.to_qasm().unwrap());
+
+    // Without labels, the registers are "q" and "c"
+    let circuit = Circuit::from_json(json!({
+        "header": {"number_of_qubits": 2, "number_of_clbits": 1},
+        "operations": [{"name": "barrier", "qubits": []}, {"name": "measure", "qubits": [1], "clbits": [0]}]
+    })).unwrap();
+    assert_eq!("OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\ncreg c[1];\nbarrier q;\nmeasure q[1] -> c[0];\n",
+               circuit.to_qasm().unwrap());
+
+    let unbound = Circuit::from_json(json!({
+        "header": {"number_of_qubits": 1},
+        "operations": [{"name": "U", "qubits": [0], "params": ["theta", 0, 0]}]
+    })).unwrap();
+    assert!(unbound.to_qasm().is_err());
+    let snapshot = Circuit::from_json(json!({
+        "header": {"number_of_qubits": 1},
+        "operations": [{"name": "snapshot", "label": "start"}]
+    })).unwrap();
+    assert!(snapshot.to_qasm().is_err());
+    let toffoli = Circuit::from_json(json!({
+        "header": {"number_of_qubits": 3},
+        "operations": [{"name": "CX", "qubits": [0, 1, 2], "modifiers": [{"name": "ctrl"}]}]
+    })).unwrap();
+    assert!(toffoli.to_qasm().is_err());
+}
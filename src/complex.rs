@@ -3,7 +3,7 @@
 use std::cmp::PartialEq;
 use std::f64::consts::PI;
 use std::fmt;
-use std::ops::{Add, AddAssign, Mul, MulAssign, Neg};
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg};
 use num::{Zero, One};
 
 
@@ -81,6 +81,21 @@ impl Complex {
         }
     }
 
+    /// The complex conjugate, i.e. _re - im * i_.
+    pub fn conj(&self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    /// Compute the norm/absolute value, i.e. _|z|_.
+    pub fn abs(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// Compute the argument (phase) in the range _(-pi, pi]_.
+    pub fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
     /// The real part.
     pub fn re(&self) -> f64 {
         self.re
@@ -139,6 +154,32 @@ impl AddAssign<f64> for Complex {
     }
 }
 
+impl Sub<Complex> for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+impl SubAssign for Complex {
+    fn sub_assign(&mut self, rhs: Complex) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<f64> for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: f64) -> Complex {
+        Complex::new(self.re - rhs, self.im)
+    }
+}
+impl SubAssign<f64> for Complex {
+    fn sub_assign(&mut self, rhs: f64) {
+        *self = *self - rhs;
+    }
+}
+
 impl Mul<Complex> for Complex {
     type Output = Complex;
 
@@ -176,6 +217,34 @@ impl MulAssign<f64> for Complex {
     }
 }
 
+impl Div<Complex> for Complex {
+    type Output = Complex;
+
+    fn div(self, rhs: Complex) -> Complex {
+        let denominator = rhs.norm_sqr();
+        Complex::new((self.re * rhs.re + self.im * rhs.im) / denominator,
+                     (self.im * rhs.re - self.re * rhs.im) / denominator)
+    }
+}
+impl DivAssign for Complex {
+    fn div_assign(&mut self, rhs: Complex) {
+        *self = *self / rhs;
+    }
+}
+
+impl Div<f64> for Complex {
+    type Output = Complex;
+
+    fn div(self, rhs: f64) -> Complex {
+        Complex::new(self.re / rhs, self.im / rhs)
+    }
+}
+impl DivAssign<f64> for Complex {
+    fn div_assign(&mut self, rhs: f64) {
+        *self = *self / rhs;
+    }
+}
+
 impl Neg for Complex {
     type Output = Complex;
 
@@ -240,4 +309,16 @@ fn complex_test() {
     assert!(Complex::one().approx_eq(&x.pow(15)));
 
     assert_eq!(Complex::one(), c![7f64, 8f64].pow(0));
+
+    assert_eq!(c![-2f64, -2f64], c![1f64, 2f64] - c![3f64, 4f64]);
+    assert_eq!(c![1f64, 2f64], c![-5f64, 10f64] / c![3f64, 4f64]);
+    assert_eq!(c![0.5f64, 1f64], c![1f64, 2f64] / 2f64);
+    assert_eq!(c![1f64, -2f64], c![1f64, 2f64].conj());
+    assert_eq!(5f64, c![3f64, 4f64].abs());
+    assert!((c![0f64, -2f64].arg() + PI / 2f64).abs() < 1e-12);
+
+    let mut z = c![1f64, 2f64];
+    z -= c![3f64, 4f64];
+    z /= c![0f64, 1f64];
+    assert_eq!(z, c![-2f64, 2f64]);
 }
@@ -0,0 +1,86 @@
+//! Circuit equivalence checking.
+//!
+//! Two circuits are equivalent if their unitaries are equal up to a global phase,
+//! which is what we need to verify that a compiler transformation didn't change
+//! the behaviour of a circuit.
+
+use UnitarySimulator;
+
+/// Default tolerance used by `circuits_equivalent()`.
+pub const DEFAULT_TOLERANCE: f64 = 1e-9;
+
+/// Outcome of an equivalence check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equivalence {
+    /// Whether `U_b == e^{i phase} U_a` within the tolerance.
+    pub equivalent: bool,
+    /// Largest element-wise deviation once the global phase is removed.
+    pub max_deviation: f64,
+    /// Global phase between the two unitaries, in radians.
+    pub phase: f64,
+}
+
+/// Simulates both compiled circuits and compares their unitaries up to a global
+/// phase, using `DEFAULT_TOLERANCE`.
+pub fn circuits_equivalent(a: String, b: String) -> Result<Equivalence, String> {
+    circuits_equivalent_tol(a, b, DEFAULT_TOLERANCE)
+}
+
+/// Same as `circuits_equivalent()` with a custom tolerance.
+pub fn circuits_equivalent_tol(a: String, b: String, tolerance: f64) -> Result<Equivalence, String> {
+    let mut us_a = UnitarySimulator::new(a)?;
+    let mut us_b = UnitarySimulator::new(b)?;
+    for result in [us_a.run()?, us_b.run()?].iter() {
+        if !result.is_done() {
+            return Err("Error: Simulation of the circuit failed!!".to_string());
+        }
+    }
+
+    let (unitary_a, unitary_b) = (us_a.unitary(), us_b.unitary());
+    if unitary_a.size() != unitary_b.size() {
+        return Err(format!("Error: Circuits have different sizes: {} != {}!!",
+                           unitary_a.size(), unitary_b.size()));
+    }
+
+    let (phase, max_deviation) = unitary_a.deviation_up_to_global_phase(unitary_b);
+
+    debug!("circuits_equivalent: phase={} max_deviation={}", phase, max_deviation);
+
+    Ok(Equivalence {
+        equivalent: max_deviation <= tolerance,
+        max_deviation: max_deviation,
+        phase: phase,
+    })
+}
+
+
+#[test]
+fn circuits_equivalent_test() {
+    // H Z H == X, up to a global phase of pi
+    let hzh = r#"{
+        "header": {"number_of_qubits": 2},
+        "operations": [
+            {"name": "U", "qubits": [1], "params": ["pi/2", 0, "pi"]},
+            {"name": "U", "qubits": [1], "params": [0, 0, "pi"]},
+            {"name": "U", "qubits": [1], "params": ["pi/2", 0, "pi"]}
+        ]
+    }"#;
+    let x = r#"{
+        "header": {"number_of_qubits": 2},
+        "operations": [{"name": "U", "qubits": [1], "params": ["pi", "pi/2", "-pi/2"]}]
+    }"#;
+    let x_on_0 = r#"{
+        "header": {"number_of_qubits": 2},
+        "operations": [{"name": "U", "qubits": [0], "params": ["pi", 0, "pi"]}]
+    }"#;
+
+    let equivalence = circuits_equivalent(hzh.to_string(), x.to_string()).unwrap();
+    assert!(equivalence.equivalent);
+    assert!(equivalence.max_deviation < 1e-12);
+    // U(pi, pi/2, pi/2) = i X
+    assert!((equivalence.phase - ::std::f64::consts::PI / 2f64).abs() < 1e-12);
+
+    let equivalence = circuits_equivalent(hzh.to_string(), x_on_0.to_string()).unwrap();
+    assert!(!equivalence.equivalent);
+    assert!(equivalence.max_deviation > 0.5f64);
+}
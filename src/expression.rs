@@ -0,0 +1,482 @@
+//! Symbolic expressions for gate parameters.
+//!
+//! Compiled circuits coming out of the unroller usually carry plain numbers as
+//! gate parameters, but hand-written circuits (and OpenQASM itself) allow things
+//! like `pi/2` or named parameters like `theta`. This module provides a tiny
+//! expression AST with a parser and an evaluator that resolves named parameters
+//! against a binding map.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+
+use serde_json;
+
+/// Binary operators supported in expressions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+/// Unary functions supported in expressions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Exp,
+    Ln,
+    Sqrt,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "sin" => Some(Function::Sin),
+            "cos" => Some(Function::Cos),
+            "tan" => Some(Function::Tan),
+            "exp" => Some(Function::Exp),
+            "ln" => Some(Function::Ln),
+            "sqrt" => Some(Function::Sqrt),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Function::Sin => "sin",
+            Function::Cos => "cos",
+            Function::Tan => "tan",
+            Function::Exp => "exp",
+            Function::Ln => "ln",
+            Function::Sqrt => "sqrt",
+        }
+    }
+
+    fn apply(&self, x: f64) -> f64 {
+        match *self {
+            Function::Sin => x.sin(),
+            Function::Cos => x.cos(),
+            Function::Tan => x.tan(),
+            Function::Exp => x.exp(),
+            Function::Ln => x.ln(),
+            Function::Sqrt => x.sqrt(),
+        }
+    }
+}
+
+/// A parameter expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Pi,
+    Parameter(String),
+    Neg(Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+    Call(Function, Box<Expression>),
+}
+
+impl Expression {
+    /// Parse an expression like `-pi/2 + 2*theta`.
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens: tokens, position: 0 };
+        let expression = parser.expression()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(format!("Error: Unexpected token '{}' in expression '{}'!!", token, text)),
+        }
+    }
+
+    /// Build an expression from a JSON operation parameter, which can either be a
+    /// number or a string with a symbolic expression.
+    pub fn from_json(value: &serde_json::Value) -> Result<Expression, String> {
+        if let Some(number) = value.as_f64() {
+            return Ok(Expression::Number(number));
+        }
+        match value.as_str() {
+            Some(text) => Expression::parse(text),
+            None => Err(format!("Error: Invalid gate parameter: {}!!", value)),
+        }
+    }
+
+    /// Evaluate the expression, resolving named parameters with `parameters`.
+    ///
+    /// Returns an error if a parameter is not bound.
+    pub fn eval(&self, parameters: &HashMap<String, f64>) -> Result<f64, String> {
+        match *self {
+            Expression::Number(value) => Ok(value),
+            Expression::Pi => Ok(PI),
+            Expression::Parameter(ref name) => match parameters.get(name) {
+                Some(value) => Ok(*value),
+                None => Err(format!("Error: Unbound parameter '{}'!!", name)),
+            },
+            Expression::Neg(ref operand) => Ok(-operand.eval(parameters)?),
+            Expression::Binary(op, ref lhs, ref rhs) => {
+                let lhs = lhs.eval(parameters)?;
+                let rhs = rhs.eval(parameters)?;
+                Ok(match op {
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Mul => lhs * rhs,
+                    BinaryOp::Div => lhs / rhs,
+                    BinaryOp::Pow => lhs.powf(rhs),
+                })
+            },
+            Expression::Call(function, ref argument) => Ok(function.apply(argument.eval(parameters)?)),
+        }
+    }
+
+    /// Evaluate the derivative of the expression with respect to `parameter`.
+    ///
+    /// It is computed exactly by propagating (value, derivative) pairs through the
+    /// expression tree (forward-mode differentiation).
+    pub fn eval_derivative(&self, parameter: &str, parameters: &HashMap<String, f64>) -> Result<f64, String> {
+        Ok(self.eval_dual(parameter, parameters)?.1)
+    }
+
+    fn eval_dual(&self, parameter: &str, parameters: &HashMap<String, f64>) -> Result<(f64, f64), String> {
+        match *self {
+            Expression::Parameter(ref name) => {
+                let value = self.eval(parameters)?;
+                Ok((value, if name == parameter { 1f64 } else { 0f64 }))
+            },
+            Expression::Number(_) | Expression::Pi => Ok((self.eval(parameters)?, 0f64)),
+            Expression::Neg(ref operand) => {
+                let (u, du) = operand.eval_dual(parameter, parameters)?;
+                Ok((-u, -du))
+            },
+            Expression::Binary(op, ref lhs, ref rhs) => {
+                let (u, du) = lhs.eval_dual(parameter, parameters)?;
+                let (v, dv) = rhs.eval_dual(parameter, parameters)?;
+                Ok(match op {
+                    BinaryOp::Add => (u + v, du + dv),
+                    BinaryOp::Sub => (u - v, du - dv),
+                    BinaryOp::Mul => (u * v, du * v + u * dv),
+                    BinaryOp::Div => (u / v, (du * v - u * dv) / (v * v)),
+                    BinaryOp::Pow => {
+                        let value = u.powf(v);
+                        // d(u^v) = v u^(v-1) du + u^v ln(u) dv, skipping the terms that
+                        // vanish so constant exponents work with negative bases.
+                        let mut derivative = 0f64;
+                        if du != 0f64 {
+                            derivative += v * u.powf(v - 1f64) * du;
+                        }
+                        if dv != 0f64 {
+                            derivative += value * u.ln() * dv;
+                        }
+                        (value, derivative)
+                    },
+                })
+            },
+            Expression::Call(function, ref argument) => {
+                let (u, du) = argument.eval_dual(parameter, parameters)?;
+                let derivative = match function {
+                    Function::Sin => u.cos(),
+                    Function::Cos => -u.sin(),
+                    Function::Tan => 1f64 / (u.cos() * u.cos()),
+                    Function::Exp => u.exp(),
+                    Function::Ln => 1f64 / u,
+                    Function::Sqrt => 0.5f64 / u.sqrt(),
+                };
+                Ok((function.apply(u), derivative * du))
+            },
+        }
+    }
+
+    /// Names of the parameters this expression depends on, in order of appearance
+    /// and without duplicates.
+    pub fn parameters(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.collect_parameters(&mut names);
+        names
+    }
+
+    fn collect_parameters(&self, names: &mut Vec<String>) {
+        match *self {
+            Expression::Parameter(ref name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            },
+            Expression::Neg(ref operand) | Expression::Call(_, ref operand) => operand.collect_parameters(names),
+            Expression::Binary(_, ref lhs, ref rhs) => {
+                lhs.collect_parameters(names);
+                rhs.collect_parameters(names);
+            },
+            Expression::Number(_) | Expression::Pi => (),
+        }
+    }
+}
+
+impl From<f64> for Expression {
+    fn from(value: f64) -> Expression {
+        Expression::Number(value)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Number(value) => write!(f, "{}", value),
+            Expression::Pi => write!(f, "pi"),
+            Expression::Parameter(ref name) => write!(f, "{}", name),
+            Expression::Neg(ref operand) => write!(f, "-({})", operand),
+            Expression::Binary(op, ref lhs, ref rhs) => {
+                let symbol = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "*",
+                    BinaryOp::Div => "/",
+                    BinaryOp::Pow => "^",
+                };
+                write!(f, "({} {} {})", lhs, symbol, rhs)
+            },
+            Expression::Call(function, ref argument) => write!(f, "{}({})", function.name(), argument),
+        }
+    }
+}
+
+///
+/// Tokenizer
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Symbol(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Identifier(ref name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // Scientific notation: 1e-3, 2.5E+4
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let literal: String = chars[start..i].iter().collect();
+            match literal.parse::<f64>() {
+                Ok(value) => tokens.push(Token::Number(value)),
+                Err(_) => return Err(format!("Error: Invalid number '{}' in expression!!", literal)),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Identifier(chars[start..i].iter().collect()));
+        } else if "+-*/^()".contains(c) {
+            tokens.push(Token::Symbol(c));
+            i += 1;
+        } else {
+            return Err(format!("Error: Unexpected character '{}' in expression '{}'!!", c, text));
+        }
+    }
+    Ok(tokens)
+}
+
+///
+/// Recursive descent parser.
+///
+/// expression := term (('+' | '-') term)*
+/// term       := unary (('*' | '/') unary)*
+/// unary      := ('-' | '+') unary | power
+/// power      := primary ('^' unary)?
+/// primary    := number | 'pi' | identifier | function '(' expression ')' | '(' expression ')'
+///
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("Error: Expected '{}' in expression!!", symbol))
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, String> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = if self.eat('+') {
+                BinaryOp::Add
+            } else if self.eat('-') {
+                BinaryOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.term()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expression, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                BinaryOp::Mul
+            } else if self.eat('/') {
+                BinaryOp::Div
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.unary()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.eat('-') {
+            Ok(Expression::Neg(Box::new(self.unary()?)))
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Expression, String> {
+        let base = self.primary()?;
+        if self.eat('^') {
+            // Right associative: 2^3^2 == 2^(3^2)
+            let exponent = self.unary()?;
+            return Ok(Expression::Binary(BinaryOp::Pow, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expression::Number(value)),
+            Some(Token::Identifier(name)) => {
+                if name == "pi" {
+                    return Ok(Expression::Pi);
+                }
+                match Function::from_name(name.as_str()) {
+                    Some(function) => {
+                        self.expect('(')?;
+                        let argument = self.expression()?;
+                        self.expect(')')?;
+                        Ok(Expression::Call(function, Box::new(argument)))
+                    },
+                    None => Ok(Expression::Parameter(name)),
+                }
+            },
+            Some(Token::Symbol('(')) => {
+                let expression = self.expression()?;
+                self.expect(')')?;
+                Ok(expression)
+            },
+            Some(token) => Err(format!("Error: Unexpected token '{}' in expression!!", token)),
+            None => Err("Error: Unexpected end of expression!!".to_string()),
+        }
+    }
+}
+
+
+#[test]
+fn expression_eval_test() {
+    let no_params = HashMap::new();
+    let eval = |text: &str| Expression::parse(text).unwrap().eval(&no_params).unwrap();
+
+    assert!((eval("pi/2") - PI / 2f64).abs() < 1e-12);
+    assert!((eval("-pi/4 + 1") - (1f64 - PI / 4f64)).abs() < 1e-12);
+    assert!((eval("2^3^2") - 512f64).abs() < 1e-12);
+    assert!((eval("-2^2") + 4f64).abs() < 1e-12);
+    assert!((eval("sqrt(4) * cos(0) - ln(exp(1))") - 1f64).abs() < 1e-12);
+    assert!((eval("1.5e-1 * 2") - 0.3f64).abs() < 1e-12);
+    assert!((eval("sin(pi/2) + tan(0)") - 1f64).abs() < 1e-12);
+}
+
+#[test]
+fn expression_parameters_test() {
+    let expression = Expression::parse("2*theta - phi/theta").unwrap();
+    assert_eq!(vec!["theta".to_string(), "phi".to_string()], expression.parameters());
+
+    let mut parameters = HashMap::new();
+    parameters.insert("theta".to_string(), 2f64);
+    assert!(expression.eval(&parameters).is_err());
+
+    parameters.insert("phi".to_string(), 1f64);
+    assert!((expression.eval(&parameters).unwrap() - 3.5f64).abs() < 1e-12);
+}
+
+#[test]
+fn expression_derivative_test() {
+    let mut parameters = HashMap::new();
+    parameters.insert("x".to_string(), 0.5f64);
+    parameters.insert("y".to_string(), 2f64);
+    let derivative = |text: &str| Expression::parse(text).unwrap().eval_derivative("x", &parameters).unwrap();
+
+    assert!((derivative("3*x - y") - 3f64).abs() < 1e-12);
+    assert!((derivative("x^2 / y") - 0.5f64).abs() < 1e-12);
+    assert!((derivative("sin(2*x)") - 2f64 * 1f64.cos()).abs() < 1e-12);
+    assert!((derivative("y^x") - 2f64.sqrt() * 2f64.ln()).abs() < 1e-12);
+    assert!((derivative("sqrt(x) + ln(x) + exp(-x)") - (0.5f64 / 0.5f64.sqrt() + 2f64 - (-0.5f64).exp())).abs() < 1e-12);
+    assert_eq!(0f64, derivative("pi * y"));
+}
+
+#[test]
+fn expression_parse_errors_test() {
+    assert!(Expression::parse("pi +").is_err());
+    assert!(Expression::parse("(1 + 2").is_err());
+    assert!(Expression::parse("1 $ 2").is_err());
+    assert!(Expression::parse("sin 1").is_err());
+    assert!(Expression::from_json(&json!(true)).is_err());
+    assert_eq!(Expression::Number(0.5), Expression::from_json(&json!(0.5)).unwrap());
+}
@@ -5,6 +5,12 @@ use num::traits::{Zero, One};
 use std::cmp::PartialEq;
 use std::fmt;
 use std::fmt::{Debug};
+use std::f64::consts::PI;
+
+use serde_json;
+
+/// Tolerance used to check the input matrices are unitary.
+pub const UNITARY_TOLERANCE: f64 = 1e-8;
 
 #[derive(Debug)]
 pub struct Gate<T=Complex>
@@ -30,6 +36,122 @@ impl <T> Gate<T>
     }
 }
 
+impl Gate<Complex> {
+    /// The single-qubit U(theta, phi, lambda) gate.
+    pub fn u(theta: f64, phi: f64, lam: f64) -> Gate<Complex> {
+        Gate::<Complex>::from_slice(&[
+            Complex::new(f64::cos(theta/2.0f64),0.0f64),
+            -(Complex::i() * lam).exp() * f64::sin(theta / 2.0f64),
+            (Complex::i() * phi).exp() * Complex::new(f64::sin(theta / 2.0f64),0.0f64),
+            (Complex::i() * phi + Complex::i() * lam).exp() * Complex::new(f64::cos(theta / 2.0f64), 0.0f64)])
+    }
+
+    /// Time evolution gate e^{-iHt} for the Hermitian `hamiltonian`.
+    pub fn evolution(hamiltonian: &Matrix<Complex>, time: f64) -> Result<Gate<Complex>, String> {
+        if !hamiltonian.size().is_power_of_two() {
+            return Err(format!("Error: Hamiltonian size {} is not a power of two!!", hamiltonian.size()));
+        }
+        if (hamiltonian - &hamiltonian.adjoint()).frobenius_norm() > 1e-10 * hamiltonian.frobenius_norm().max(1f64) {
+            return Err("Error: Hamiltonian is not Hermitian!!".to_string());
+        }
+        let matrix = hamiltonian.scale(Complex::new(0f64, -time)).expm();
+        // Same size convention as from_slice(): log2 of the number of elements
+        Ok(Gate::new(2 * hamiltonian.size().trailing_zeros() as usize, matrix))
+    }
+
+    /// Reads a gate from the row-major [re, im] pairs of its matrix, as in the
+    /// params of a "unitary" operation, checking that it is a 2^k x 2^k unitary.
+    pub fn from_json(elements: &serde_json::Value) -> Result<Gate<Complex>, String> {
+        let elements = match elements.as_array() {
+            Some(elements) => elements,
+            None => return Err(format!("Error: Expected a list of matrix elements, got {}!!", elements)),
+        };
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements.iter() {
+            match (element[0].as_f64(), element[1].as_f64(), element.as_array().map(|pair| pair.len())) {
+                (Some(re), Some(im), Some(2)) => values.push(Complex::new(re, im)),
+                _ => return Err(format!("Error: Matrix element {} is not a [re, im] pair!!", element)),
+            }
+        }
+        let size = (values.len() as f64).sqrt().round() as usize;
+        if size * size != values.len() {
+            return Err(format!("Error: {} elements do not make a square matrix!!", values.len()));
+        }
+        if size < 2 || !size.is_power_of_two() {
+            return Err(format!("Error: Gate matrix size {} is not a power of two!!", size));
+        }
+        let matrix = Matrix::new_from_vector(size, values);
+        if !matrix.is_unitary(UNITARY_TOLERANCE) {
+            return Err("Error: Gate matrix is not unitary!!".to_string());
+        }
+        Ok(Gate::new(2 * size.trailing_zeros() as usize, matrix))
+    }
+
+    /// Number of qubits the gate acts on.
+    pub fn number_of_qubits(&self) -> usize {
+        self.matrix.size().trailing_zeros() as usize
+    }
+
+    /// The gate controlled by `n_controls` more qubits. They come first, as the
+    /// lowest bits of the basis index, and the gate applies when they are all 1.
+    pub fn controlled(&self, n_controls: usize) -> Gate<Complex> {
+        let size = self.matrix.size();
+        let mask = (1usize << n_controls) - 1;
+        let mut matrix = Matrix::<Complex>::identity(size << n_controls);
+        for i in 0..size {
+            for j in 0..size {
+                matrix.set(mask | (i << n_controls), mask | (j << n_controls), self.matrix.get(i, j));
+            }
+        }
+        Gate::new(2 * (self.number_of_qubits() + n_controls), matrix)
+    }
+
+    /// The adjoint of the gate.
+    pub fn inverse(&self) -> Gate<Complex> {
+        Gate::new(self.size, self.matrix.adjoint())
+    }
+
+    /// The gate raised to `exponent`. Integer powers are repeated products, and
+    /// fractional ones take the principal power of every eigenvalue, with -1 as
+    /// e^{i pi} so that X^0.5 is the usual square root of X.
+    pub fn pow(&self, exponent: f64) -> Result<Gate<Complex>, String> {
+        if !self.matrix.is_unitary(UNITARY_TOLERANCE) {
+            return Err("Error: Only unitary gates can be raised to a power!!".to_string());
+        }
+        if !exponent.is_finite() {
+            return Err(format!("Error: Invalid gate exponent {}!!", exponent));
+        }
+        if exponent.fract() == 0f64 && exponent.abs() < (1u64 << 63) as f64 {
+            let mut base = if exponent < 0f64 { self.matrix.adjoint() } else { self.matrix.clone() };
+            let mut matrix = Matrix::<Complex>::identity(self.matrix.size());
+            let mut remaining = exponent.abs() as u64;
+            while remaining > 0 {
+                if remaining & 1 == 1 {
+                    matrix = &matrix * &base;
+                }
+                base = &base * &base;
+                remaining >>= 1;
+            }
+            return Ok(Gate::new(self.size, matrix));
+        }
+        let (eigenvalues, eigenvectors) = self.matrix.eig_unitary()?;
+        let powers : Vec<Complex> = eigenvalues.iter().map(|eigenvalue| {
+            let angle = if eigenvalue.arg() <= -PI + 1e-12 { PI } else { eigenvalue.arg() };
+            (Complex::i() * (angle * exponent)).exp()
+        }).collect();
+        Ok(Gate::new(self.size, Matrix::from_eigendecomposition(&powers, &eigenvectors)))
+    }
+}
+
+impl Gate<f64> {
+    /// The CX gate, where the first qubit is the control.
+    pub fn cx() -> Gate<f64> {
+        Gate::<f64>::from_slice(&[1.0f64, 0.0f64, 0.0f64, 0.0f64, 0.0f64, 0.0f64,
+                                  0.0f64, 1.0f64, 0.0f64, 0.0f64, 1.0f64, 0.0f64,
+                                  0.0f64, 1.0f64, 0.0f64, 0.0f64])
+    }
+}
+
 // TODO make macros!
 // We want to emulate 2D Array indices, so we use a tuple like (row, col)
 impl Index<(usize,usize)> for Gate<Complex> {
@@ -79,3 +201,75 @@ impl fmt::Display for Gate<f64> {
         write!(f, "Gate({}): {}", self.size, self.matrix)
     }
 }
+
+
+#[test]
+fn evolution_test() {
+    // e^{-i (pi/2) Z} = -i Z
+    let z = Matrix::new_from_row_slice(&[Complex::one(), Complex::zero(), Complex::zero(), -Complex::one()]);
+    let gate = Gate::evolution(&z, ::std::f64::consts::PI / 2f64).unwrap();
+    assert_eq!(Complex::new(0f64, -1f64), gate[(0, 0)]);
+    assert_eq!(Complex::new(0f64, 1f64), gate[(1, 1)]);
+    assert_eq!(gate.size, Gate::<Complex>::u(0f64, 0f64, 0f64).size);
+
+    let not_hermitian = Matrix::new_from_row_slice(&[Complex::one(), Complex::one(), Complex::zero(), Complex::one()]);
+    assert!(Gate::evolution(&not_hermitian, 1f64).is_err());
+    assert!(Gate::evolution(&Matrix::identity(3), 1f64).is_err());
+}
+
+#[test]
+fn modifiers_test() {
+    let x = Gate::<Complex>::u(PI, 0f64, PI);
+    let cx = x.controlled(1);
+    assert_eq!(2, cx.number_of_qubits());
+    for &(i, j) in [(0, 0), (1, 3), (2, 2), (3, 1)].iter() {
+        assert_eq!(Complex::one(), cx[(i, j)]);
+    }
+    let ccx = x.controlled(2);
+    assert_eq!(Complex::one(), ccx[(7, 3)]);
+    assert_eq!(Complex::one(), ccx[(5, 5)]);
+
+    let t = Gate::<Complex>::u(0f64, 0f64, PI / 4f64);
+    assert!(t.inverse().matrix.approx_eq(&Gate::<Complex>::u(0f64, 0f64, -PI / 4f64).matrix));
+    assert!(t.pow(2f64).unwrap().matrix.approx_eq(&Gate::<Complex>::u(0f64, 0f64, PI / 2f64).matrix));
+    assert!(t.pow(-3f64).unwrap().matrix.approx_eq(&Gate::<Complex>::u(0f64, 0f64, -3f64 * PI / 4f64).matrix));
+    assert!(t.pow(0.5f64).unwrap().matrix.max_deviation(&Gate::<Complex>::u(0f64, 0f64, PI / 8f64).matrix) < 1e-10);
+    // sqrt(X) = [[1+i, 1-i], [1-i, 1+i]] / 2
+    let sx = x.pow(0.5f64).unwrap();
+    assert!((sx[(0, 0)] - Complex::new(0.5f64, 0.5f64)).abs() < 1e-10);
+    assert!((sx[(0, 1)] - Complex::new(0.5f64, -0.5f64)).abs() < 1e-10);
+    assert!(sx.pow(2f64).unwrap().matrix.max_deviation(&x.matrix) < 1e-10);
+    let u = Gate::new(4, ::linalg::test_unitary(4, 3));
+    assert!(u.pow(1f64 / 3f64).unwrap().pow(3f64).unwrap().matrix.max_deviation(&u.matrix) < 1e-10);
+    let controlled_root = u.controlled(1).pow(0.25f64).unwrap();
+    assert!(controlled_root.matrix.max_deviation(&u.pow(0.25f64).unwrap().controlled(1).matrix) < 1e-10);
+
+    // Eigenvalues 1 and e^{2i atan(0.618)}, which used to collide in eig_unitary
+    let h = Gate::<Complex>::u(PI / 2f64, 0f64, PI).matrix;
+    let mut diagonal = Matrix::<Complex>::identity(2);
+    diagonal.set(1, 1, &Complex::new_euler(1f64, 2f64 * 0.6180339887498949f64.atan()));
+    let u = Gate::new(2, &(&h * &diagonal) * &h);
+    assert!(u.pow(0.5f64).unwrap().pow(2f64).unwrap().matrix.max_deviation(&u.matrix) < 1e-10);
+
+    let not_unitary = Gate::<Complex>::from_slice(&[Complex::one(), Complex::one(), Complex::zero(), Complex::one()]);
+    assert!(not_unitary.pow(0.5f64).is_err());
+}
+
+#[test]
+fn from_json_test() {
+    let s = ::std::f64::consts::FRAC_1_SQRT_2;
+    let h = Gate::from_json(&json!([[s, 0], [s, 0], [s, 0], [-s, 0]])).unwrap();
+    assert_eq!(1, h.number_of_qubits());
+    assert!(h.matrix.approx_eq(&Gate::<Complex>::u(PI / 2f64, 0f64, PI).matrix));
+    let iswap = Gate::from_json(&json!([[1, 0], [0, 0], [0, 0], [0, 0],
+                                        [0, 0], [0, 0], [0, 1], [0, 0],
+                                        [0, 0], [0, 1], [0, 0], [0, 0],
+                                        [0, 0], [0, 0], [0, 0], [1, 0]])).unwrap();
+    assert_eq!(Complex::new(0f64, 1f64), *iswap.matrix.get(1, 2));
+
+    assert!(Gate::from_json(&json!([[1, 0], [0, 0], [0, 0]])).is_err());
+    assert!(Gate::from_json(&json!([[1, 0], [0, 0], [0, 0], [0, 0], [0, 0], [0, 0], [0, 0], [0, 0], [1, 0]])).is_err());
+    assert!(Gate::from_json(&json!([[1, 0], [1, 0], [0, 0], [1, 0]])).is_err());
+    assert!(Gate::from_json(&json!([1, 0, 0, 1])).is_err());
+    assert!(Gate::from_json(&json!("identity")).is_err());
+}
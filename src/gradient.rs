@@ -0,0 +1,135 @@
+//! Analytic gradients of expectation values via the parameter-shift rule.
+//!
+//! U(theta, phi, lam) = e^{i(phi+lam)/2} Rz(phi) Ry(theta) Rz(lam), so every angle
+//! of a U gate enters the circuit through a rotation generated by a Pauli
+//! operator. For those, the derivative of an expectation value f is exactly
+//!
+//! ```text
+//! df/dx = (f(x + pi/2) - f(x - pi/2)) / 2
+//! ```
+//!
+//! Symbolic gate parameters are handled with the chain rule: the derivative with
+//! respect to a circuit parameter adds up the shifts of every gate angle that
+//! depends on it, weighted by the derivative of the angle expression.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use observable::Observable;
+use parameterized::ParameterizedCircuit;
+use UnitarySimulator;
+
+/// Expectation value of `observable` on the state prepared by the circuit bound
+/// with `binding`, starting from |0...0>.
+pub fn expectation<O: Observable>(circuit: &ParameterizedCircuit, observable: &O,
+                                  binding: &HashMap<String, f64>) -> Result<f64, String> {
+    expectation_shifted(circuit, observable, binding, None)
+}
+
+fn expectation_shifted<O: Observable>(circuit: &ParameterizedCircuit, observable: &O,
+                                      binding: &HashMap<String, f64>, shift: Option<(usize, usize, f64)>)
+    -> Result<f64, String> {
+    let mut us = UnitarySimulator::from_json(circuit.bind_shifted(binding, shift)?)?;
+    if !us.run()?.is_done() {
+        return Err("Error: Simulation of the circuit failed!!".to_string());
+    }
+    observable.expectation_unitary(us.unitary())
+}
+
+/// Exact gradient of the expectation value of `observable` with respect to every
+/// parameter of `circuit`, evaluated at `binding`.
+///
+/// Returns an error if a parameter is used by a gate other than a plain U (no
+/// modifiers), since we only know the shift rule for U angles.
+pub fn parameter_shift_gradient<O: Observable>(circuit: &ParameterizedCircuit, observable: &O,
+                                               binding: &HashMap<String, f64>)
+    -> Result<HashMap<String, f64>, String> {
+    for op_exponents in circuit.modifier_exponents() {
+        if op_exponents.iter().any(|(_, expression)| !expression.parameters().is_empty()) {
+            return Err("Error: Cannot differentiate pow exponents!!".to_string());
+        }
+    }
+
+    let mut gradient : HashMap<String, f64> = circuit.parameters().iter()
+        .map(|name| (name.clone(), 0f64))
+        .collect();
+
+    for (j, op_params) in circuit.operation_params().iter().enumerate() {
+        for (k, expression) in op_params.iter().enumerate() {
+            let names = expression.parameters();
+            if names.is_empty() {
+                continue;
+            }
+
+            let operation = &circuit.circuit()["operations"][j];
+            if operation["name"] != "U" || !operation["modifiers"].is_null() {
+                return Err(format!("Error: Cannot differentiate parameters of gate {}!!", operation["name"]));
+            }
+
+            let plus = expectation_shifted(circuit, observable, binding, Some((j, k, PI / 2f64)))?;
+            let minus = expectation_shifted(circuit, observable, binding, Some((j, k, -PI / 2f64)))?;
+            let angle_derivative = (plus - minus) / 2f64;
+            debug!("parameter_shift_gradient: op={} param={} df/dangle={}", j, k, angle_derivative);
+
+            for name in names {
+                let chain = expression.eval_derivative(name.as_str(), binding)?;
+                *gradient.get_mut(&name).unwrap() += angle_derivative * chain;
+            }
+        }
+    }
+
+    Ok(gradient)
+}
+
+
+#[test]
+fn parameter_shift_gradient_test() {
+    use complex::Complex;
+    use matrix::Matrix;
+
+    let circuit = ParameterizedCircuit::new(r#"{
+        "header": {"number_of_qubits": 2},
+        "operations": [
+            {"name": "U", "qubits": [0], "params": ["2*a", "b", 0]},
+            {"name": "CX", "qubits": [0, 1]},
+            {"name": "U", "qubits": [1], "params": ["a - b", "pi/3", "b"]}
+        ]
+    }"#.to_string()).unwrap();
+
+    // Z on qubit 1 plus X on qubit 0, as a dense observable
+    let mut observable = Matrix::<Complex>::new(4);
+    for i in 0..4 {
+        let z1 = if i & 2 == 0 { 1f64 } else { -1f64 };
+        observable.set(i, i, &Complex::new(z1, 0f64));
+        observable.set(i, i ^ 1, &Complex::new(1f64, 0f64));
+    }
+
+    let mut binding = HashMap::new();
+    binding.insert("a".to_string(), 0.3f64);
+    binding.insert("b".to_string(), -0.7f64);
+
+    let gradient = parameter_shift_gradient(&circuit, &observable, &binding).unwrap();
+
+    // Compare against central finite differences
+    let epsilon = 1e-5f64;
+    for name in circuit.parameters() {
+        let mut plus = binding.clone();
+        *plus.get_mut(name).unwrap() += epsilon;
+        let mut minus = binding.clone();
+        *minus.get_mut(name).unwrap() -= epsilon;
+        let numeric = (expectation(&circuit, &observable, &plus).unwrap() -
+                       expectation(&circuit, &observable, &minus).unwrap()) / (2f64 * epsilon);
+        assert!((gradient[name] - numeric).abs() < 1e-7, "{}: {} != {}", name, gradient[name], numeric);
+    }
+
+    let modified = ParameterizedCircuit::new(r#"{
+        "header": {"number_of_qubits": 2},
+        "operations": [{"name": "U", "qubits": [0, 1], "params": ["a", 0, 0], "modifiers": [{"name": "ctrl"}]}]
+    }"#.to_string()).unwrap();
+    assert!(parameter_shift_gradient(&modified, &observable, &binding).is_err());
+    let power = ParameterizedCircuit::new(r#"{
+        "header": {"number_of_qubits": 1},
+        "operations": [{"name": "U", "qubits": [0], "params": [1, 0, 0], "modifiers": [{"name": "pow", "exponent": "a"}]}]
+    }"#.to_string()).unwrap();
+    assert!(parameter_shift_gradient(&power, &Matrix::<Complex>::identity(2), &binding).is_err());
+}
@@ -25,9 +25,9 @@ Based on the Python Unitary Simulator created by: Jay Gambetta and John Smolin
 It simulates a unitary of a quantum circuit that has been compiled to run on
 the simulator. It is exponential in the number of qubits.
 
-The input is the circuit object and the output is the same circuit object with
-a result field added results["data"]["unitary"] where the unitary is
-a 2**n x 2**n complex numpy array representing the unitary matrix.
+The input is the circuit object and the output is a `SimulationResult`, whose
+JSON form has the unitary in results["data"]["unitary"], a 2**n x 2**n complex
+matrix in row-major order.
 
 The input is
     compiled_circuit object
@@ -41,6 +41,7 @@ extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate nalgebra as na;
 extern crate num;
+extern crate rand;
 #[macro_use] extern crate log;
 extern crate env_logger;
 extern crate cpython;
@@ -49,127 +50,506 @@ pub mod python;
 pub mod macros;
 pub mod matrix;
 pub mod complex;
+pub mod linalg;
+pub mod sparse;
+pub mod memory;
+pub mod circuit;
+pub mod builder;
+pub mod qasm3;
+pub mod simulator;
+pub mod result;
 pub mod simulatortools;
 pub mod gate;
+pub mod expression;
+pub mod parameterized;
+pub mod observable;
+pub mod gradient;
+pub mod pauli;
+pub mod equivalence;
+pub mod synthesis;
+pub mod stabilizer;
+pub mod mps;
 
 use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::mem;
+use std::time::Instant;
 use complex::Complex;
 use gate::Gate;
+use expression::Expression;
 use simulatortools::*;
 use matrix::*;
+use sparse::SparseMatrix;
+use memory::{MemoryLimits, SimulatorError, unitary_memory};
+use observable::Observable;
+use pauli::PauliSum;
+use result::{ResultData, SimulationResult, Status};
+use circuit::Circuit;
 
 pub struct UnitarySimulator {
     circuit: serde_json::Value,
     number_of_qubits: usize,
-    result: HashMap<&'static str, serde_json::Value>,
+    result: SimulationResult,
+    /// The dense unitary, only allocated once the state densifies (or it is asked
+    /// for with `unitary()`).
     unitary_state: Matrix,
-    number_of_operations: usize
+    /// The unitary while it is sparse enough, `unitary_state` is only up to date
+    /// when this is `None` or `dense_synced` is set.
+    sparse_state: Option<SparseMatrix>,
+    dense_synced: bool,
+    /// Where the next dense product goes, swapped with `unitary_state` after every
+    /// gate.
+    scratch: Matrix,
+    number_of_operations: usize,
+    /// Index of the next operation `step()` applies.
+    next_operation: usize,
+    parameters: HashMap<String, f64>,
+    endianness: Endianness
 }
 
+/// Gets called after every operation applied by `step_with()` and `run_with()`,
+/// to debug circuits or look at how the state evolves.
+pub trait StepObserver {
+    /// `index` is the position of `operation` in the circuit, and `simulator` has
+    /// already applied it.
+    fn after_operation(&mut self, index: usize, operation: &serde_json::Value, simulator: &UnitarySimulator);
+}
+
+/// Does nothing, for `step()` and `run()`.
+impl StepObserver for () {
+    fn after_operation(&mut self, _index: usize, _operation: &serde_json::Value, _simulator: &UnitarySimulator) {}
+}
+
+impl<F> StepObserver for F where F: FnMut(usize, &serde_json::Value, &UnitarySimulator) {
+    fn after_operation(&mut self, index: usize, operation: &serde_json::Value, simulator: &UnitarySimulator) {
+        self(index, operation, simulator)
+    }
+}
+
+/// Density above which the sparse unitary is converted to a dense one. CSR
+/// stores a column index next to every value, so past this point it takes more
+/// memory than the dense matrix.
+const SPARSE_DENSITY_LIMIT: f64 = 0.5;
+
 
 impl UnitarySimulator {
-    pub fn new(compiled_circuit: String) -> Result<UnitarySimulator, String> {
+    pub fn new(compiled_circuit: String) -> Result<UnitarySimulator, SimulatorError> {
         let circuit : serde_json::Value = match serde_json::from_str(compiled_circuit.as_str()) {
             Ok(_circuit) => _circuit,
-            Err(err) => return Err(format!("Error: parsing compiled circuit!!: {}", err)),
+            Err(err) => return Err(SimulatorError::Circuit(format!("Error: parsing compiled circuit!!: {}", err))),
         };
+        UnitarySimulator::from_json(circuit)
+    }
+
+    /// Same as `new()` but takes an already parsed compiled circuit. The memory
+    /// limits come from the circuit config, see `MemoryLimits::from_circuit()`.
+    pub fn from_json(circuit: serde_json::Value) -> Result<UnitarySimulator, SimulatorError> {
+        let limits = MemoryLimits::from_circuit(&circuit)?;
+        UnitarySimulator::from_json_with_limits(circuit, &limits)
+    }
+
+    /// Same as `new()` but takes a `Circuit`, like the ones `CircuitBuilder` makes.
+    pub fn from_circuit(circuit: &Circuit) -> Result<UnitarySimulator, SimulatorError> {
+        UnitarySimulator::from_json(circuit.to_json())
+    }
 
-        let mut result = HashMap::new();
-        result.insert("data",json!({"unitary":{}}));
-        result.insert("result", json!({}));
-        result.insert("status", json!({}));
+    /// Same as `from_json()`, but fails with `SimulatorError::Memory` without
+    /// allocating anything if the estimated memory of the unitary is over `limits`.
+    pub fn from_json_with_limits(circuit: serde_json::Value, limits: &MemoryLimits) -> Result<UnitarySimulator, SimulatorError> {
+        let result = SimulationResult::new(circuit["header"].clone());
 
         let number_of_qubits = match circuit["header"]["number_of_qubits"].as_u64() {
             Some(val) => val,
-            None => return Err("No number_of_qubits field in the circuit!!".to_string()),
+            None => return Err(SimulatorError::Circuit("No number_of_qubits field in the circuit!!".to_string())),
         };
 
         let number_of_operations = match circuit["operations"].as_array() {
             Some(operations) => operations.len(),
-            None => return Err("No operations field in the circuit!!".to_string())
+            None => return Err(SimulatorError::Circuit("No operations field in the circuit!!".to_string()))
         };
 
+        let endianness = Endianness::from_circuit(&circuit)?;
+        limits.check(number_of_qubits as usize, unitary_memory(number_of_qubits as usize))?;
         let possible_states = 2usize.pow(number_of_qubits as u32);
-        let unitary_state = Matrix::identity(possible_states);
 
-        debug!("new: number_of_qubits={} number_of_operations={} unitary_state.size={}",
-                number_of_qubits, number_of_operations, unitary_state.size());
+        debug!("new: number_of_qubits={} number_of_operations={} possible_states={}",
+                number_of_qubits, number_of_operations, possible_states);
 
         Ok(UnitarySimulator {
             circuit : circuit,
             number_of_qubits: number_of_qubits as usize,
             result: result,
-            unitary_state: unitary_state,
-            number_of_operations: number_of_operations as usize
+            unitary_state: Matrix::new(0),
+            sparse_state: Some(SparseMatrix::identity(possible_states)),
+            dense_synced: false,
+            scratch: Matrix::new(0),
+            number_of_operations: number_of_operations as usize,
+            next_operation: 0,
+            parameters: HashMap::new(),
+            endianness: endianness
         })
     }
 
+    /// Binds values to the named parameters used in symbolic gate parameters.
+    pub fn set_parameters(&mut self, parameters: HashMap<String, f64>) {
+        self.parameters = parameters;
+    }
+
+    /// Order of the qubits in the "unitary" of the results, overriding the circuit
+    /// config.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// Evaluates the gate parameter at `index`, which can either be a number or a
+    /// symbolic expression like "pi/2" or "2*theta".
+    fn param(&self, c_qasm: &serde_json::Value, index: usize) -> Result<f64, String> {
+        Expression::from_json(&c_qasm["params"][index])?.eval(&self.parameters)
+    }
+
+    /// The unitary of the operations applied so far, always little-endian (qubit 0
+    /// is the least significant bit of the basis state index). A sparse unitary is
+    /// converted to a dense one first, see `current_unitary()` for a copy instead.
+    pub fn unitary(&mut self) -> &Matrix {
+        self.sync_dense();
+        &self.unitary_state
+    }
+
+    /// Brings `unitary_state` up to date with the sparse unitary, if any.
+    fn sync_dense(&mut self) {
+        if self.dense_synced {
+            return;
+        }
+        if let Some(ref state) = self.sparse_state {
+            state.to_dense_into(&mut self.unitary_state);
+            self.dense_synced = true;
+        }
+    }
+
+    /// The state the operations applied so far take |0...0> to, in the configured
+    /// endianness.
+    pub fn statevector(&self) -> Vec<Complex> {
+        self.endianness.reorder_state(&self.first_column(), self.number_of_qubits)
+    }
+
+    /// Column 0 of the unitary, little-endian, without densifying it.
+    fn first_column(&self) -> Vec<Complex> {
+        match self.sparse_state {
+            Some(ref sparse) => (0..sparse.size()).map(|i| sparse.get(i, 0)).collect(),
+            None => self.unitary_state.column(0),
+        }
+    }
+
+    /// Copy of the unitary of the operations applied so far, which is what
+    /// `unitary()` only returns after `run()`.
+    pub fn current_unitary(&self) -> Matrix {
+        match self.sparse_state {
+            Some(ref sparse) => sparse.to_dense(),
+            None => self.unitary_state.clone(),
+        }
+    }
+
+    /// Index of the next operation `step()` applies, which is the number of
+    /// operations when the circuit is done.
+    pub fn next_operation(&self) -> usize {
+        self.next_operation
+    }
+
+    /// Replaces the circuit with one on the same number of qubits, reusing the
+    /// allocated unitaries.
+    fn load(&mut self, circuit: serde_json::Value) -> Result<(), String> {
+        let number_of_operations = match circuit["operations"].as_array() {
+            Some(operations) => operations.len(),
+            None => return Err("No operations field in the circuit!!".to_string())
+        };
+        self.endianness = Endianness::from_circuit(&circuit)?;
+        self.circuit = circuit;
+        self.number_of_operations = number_of_operations;
+        self.reset();
+        Ok(())
+    }
+
+    /// Goes back to the identity, before the first operation. The dense unitary
+    /// is kept allocated, it is rewritten once the state densifies again.
+    pub fn reset(&mut self) {
+        let possible_states = 2usize.pow(self.number_of_qubits as u32);
+        self.sparse_state = Some(SparseMatrix::identity(possible_states));
+        self.dense_synced = false;
+        self.next_operation = 0;
+        self.result.data = ResultData::default();
+    }
+
+    /// The unitary accumulated by `run()`, if it stayed sparse (for instance,
+    /// circuits of X and CX gates give permutation matrices).
+    pub fn sparse_unitary(&self) -> Option<&SparseMatrix> {
+        self.sparse_state.as_ref()
+    }
+
+    /// Multiplies the enlarged gate into the unitary, converting it to a dense
+    /// matrix once it fills up.
+    fn add_unitary_sparse(&mut self, unitary_add: &SparseMatrix) {
+        match self.sparse_state.take() {
+            Some(state) => {
+                self.dense_synced = false;
+                let product = unitary_add * &state;
+                debug!("add_unitary_sparse: nnz={} density={}", product.nnz(), product.density());
+                if product.density() > SPARSE_DENSITY_LIMIT {
+                    product.to_dense_into(&mut self.unitary_state);
+                } else {
+                    self.sparse_state = Some(product);
+                }
+            },
+            None => {
+                if self.scratch.size() != self.unitary_state.size() {
+                    self.scratch = Matrix::new(self.unitary_state.size());
+                }
+                unitary_add.mul_dense_into(&self.unitary_state, &mut self.scratch);
+                mem::swap(&mut self.unitary_state, &mut self.scratch);
+            },
+        }
+    }
+
     fn add_unitary_single(&mut self, gate: &Gate<Complex>, qubit: usize){
-        let unitary_add = enlarge_single_opt(gate, qubit, self.number_of_qubits);
+        let unitary_add = enlarge_single_sparse(gate, qubit, self.number_of_qubits);
         debug!("add_unitary_single: unitary_add: {}", unitary_add);
-        // dot() from numpy does a matrix mult when the input is a 2D Matrix,
-        // We don't use 2D Arrays so we should probably be using a simple Matrix mult.
-        self.unitary_state = &unitary_add * &self.unitary_state; //dot product
-        debug!("add_unitary_single: unitary_state: {}", self.unitary_state);
+        self.add_unitary_sparse(&unitary_add);
     }
 
     fn add_unitary_two(&mut self, gate: &Gate<f64>, qubit0: usize , qubit1: usize){
-        let unitary_add = enlarge_two_opt(&gate, qubit0, qubit1, self.number_of_qubits);
+        let unitary_add = enlarge_two_sparse(gate, qubit0, qubit1, self.number_of_qubits);
         debug!("add_unitary_two: unitary_add: {}", unitary_add);
-        // dot() from numpy does a matrix mult when the input is a 2D Matrix,
-        // We don't use 2D Arrays so we should probably be using a simple Matrix mult.
-        self.unitary_state = &unitary_add * &self.unitary_state; //dot product
-        debug!("add_unitary_two: unitary_state: {}",  self.unitary_state);
-    }
-
-    pub fn run(&mut self) -> Result<HashMap<&'static str, serde_json::Value>, String> {
-        for j in 0..self.number_of_operations {
-            let c_qasm = self.circuit["operations"][j].clone();
-            debug!("Gate: {}", c_qasm["name"].to_string().as_str());
-            match c_qasm["name"].to_string().as_str() {
-                "\"U\"" => {
-                    let qubit = c_qasm["qubits"][0].as_i64().unwrap() as usize;
-                    let theta  = c_qasm["params"][0].as_f64().unwrap();
-                    let phi = c_qasm["params"][1].as_f64().unwrap();
-                    let lam = c_qasm["params"][2].as_f64().unwrap();
-
-                    let gate = Gate::<Complex>::from_slice(&[
-                        Complex::new(f64::cos(theta/2.0f64),0.0f64),
-                        -(Complex::i() * lam).exp() * f64::sin(theta / 2.0f64),
-                        (Complex::i() * phi).exp() * Complex::new(f64::sin(theta / 2.0f64),0.0f64),
-                        (Complex::i() * phi + Complex::i() * lam).exp() * Complex::new(f64::cos(theta / 2.0f64), 0.0f64)]);
-                    debug!("run: U match: qubqiskit_pythonit:'{}' theta:'{}' phi:'{}' lam:'{}' gate:'{}'", qubit, theta, phi, lam, gate);
-                    self.add_unitary_single(&gate, qubit);
-                },
-                "\"CX\"" => {
-                    let qubit0 = c_qasm["qubits"][0].as_i64().unwrap() as usize;
-                    let qubit1 = c_qasm["qubits"][1].as_i64().unwrap() as usize;
-                    let gate = Gate::<f64>::from_slice(&[1.0f64, 0.0f64, 0.0f64, 0.0f64, 0.0f64, 0.0f64,
-                                                         0.0f64, 1.0f64, 0.0f64, 0.0f64, 1.0f64, 0.0f64,
-                                                         0.0f64, 1.0f64, 0.0f64, 0.0f64]);
-                    debug!("run: CX match: qubit0:'{}' qubit1:'{}' gate:'{}'", qubit0, qubit1, gate);
-                    self.add_unitary_two(&gate, qubit0, qubit1);
-                },
-                "\"measure\"" => {
-                    warn!("Warning: Measure has been dropped from unitary simulator");
-                },
-                "\"reset\"" => {
-                    warn!("Warning: Reset has been dropped from unitary simulator");
+        self.add_unitary_sparse(&unitary_add);
+    }
+
+    /// Applies the single-qubit `gate` on the last of `c_qasm["qubits"]`, controlled
+    /// by all the others. A dense unitary is updated in place, only in the rows
+    /// whose control bits are all 1.
+    fn add_multi_controlled(&mut self, gate: &Gate<Complex>, c_qasm: &serde_json::Value) -> Result<(), String> {
+        let qubits : Vec<usize> = c_qasm["qubits"].as_array().map_or(vec![], |qubits| {
+            qubits.iter().filter_map(|qubit| qubit.as_u64()).map(|qubit| qubit as usize).collect()
+        });
+        let distinct = qubits.iter().enumerate().all(|(i, qubit)| !qubits[..i].contains(qubit));
+        if qubits.len() < 2 || !distinct || qubits.iter().any(|&qubit| qubit >= self.number_of_qubits) {
+            return Err(format!("Error: Wrong qubits in operation {}!!", c_qasm));
+        }
+        let (target, controls) = qubits.split_last().unwrap();
+        debug!("add_multi_controlled: controls:'{:?}' target:'{}' gate:'{}'", controls, target, gate);
+        if self.sparse_state.is_some() {
+            let unitary_add = enlarge_controlled_sparse(gate, controls, &[*target], self.number_of_qubits);
+            self.add_unitary_sparse(&unitary_add);
+        } else {
+            apply_controlled_single(&mut self.unitary_state, gate, controls, *target);
+        }
+        Ok(())
+    }
+
+    /// Applies an operation with "modifiers", like {"name": "U", "qubits": [0, 1],
+    /// "params": [...], "modifiers": [{"name": "ctrl"}, {"name": "pow", "exponent": 0.5}]}.
+    /// As in OpenQASM 3 they apply from the gate outwards: "inv", "pow" and "ctrl"
+    /// (with an optional "count"), whose controls come first in "qubits".
+    fn add_modified(&mut self, c_qasm: &serde_json::Value) -> Result<(), String> {
+        let mut gate = match c_qasm["name"].as_str() {
+            Some("U") => Gate::<Complex>::u(self.param(c_qasm, 0)?, self.param(c_qasm, 1)?, self.param(c_qasm, 2)?),
+            Some("CX") => Gate::<Complex>::u(PI, 0f64, PI).controlled(1),
+            Some("unitary") => Gate::from_json(&c_qasm["params"])?,
+            _ => return Err(format!("Error: Modifiers are not supported on {}!!", c_qasm["name"])),
+        };
+        let modifiers = match c_qasm["modifiers"].as_array() {
+            Some(modifiers) => modifiers,
+            None => return Err(format!("Error: Modifiers must be a list in operation {}!!", c_qasm)),
+        };
+        let mut controls = 0;
+        for modifier in modifiers.iter().rev() {
+            match modifier["name"].as_str() {
+                Some("ctrl") => controls += match modifier["count"] {
+                    serde_json::Value::Null => 1,
+                    ref count => match count.as_u64() {
+                        Some(count) if count > 0 => count as usize,
+                        _ => return Err(format!("Error: Wrong number of controls in modifier {}!!", modifier)),
+                    },
                 },
-                "\"barrier\"" => {
-                    () // Pass
+                Some("inv") => gate = gate.inverse(),
+                Some("pow") => gate = gate.pow(Expression::from_json(&modifier["exponent"])?.eval(&self.parameters)?)?,
+                _ => return Err(format!("Error: Unknown gate modifier {}!!", modifier)),
+            }
+        }
+
+        let qubits : Vec<usize> = c_qasm["qubits"].as_array().map_or(vec![], |qubits| {
+            qubits.iter().filter_map(|qubit| qubit.as_u64()).map(|qubit| qubit as usize).collect()
+        });
+        let distinct = qubits.iter().enumerate().all(|(i, qubit)| !qubits[..i].contains(qubit));
+        if qubits.len() != controls + gate.number_of_qubits() || !distinct
+            || qubits.iter().any(|&qubit| qubit >= self.number_of_qubits) {
+            return Err(format!("Error: Wrong qubits in operation {}!!", c_qasm));
+        }
+        debug!("add_modified: controls:'{:?}' targets:'{:?}' gate:'{}'", &qubits[..controls], &qubits[controls..], gate);
+        let unitary_add = enlarge_controlled_sparse(&gate, &qubits[..controls], &qubits[controls..], self.number_of_qubits);
+        self.add_unitary_sparse(&unitary_add);
+        Ok(())
+    }
+
+    /// Records the state under the label of the snapshot operation, in
+    /// data.snapshots.<snapshot_type>.<label>. The types are "unitary",
+    /// "statevector" (the default), "probabilities" and "expectation_value_pauli",
+    /// which takes the Pauli sum in its params, like "0.5 * X0 Z1 - Y2".
+    fn snapshot(&mut self, c_qasm: &serde_json::Value) -> Result<(), String> {
+        let label = match c_qasm["label"].as_str() {
+            Some(label) => label,
+            None => return Err("Error: Snapshot without a label!!".to_string()),
+        };
+        let snapshot_type = c_qasm["snapshot_type"].as_str().unwrap_or("statevector");
+        let value = match snapshot_type {
+            "unitary" => json!(self.endianness.reorder_unitary(&self.current_unitary(), self.number_of_qubits).as_slice()),
+            "statevector" => json!(self.statevector()),
+            "probabilities" => json!(self.statevector().iter().map(|amplitude| amplitude.norm_sqr()).collect::<Vec<f64>>()),
+            "expectation_value_pauli" => {
+                let observable = match c_qasm["params"][0].as_str() {
+                    Some(observable) => PauliSum::parse(observable)?,
+                    None => return Err(format!("Error: Snapshot '{}' needs a Pauli sum!!", label)),
+                };
+                // Pauli qubits are the simulator ones, whatever the output endianness
+                json!(observable.expectation(&self.first_column())?)
+            },
+            _ => return Err(format!("Error: Unknown snapshot type '{}'!!", snapshot_type)),
+        };
+        debug!("snapshot: label:'{}' snapshot_type:'{}'", label, snapshot_type);
+        self.result.data.snapshots.get_or_insert_with(|| json!({}))[snapshot_type][label] = value;
+        Ok(())
+    }
+
+    /// Applies the next operation and calls the observer, returns false for unknown
+    /// gates.
+    fn apply_next<O: StepObserver>(&mut self, observer: &mut O) -> Result<bool, String> {
+        let index = self.next_operation;
+        let c_qasm = self.circuit["operations"][index].clone();
+        debug!("Gate: {}", c_qasm["name"].to_string().as_str());
+        match c_qasm["name"].to_string().as_str() {
+            _ if !c_qasm["modifiers"].is_null() => self.add_modified(&c_qasm)?,
+            "\"U\"" => {
+                let qubit = c_qasm["qubits"][0].as_i64().unwrap() as usize;
+                let theta  = self.param(&c_qasm, 0)?;
+                let phi = self.param(&c_qasm, 1)?;
+                let lam = self.param(&c_qasm, 2)?;
+
+                let gate = Gate::<Complex>::u(theta, phi, lam);
+                debug!("run: U match: qubqiskit_pythonit:'{}' theta:'{}' phi:'{}' lam:'{}' gate:'{}'", qubit, theta, phi, lam, gate);
+                self.add_unitary_single(&gate, qubit);
+            },
+            "\"CX\"" => {
+                let qubit0 = c_qasm["qubits"][0].as_i64().unwrap() as usize;
+                let qubit1 = c_qasm["qubits"][1].as_i64().unwrap() as usize;
+                let gate = Gate::<f64>::cx();
+                debug!("run: CX match: qubit0:'{}' qubit1:'{}' gate:'{}'", qubit0, qubit1, gate);
+                self.add_unitary_two(&gate, qubit0, qubit1);
+            },
+            "\"ccx\"" | "\"mcx\"" => {
+                if c_qasm["name"] == "ccx" && c_qasm["qubits"].as_array().map_or(0, |qubits| qubits.len()) != 3 {
+                    return Err(format!("Error: ccx needs two controls and a target: {}!!", c_qasm));
                 }
-                _ => {
-                    error!("Error: Unknown gate type!!");
-                    *self.result.get_mut("status").unwrap() = json!("ERROR");
-                    return Ok(self.result.clone());
+                self.add_multi_controlled(&Gate::<Complex>::u(PI, 0f64, PI), &c_qasm)?;
+            },
+            "\"mcz\"" => {
+                self.add_multi_controlled(&Gate::<Complex>::u(0f64, 0f64, PI), &c_qasm)?;
+            },
+            "\"mcu\"" => {
+                let gate = Gate::<Complex>::u(self.param(&c_qasm, 0)?, self.param(&c_qasm, 1)?, self.param(&c_qasm, 2)?);
+                self.add_multi_controlled(&gate, &c_qasm)?;
+            },
+            "\"unitary\"" => {
+                let gate = Gate::from_json(&c_qasm["params"])?;
+                let qubits : Vec<usize> = c_qasm["qubits"].as_array().map_or(vec![], |qubits| {
+                    qubits.iter().filter_map(|qubit| qubit.as_u64()).map(|qubit| qubit as usize).collect()
+                });
+                let distinct = qubits.iter().enumerate().all(|(i, qubit)| !qubits[..i].contains(qubit));
+                if qubits.len() != gate.number_of_qubits() || !distinct || qubits.iter().any(|&qubit| qubit >= self.number_of_qubits) {
+                    return Err(format!("Error: A {}-qubit unitary cannot act on qubits {}!!", gate.number_of_qubits(), c_qasm["qubits"]));
                 }
+                debug!("run: unitary match: qubits:'{:?}' gate:'{}'", qubits, gate);
+                let unitary_add = enlarge_controlled_sparse(&gate, &[], &qubits, self.number_of_qubits);
+                self.add_unitary_sparse(&unitary_add);
+            },
+            "\"measure\"" => {
+                warn!("Warning: Measure has been dropped from unitary simulator");
+            },
+            "\"reset\"" => {
+                warn!("Warning: Reset has been dropped from unitary simulator");
+            },
+            "\"barrier\"" => {
+                () // Pass
+            },
+            "\"snapshot\"" => {
+                self.snapshot(&c_qasm)?;
+            }
+            _ => {
+                error!("Error: Unknown gate type!!");
+                return Ok(false);
+            }
+        }
+        self.next_operation += 1;
+        observer.after_operation(index, &c_qasm, self);
+        Ok(true)
+    }
+
+    /// Applies the next operation, returns false if there are none left.
+    pub fn step(&mut self) -> Result<bool, String> {
+        self.step_with(&mut ())
+    }
+
+    /// Same as `step()`, calling `observer` after the operation.
+    pub fn step_with<O: StepObserver>(&mut self, observer: &mut O) -> Result<bool, String> {
+        if self.next_operation == self.number_of_operations {
+            return Ok(false);
+        }
+        if !self.apply_next(observer)? {
+            return Err(format!("Error: Unknown gate type {}!!", self.circuit["operations"][self.next_operation]["name"]));
+        }
+        Ok(true)
+    }
+
+    /// Applies the operations before `index`, starting over if it is behind
+    /// `next_operation()`.
+    pub fn run_until(&mut self, index: usize) -> Result<(), String> {
+        if index > self.number_of_operations {
+            return Err(format!("Error: There are only {} operations!!", self.number_of_operations));
+        }
+        if index < self.next_operation {
+            self.reset();
+        }
+        while self.next_operation < index {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Applies the remaining operations and returns the unitary in the results.
+    pub fn run(&mut self) -> Result<SimulationResult, SimulatorError> {
+        self.run_with(&mut ())
+    }
+
+    /// Same as `run()`, calling `observer` after every operation.
+    pub fn run_with<O: StepObserver>(&mut self, observer: &mut O) -> Result<SimulationResult, SimulatorError> {
+        let start = Instant::now();
+        while self.next_operation < self.number_of_operations {
+            if !self.apply_next(observer)? {
+                self.result.status = Status::Error;
+                return Ok(self.take_result());
             }
         }
 
-        *self.result.get_mut("data").unwrap().get_mut("unitary").unwrap() = json!(self.unitary_state.as_slice());
-        *self.result.get_mut("status").unwrap() = json!("DONE");
-        Ok(self.result.clone())
+        self.sync_dense();
+        let unitary = match self.endianness {
+            Endianness::Little => self.unitary_state.as_slice().to_vec(),
+            Endianness::Big => self.endianness.reorder_unitary(&self.unitary_state, self.number_of_qubits).into_vec(),
+        };
+        self.result.data.unitary = Some(unitary);
+        self.result.done(start);
+        Ok(self.take_result())
+    }
+
+    /// Moves the results out, instead of keeping a copy of the unitary around.
+    fn take_result(&mut self) -> SimulationResult {
+        let empty = SimulationResult::new(self.circuit["header"].clone());
+        mem::replace(&mut self.result, empty)
     }
 }
 
@@ -183,6 +563,9 @@ use super::env_logger;
 use super::UnitarySimulator;
 use super::Matrix;
 use super::Complex;
+use super::Gate;
+use super::memory::{MemoryError, SimulatorError};
+use std::collections::HashMap;
 
     #[test]
     fn circuit1() {
@@ -191,7 +574,7 @@ use super::Complex;
         let circuit = qiskit.get_qasm_circuit("example", "example/example.qasm").unwrap();
         let backend_circuit = qiskit.get_backend_circuit(circuit).unwrap();
         let mut us = UnitarySimulator::new(backend_circuit.to_string()).unwrap();
-        let result = us.run().unwrap();
+        let result = us.run().unwrap().to_json();
 
         let dim = ((result["data"]["unitary"].as_array().unwrap().len()) as f64).sqrt() as usize;
         info!("dim = {}", dim);
@@ -226,6 +609,330 @@ use super::Complex;
             Complex::new(0f64,0f64), Complex::new(0f64,0f64), Complex::new(0f64,0f64), Complex::new(0.3535533905932737f64,0f64),
         ]);
 
-        assert_eq!(expected, result);
+        assert!(expected.equiv_up_to_global_phase(&result, 1e-12));
+        assert!(expected.max_deviation(&result) < 1e-12);
+    }
+
+    #[test]
+    fn symbolic_parameters() {
+        let circuit = r#"{
+            "header": {"number_of_qubits": 1},
+            "operations": [{"name": "U", "qubits": [0], "params": ["2*theta", "-pi/2", "pi/2"]}]
+        }"#;
+        let mut us = UnitarySimulator::new(circuit.to_string()).unwrap();
+        assert!(us.run().is_err());
+
+        let mut parameters = HashMap::new();
+        parameters.insert("theta".to_string(), ::std::f64::consts::PI / 4f64);
+        us.set_parameters(parameters);
+        let result = us.run().unwrap().to_json();
+        assert_eq!(result["status"], json!("DONE"));
+
+        // U(pi/2, -pi/2, pi/2) == Rx(pi/2)
+        let s = 0.5f64.sqrt();
+        let unitary = result["data"]["unitary"].as_array().unwrap();
+        let expected = [(s, 0f64), (0f64, -s), (0f64, -s), (s, 0f64)];
+        for (value, &(re, im)) in unitary.iter().zip(expected.iter()) {
+            assert!((value["re"].as_f64().unwrap() - re).abs() < 1e-12);
+            assert!((value["im"].as_f64().unwrap() - im).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn sparse_unitary() {
+        // X and CX gates keep the unitary a permutation
+        let circuit = r#"{
+            "header": {"number_of_qubits": 6},
+            "operations": [
+                {"name": "U", "qubits": [0], "params": ["pi", 0, "pi"]},
+                {"name": "CX", "qubits": [0, 5]},
+                {"name": "CX", "qubits": [5, 2]},
+                {"name": "U", "qubits": [3], "params": ["pi", "pi/2", "pi/2"]}
+            ]
+        }"#;
+        let mut us = UnitarySimulator::new(circuit.to_string()).unwrap();
+        // The dense unitary is only allocated when needed, and is kept up to date
+        assert_eq!(0, us.unitary_state.size());
+        us.step().unwrap();
+        assert_eq!(us.current_unitary(), *us.unitary());
+        us.step().unwrap();
+        assert_eq!(us.current_unitary(), *us.unitary());
+        us.run().unwrap();
+        let sparse = us.sparse_unitary().unwrap().clone();
+        assert!(sparse.is_monomial());
+        assert!(sparse.to_dense().approx_eq(us.unitary()));
+        // |000000> -> |100101>, with a phase from U(pi, pi/2, pi/2) = i Y
+        assert_eq!(Complex::new(0f64, 1f64), sparse.get(0b101101, 0));
+
+        // A layer of H on every qubit fills the whole unitary
+        let circuit = json!({
+            "header": {"number_of_qubits": 4},
+            "operations": (0..4).map(|qubit| json!({"name": "U", "qubits": [qubit], "params": ["pi/2", 0, "pi"]}))
+                .collect::<Vec<_>>()
+        });
+        let mut us = UnitarySimulator::from_json(circuit).unwrap();
+        us.run().unwrap();
+        assert!(us.sparse_unitary().is_none());
+        assert!((us.unitary().get(15, 15).re() - 0.25f64).abs() < 1e-12);
+    }
+
+    #[test]
+    fn endianness() {
+        // X on qubit 0 of 2 qubits: |01> little-endian, |10> big-endian
+        let circuit = json!({
+            "header": {"number_of_qubits": 2},
+            "config": {"endianness": "big"},
+            "operations": [{"name": "U", "qubits": [0], "params": ["pi", 0, "pi"]}]
+        });
+        let mut us = UnitarySimulator::from_json(circuit).unwrap();
+        let result = us.run().unwrap().to_json();
+        assert_eq!(Complex::new(1f64, 0f64), us.statevector()[2]);
+        assert_eq!(Complex::new(1f64, 0f64), *us.unitary().get(1, 0));
+        // Row 2, column 0 of the row-major big-endian unitary
+        assert_eq!(1f64, result["data"]["unitary"][8]["re"].as_f64().unwrap());
+
+        us.set_endianness(super::Endianness::Little);
+        assert_eq!(Complex::new(1f64, 0f64), us.statevector()[1]);
+    }
+
+    #[test]
+    fn memory_limits() {
+        // 16 qubits would be a 64 GiB unitary
+        let circuit = json!({"header": {"number_of_qubits": 16}, "operations": []});
+        match UnitarySimulator::from_json(circuit).err().unwrap() {
+            SimulatorError::Memory(MemoryError::OverBudget { number_of_qubits: 16, .. }) => (),
+            err => panic!("Unexpected error {}", err),
+        }
+
+        let circuit = json!({"header": {"number_of_qubits": 3}, "operations": [], "config": {"max_qubits": 2}});
+        assert_eq!(SimulatorError::Memory(MemoryError::TooManyQubits { number_of_qubits: 3, max_qubits: 2 }),
+                   UnitarySimulator::from_json(circuit.clone()).err().unwrap());
+        let circuit_error = UnitarySimulator::from_json(json!({"operations": []})).err().unwrap();
+        assert_eq!(SimulatorError::Circuit("No number_of_qubits field in the circuit!!".to_string()), circuit_error);
+        assert!(UnitarySimulator::from_json_with_limits(circuit, &super::MemoryLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn baseline_result_format() {
+        // The HashMap the simulator used to return, every key of it must still be
+        // there with the same value
+        let old_result = |status: &str, unitary: serde_json::Value| {
+            let mut result : HashMap<&'static str, serde_json::Value> = HashMap::new();
+            result.insert("data", json!({"unitary": unitary}));
+            result.insert("result", json!({}));
+            result.insert("status", json!(status));
+            serde_json::to_value(&result).unwrap()
+        };
+        let same_keys = |old: &serde_json::Value, new: &serde_json::Value| {
+            for (key, value) in old.as_object().unwrap() {
+                assert_eq!(*value, new[key], "{}", key);
+            }
+        };
+
+        let circuit = json!({
+            "header": {"number_of_qubits": 1},
+            "operations": [{"name": "U", "qubits": [0], "params": ["pi", 0, "pi"]}]
+        });
+        let mut us = UnitarySimulator::from_json(circuit).unwrap();
+        let result = us.run().unwrap().to_json();
+        same_keys(&old_result("DONE", json!(us.unitary().as_slice())), &result);
+
+        let circuit = json!({"header": {"number_of_qubits": 1}, "operations": [{"name": "foo", "qubits": [0]}]});
+        let result = UnitarySimulator::from_json(circuit).unwrap().run().unwrap().to_json();
+        same_keys(&old_result("ERROR", json!({})), &result);
+    }
+
+    #[test]
+    fn stepping() {
+        let circuit = json!({
+            "header": {"number_of_qubits": 2},
+            "operations": [
+                {"name": "U", "qubits": [0], "params": ["pi/2", 0, "pi"]},
+                {"name": "barrier", "qubits": [0, 1]},
+                {"name": "CX", "qubits": [0, 1]},
+                {"name": "toffoli", "qubits": [0, 1]}
+            ]
+        });
+        let mut us = UnitarySimulator::from_json(circuit).unwrap();
+        let s = 0.5f64.sqrt();
+
+        // H|00> is (|00> + |01>)/sqrt(2), the CX makes it a Bell pair
+        assert!(us.step().unwrap());
+        assert_eq!(1, us.next_operation());
+        assert!((us.statevector()[1].re() - s).abs() < 1e-12);
+        us.run_until(3).unwrap();
+        assert!((us.statevector()[3].re() - s).abs() < 1e-12);
+        assert!(us.step().is_err());
+
+        // Going back starts over
+        us.run_until(1).unwrap();
+        assert_eq!(Complex::new(0f64, 0f64), us.statevector()[3]);
+        assert!(us.run_until(5).is_err());
+
+        let mut visited = Vec::new();
+        let result = {
+            let mut observer = |index: usize, operation: &serde_json::Value, simulator: &UnitarySimulator| {
+                visited.push((index, operation["name"].clone(), simulator.current_unitary().get(3, 0).re()));
+            };
+            us.run_with(&mut observer).unwrap().to_json()
+        };
+        assert_eq!(json!("ERROR"), result["status"]);
+        assert_eq!(2, visited.len());
+        assert_eq!((1, json!("barrier")), (visited[0].0, visited[0].1.clone()));
+        assert!((visited[1].2 - s).abs() < 1e-12);
+    }
+
+    #[test]
+    fn gate_modifiers() {
+        let unitary = |operations: serde_json::Value| {
+            let circuit = json!({"header": {"number_of_qubits": 3}, "operations": operations});
+            UnitarySimulator::from_json(circuit).unwrap().run().unwrap().unitary().unwrap()
+        };
+        // Controlled CX is the Toffoli gate
+        let toffoli = unitary(json!([{"name": "CX", "qubits": [0, 1, 2], "modifiers": [{"name": "ctrl"}]}]));
+        for column in 0..8 {
+            let row = if column & 3 == 3 { column ^ 4 } else { column };
+            assert_eq!(Complex::new(1f64, 0f64), *toffoli.get(row, column));
+        }
+
+        let root = json!({"name": "U", "qubits": [2, 0], "params": ["pi", 0, "pi"],
+                          "modifiers": [{"name": "ctrl"}, {"name": "pow", "exponent": 0.5}]});
+        let cx = unitary(json!([{"name": "CX", "qubits": [2, 0]}]));
+        assert!(unitary(json!([root, root])).max_deviation(&cx) < 1e-10);
+
+        let u = json!({"name": "U", "qubits": [1], "params": [0.3, -0.2, 1.4]});
+        let undo = json!({"name": "U", "qubits": [1], "params": [0.3, -0.2, 1.4], "modifiers": [{"name": "inv"}]});
+        assert!(unitary(json!([u, undo])).max_deviation(&Matrix::identity(8)) < 1e-12);
+        let controls = json!({"name": "U", "qubits": [0, 2, 1], "params": [0.3, -0.2, 1.4],
+                              "modifiers": [{"name": "ctrl", "count": 2}, {"name": "pow", "exponent": -1}]});
+        let result = unitary(json!([controls]));
+        assert_eq!(Complex::new(1f64, 0f64), *result.get(1, 1));
+        assert!((*result.get(5, 5) - Gate::<Complex>::u(0.3, -0.2, 1.4).inverse()[(0, 0)]).abs() < 1e-12);
+
+        for operation in [json!({"name": "CX", "qubits": [0, 1], "modifiers": [{"name": "ctrl"}]}),
+                          json!({"name": "CX", "qubits": [0, 1, 0], "modifiers": [{"name": "ctrl"}]}),
+                          json!({"name": "CX", "qubits": [0, 1], "modifiers": [{"name": "sqrt"}]}),
+                          json!({"name": "measure", "qubits": [0], "modifiers": []})].iter() {
+            let circuit = json!({"header": {"number_of_qubits": 3}, "operations": [operation]});
+            assert!(UnitarySimulator::from_json(circuit).unwrap().run().is_err());
+        }
+    }
+
+    #[test]
+    fn multi_controlled_gates() {
+        let hadamards : Vec<_> = (0..4).map(|qubit| json!({"name": "U", "qubits": [qubit], "params": ["pi/2", 0, "pi"]})).collect();
+        let gates = [
+            (json!({"name": "ccx", "qubits": [3, 0, 1]}),
+             json!({"name": "CX", "qubits": [3, 0, 1], "modifiers": [{"name": "ctrl"}]})),
+            (json!({"name": "mcx", "qubits": [0, 1, 2, 3]}),
+             json!({"name": "U", "qubits": [0, 1, 2, 3], "params": ["pi", 0, "pi"], "modifiers": [{"name": "ctrl", "count": 3}]})),
+            (json!({"name": "mcz", "qubits": [2, 1, 0]}),
+             json!({"name": "U", "qubits": [2, 1, 0], "params": [0, 0, "pi"], "modifiers": [{"name": "ctrl", "count": 2}]})),
+            (json!({"name": "mcu", "qubits": [1, 3], "params": [0.4, "theta", -1.3]}),
+             json!({"name": "U", "qubits": [1, 3], "params": [0.4, "theta", -1.3], "modifiers": [{"name": "ctrl"}]})),
+        ];
+        let unitary = |operations: Vec<serde_json::Value>| {
+            let circuit = json!({"header": {"number_of_qubits": 4}, "operations": operations});
+            let mut us = UnitarySimulator::from_json(circuit).unwrap();
+            let mut parameters = HashMap::new();
+            parameters.insert("theta".to_string(), 0.7f64);
+            us.set_parameters(parameters);
+            us.run().unwrap().unitary().unwrap()
+        };
+        // Both on the sparse unitary and, after the Hadamards, on the dense one
+        for prefix in [vec![], hadamards].iter() {
+            for (gate, modified) in gates.iter() {
+                let mut operations = prefix.clone();
+                operations.push(gate.clone());
+                let mut expected = prefix.clone();
+                expected.push(modified.clone());
+                assert!(unitary(operations).max_deviation(&unitary(expected)) < 1e-12);
+            }
+        }
+
+        for operation in [json!({"name": "mcx", "qubits": [0]}),
+                          json!({"name": "mcx", "qubits": [0, 1, 0]}),
+                          json!({"name": "ccx", "qubits": [0, 1]}),
+                          json!({"name": "mcz", "qubits": [0, 4]}),
+                          json!({"name": "mcu", "qubits": [0, 1], "params": [0.4]})].iter() {
+            let circuit = json!({"header": {"number_of_qubits": 4}, "operations": [operation]});
+            assert!(UnitarySimulator::from_json(circuit).unwrap().run().is_err());
+        }
+    }
+
+    #[test]
+    fn custom_unitary() {
+        let s = ::std::f64::consts::FRAC_1_SQRT_2;
+        // CX with qubit 0 as target and qubit 1 as control, in the basis index order
+        // where the first of "qubits" is the lowest bit
+        let cx = json!([[1, 0], [0, 0], [0, 0], [0, 0],
+                        [0, 0], [1, 0], [0, 0], [0, 0],
+                        [0, 0], [0, 0], [0, 0], [1, 0],
+                        [0, 0], [0, 0], [1, 0], [0, 0]]);
+        let circuit = json!({
+            "header": {"number_of_qubits": 3},
+            "operations": [
+                {"name": "unitary", "qubits": [2], "params": [[s, 0], [s, 0], [s, 0], [-s, 0]]},
+                {"name": "unitary", "qubits": [0, 2], "params": cx}
+            ]
+        });
+        let expected = json!({
+            "header": {"number_of_qubits": 3},
+            "operations": [
+                {"name": "U", "qubits": [2], "params": ["pi/2", 0, "pi"]},
+                {"name": "CX", "qubits": [2, 0]}
+            ]
+        });
+        let unitary = |circuit: serde_json::Value| UnitarySimulator::from_json(circuit).unwrap().run().unwrap().unitary().unwrap();
+        assert!(unitary(circuit).max_deviation(&unitary(expected)) < 1e-12);
+
+        let controlled = json!({
+            "header": {"number_of_qubits": 3},
+            "operations": [{"name": "unitary", "qubits": [1, 0, 2], "params": cx, "modifiers": [{"name": "ctrl"}]}]
+        });
+        let toffoli = json!({"header": {"number_of_qubits": 3}, "operations": [{"name": "ccx", "qubits": [1, 2, 0]}]});
+        assert!(unitary(controlled).max_deviation(&unitary(toffoli)) < 1e-12);
+
+        let not_unitary = json!([[1, 0], [1, 0], [0, 0], [1, 0]]);
+        for operation in [json!({"name": "unitary", "qubits": [0], "params": not_unitary}),
+                          json!({"name": "unitary", "qubits": [0], "params": cx}),
+                          json!({"name": "unitary", "qubits": [1, 1], "params": cx}),
+                          json!({"name": "unitary", "qubits": [0, 3], "params": cx}),
+                          json!({"name": "unitary", "qubits": [0], "params": [[1, 0], [0, 0], [0, 0]]})].iter() {
+            let circuit = json!({"header": {"number_of_qubits": 3}, "operations": [operation]});
+            assert!(UnitarySimulator::from_json(circuit).unwrap().run().is_err());
+        }
+    }
+
+    #[test]
+    fn snapshots() {
+        let circuit = json!({
+            "header": {"number_of_qubits": 2},
+            "operations": [
+                {"name": "U", "qubits": [0], "params": ["pi/2", 0, "pi"]},
+                {"name": "snapshot", "label": "plus", "snapshot_type": "probabilities"},
+                {"name": "snapshot", "label": "plus", "snapshot_type": "expectation_value_pauli", "params": ["X0 + 0.5 * Z1"]},
+                {"name": "CX", "qubits": [0, 1]},
+                {"name": "snapshot", "label": "bell"},
+                {"name": "snapshot", "label": "bell", "snapshot_type": "unitary"}
+            ]
+        });
+        let mut us = UnitarySimulator::from_json(circuit).unwrap();
+        let result = us.run().unwrap().to_json();
+        assert_eq!(json!("DONE"), result["status"]);
+        let snapshots = &result["data"]["snapshots"];
+        let probabilities : Vec<f64> = snapshots["probabilities"]["plus"].as_array().unwrap().iter()
+            .map(|p| p.as_f64().unwrap()).collect();
+        assert!((probabilities[0] - 0.5).abs() < 1e-12 && (probabilities[1] - 0.5).abs() < 1e-12);
+        assert!((snapshots["expectation_value_pauli"]["plus"].as_f64().unwrap() - 1.5).abs() < 1e-12);
+        let bell = &snapshots["statevector"]["bell"];
+        assert!((bell[3]["re"].as_f64().unwrap() - 0.5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(result["data"]["unitary"], snapshots["unitary"]["bell"]);
+
+        let circuit = json!({
+            "header": {"number_of_qubits": 1},
+            "operations": [{"name": "snapshot", "label": "state", "snapshot_type": "density_matrix"}]
+        });
+        assert!(UnitarySimulator::from_json(circuit).unwrap().run().is_err());
     }
 }
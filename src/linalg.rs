@@ -0,0 +1,333 @@
+//! Spectral decompositions and matrix functions for `Matrix<Complex>`.
+//!
+//! As nalgebra can't do complex matrices (see matrix.rs), we implement the few
+//! algorithms we need ourselves:
+//!  * Hermitian eigendecomposition with the cyclic Jacobi method.
+//!  * Common eigenvectors of commuting Hermitian matrices, and with them the
+//!    eigendecomposition of unitaries from their Hermitian and anti-Hermitian parts.
+//!  * Matrix exponential with Padé approximants and scaling and squaring
+//!    (N. J. Higham, SIAM J. Matrix Anal. Appl. 26(4), 2005).
+
+use std::cmp::Ordering;
+
+use complex::Complex;
+use matrix::Matrix;
+use num::Zero;
+
+/// Padé(13) coefficients.
+const PADE_13: [f64; 14] = [64764752532480000f64, 32382376266240000f64, 7771770303897600f64,
+                            1187353796428800f64, 129060195264000f64, 10559470521600f64,
+                            670442572800f64, 33522128640f64, 1323241920f64, 40840800f64,
+                            960960f64, 16380f64, 182f64, 1f64];
+
+/// Largest 1-norm for which Padé(13) is accurate to double precision.
+const THETA_13: f64 = 5.371920351148152f64;
+
+impl Matrix<Complex> {
+    /// Eigendecomposition of a Hermitian matrix, `A = V diag(eigenvalues) V^\dagger`.
+    ///
+    /// Eigenvalues are returned in ascending order, and the columns of `V` are the
+    /// corresponding orthonormal eigenvectors. Returns an error if the matrix is
+    /// not Hermitian.
+    pub fn eigh(&self) -> Result<(Vec<f64>, Matrix<Complex>), String> {
+        let n = self.size();
+        let norm = self.frobenius_norm();
+        if !norm.is_finite() {
+            return Err("Error: eigh() needs a finite matrix!!".to_string());
+        }
+        if (self - &self.adjoint()).frobenius_norm() > 1e-10 * norm.max(1f64) {
+            return Err("Error: eigh() needs a Hermitian matrix!!".to_string());
+        }
+
+        let mut a = self.clone();
+        let mut v = Matrix::<Complex>::identity(n);
+        for sweep in 0..100 {
+            let off_diagonal = (0..n).fold(0f64, |acc, i| {
+                (0..n).filter(|&j| j != i).fold(acc, |acc, j| acc + a.get(i, j).norm_sqr())
+            }).sqrt();
+            if off_diagonal <= 1e-15 * norm {
+                debug!("eigh: converged after {} sweeps", sweep);
+                break;
+            }
+
+            for p in 0..n {
+                for q in p + 1..n {
+                    let apq = *a.get(p, q);
+                    let b = apq.abs();
+                    if b <= 1e-300 {
+                        continue;
+                    }
+                    // Remove the phase of a_pq, then apply the real Jacobi rotation:
+                    // V = D R D^\dagger with D = diag(1, e^{-i phi})
+                    let phase = apq / b;
+                    let zeta = (a.get(q, q).re() - a.get(p, p).re()) / (2f64 * b);
+                    let t = zeta.signum() / (zeta.abs() + (1f64 + zeta * zeta).sqrt());
+                    let c = 1f64 / (1f64 + t * t).sqrt();
+                    let s = t * c;
+                    let (vpp, vpq, vqp, vqq) = (Complex::new(c, 0f64), phase * s, -phase.conj() * s, Complex::new(c, 0f64));
+
+                    // A <- A V
+                    for k in 0..n {
+                        let (akp, akq) = (*a.get(k, p), *a.get(k, q));
+                        a.set(k, p, &(akp * vpp + akq * vqp));
+                        a.set(k, q, &(akp * vpq + akq * vqq));
+                    }
+                    // A <- V^\dagger A
+                    for k in 0..n {
+                        let (apk, aqk) = (*a.get(p, k), *a.get(q, k));
+                        a.set(p, k, &(vpp.conj() * apk + vqp.conj() * aqk));
+                        a.set(q, k, &(vpq.conj() * apk + vqq.conj() * aqk));
+                    }
+                    a.set(p, q, &Complex::zero());
+                    a.set(q, p, &Complex::zero());
+                    // Eigenvectors: W <- W V
+                    for k in 0..n {
+                        let (vkp, vkq) = (*v.get(k, p), *v.get(k, q));
+                        v.set(k, p, &(vkp * vpp + vkq * vqp));
+                        v.set(k, q, &(vkp * vpq + vkq * vqq));
+                    }
+                }
+            }
+        }
+
+        let mut order : Vec<usize> = (0..n).collect();
+        order.sort_by(|&i, &j| a.get(i, i).re().partial_cmp(&a.get(j, j).re()).unwrap_or(Ordering::Equal));
+        let eigenvalues = order.iter().map(|&i| a.get(i, i).re()).collect();
+        let mut eigenvectors = Matrix::<Complex>::new(n);
+        for (target, &source) in order.iter().enumerate() {
+            for k in 0..n {
+                eigenvectors.set(k, target, v.get(k, source));
+            }
+        }
+        Ok((eigenvalues, eigenvectors))
+    }
+
+    /// Common eigenvectors of two commuting Hermitian matrices, as the columns of
+    /// a unitary `V`: `self` is diagonalized first, then `other` within the
+    /// degenerate eigenspaces of `self`.
+    ///
+    /// Returns an error if either matrix is not Hermitian. Whether they commute is
+    /// up to the caller.
+    pub fn eigh_commuting(&self, other: &Matrix<Complex>) -> Result<Matrix<Complex>, String> {
+        let n = self.size();
+        if other.size() != n {
+            return Err("Error: eigh_commuting() needs matrices of the same size!!".to_string());
+        }
+        let (values, mut vectors) = self.eigh()?;
+        if (other - &other.adjoint()).frobenius_norm() > 1e-10 * other.frobenius_norm().max(1f64) {
+            return Err("Error: eigh_commuting() needs a Hermitian matrix!!".to_string());
+        }
+
+        let tolerance = 1e-8 * values.iter().fold(1f64, |acc, value| acc.max(value.abs()));
+        let ov = other * &vectors;
+        let mut start = 0;
+        while start < n {
+            let mut end = start + 1;
+            while end < n && values[end] - values[end - 1] <= tolerance {
+                end += 1;
+            }
+            let m = end - start;
+            if m > 1 {
+                // other restricted to the eigenspace, made exactly Hermitian
+                let mut block = Matrix::<Complex>::new(m);
+                for a in 0..m {
+                    for b in 0..m {
+                        let value = (0..n).fold(Complex::zero(), |acc, i| acc + vectors.get(i, start + a).conj() * *ov.get(i, start + b));
+                        block.set(a, b, &value);
+                    }
+                }
+                let block = (&block + &block.adjoint()).scale(Complex::new(0.5f64, 0f64));
+                let (_, w) = block.eigh()?;
+                for i in 0..n {
+                    let row : Vec<Complex> = (0..m).map(|b| {
+                        (0..m).fold(Complex::zero(), |acc, a| acc + *vectors.get(i, start + a) * *w.get(a, b))
+                    }).collect();
+                    for (b, value) in row.iter().enumerate() {
+                        vectors.set(i, start + b, value);
+                    }
+                }
+            }
+            start = end;
+        }
+        Ok(vectors)
+    }
+
+    /// Eigendecomposition of a unitary (or any normal) matrix,
+    /// `U = V diag(eigenvalues) V^\dagger`, with unitary `V`.
+    ///
+    /// The Hermitian and anti-Hermitian parts of a normal matrix commute, so both
+    /// are diagonalized with `eigh_commuting()`.
+    pub fn eig_unitary(&self) -> Result<(Vec<Complex>, Matrix<Complex>), String> {
+        let n = self.size();
+        let norm = self.frobenius_norm().max(1f64);
+        if (&(self * &self.adjoint()) - &(&self.adjoint() * self)).frobenius_norm() > 1e-10 * norm {
+            return Err("Error: eig_unitary() needs a normal matrix!!".to_string());
+        }
+        let adjoint = self.adjoint();
+        let hermitian = (self + &adjoint).scale(Complex::new(0.5f64, 0f64));
+        let anti_hermitian = (self - &adjoint).scale(Complex::new(0f64, -0.5f64));
+        let vectors = hermitian.eigh_commuting(&anti_hermitian)?;
+
+        let uv = self * &vectors;
+        let eigenvalues : Vec<Complex> = (0..n)
+            .map(|k| (0..n).fold(Complex::zero(), |acc, i| acc + vectors.get(i, k).conj() * *uv.get(i, k)))
+            .collect();
+        if Matrix::from_eigendecomposition(&eigenvalues, &vectors).max_deviation(self) > 1e-8 * norm {
+            return Err("Error: eig_unitary() did not converge!!".to_string());
+        }
+        Ok((eigenvalues, vectors))
+    }
+
+    /// 1-norm, i.e. the largest absolute column sum.
+    pub fn norm1(&self) -> f64 {
+        (0..self.size())
+            .map(|j| (0..self.size()).fold(0f64, |acc, i| acc + self.get(i, j).abs()))
+            .fold(0f64, f64::max)
+    }
+
+    /// Matrix exponential e^A, with a Padé(13) approximant and scaling and squaring.
+    pub fn expm(&self) -> Matrix<Complex> {
+        let n = self.size();
+        let norm = self.norm1();
+        let squarings = if norm > THETA_13 { (norm / THETA_13).log2().ceil() as u32 } else { 0 };
+        let a = self.scale(Complex::new(0.5f64.powi(squarings as i32), 0f64));
+
+        let b = |k: usize| Complex::new(PADE_13[k], 0f64);
+        let identity = Matrix::<Complex>::identity(n);
+        let a2 = &a * &a;
+        let a4 = &a2 * &a2;
+        let a6 = &a4 * &a2;
+
+        let u_inner = &(&a6.scale(b(13)) + &a4.scale(b(11))) + &a2.scale(b(9));
+        let u_outer = &(&(&(&a6 * &u_inner) + &a6.scale(b(7))) + &a4.scale(b(5))) + &(&a2.scale(b(3)) + &identity.scale(b(1)));
+        let u = &a * &u_outer;
+        let v_inner = &(&a6.scale(b(12)) + &a4.scale(b(10))) + &a2.scale(b(8));
+        let v = &(&(&(&a6 * &v_inner) + &a6.scale(b(6))) + &a4.scale(b(4))) + &(&a2.scale(b(2)) + &identity.scale(b(0)));
+
+        // (V - U)^{-1} (V + U). V - U is always well conditioned for ||A|| <= theta_13
+        let mut r = &(&v - &u).inverse().expect("Padé denominator is singular") * &(&v + &u);
+        for _ in 0..squarings {
+            r = &r * &r;
+        }
+        r
+    }
+
+    /// Builds a matrix from an eigendecomposition, `V diag(eigenvalues) V^\dagger`.
+    pub fn from_eigendecomposition(eigenvalues: &[Complex], eigenvectors: &Matrix<Complex>) -> Matrix<Complex> {
+        let n = eigenvectors.size();
+        let mut m = Matrix::<Complex>::new(n);
+        for i in 0..n {
+            for j in 0..n {
+                let value = eigenvalues.iter().enumerate().fold(Complex::zero(), |acc, (k, lambda)| {
+                    acc + *eigenvectors.get(i, k) * *lambda * eigenvectors.get(j, k).conj()
+                });
+                m.set(i, j, &value);
+            }
+        }
+        m
+    }
+}
+
+/// Deterministic pseudo-random Hermitian matrix, for tests.
+#[cfg(test)]
+pub fn test_hermitian(size: usize, seed: u64) -> Matrix<Complex> {
+    let mut state = seed;
+    let mut next = || {
+        // Numerical Recipes LCG
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((state >> 11) as f64) / ((1u64 << 53) as f64) - 0.5f64
+    };
+    let mut m = Matrix::<Complex>::new(size);
+    for i in 0..size {
+        m.set(i, i, &Complex::new(next(), 0f64));
+        for j in i + 1..size {
+            let value = Complex::new(next(), next());
+            m.set(i, j, &value);
+            m.set(j, i, &value.conj());
+        }
+    }
+    m
+}
+
+/// Deterministic pseudo-random unitary matrix, for tests.
+#[cfg(test)]
+pub fn test_unitary(size: usize, seed: u64) -> Matrix<Complex> {
+    test_hermitian(size, seed).scale(Complex::new(0f64, 4f64)).expm()
+}
+
+
+#[test]
+fn eigh_test() {
+    use num::One;
+
+    let h = test_hermitian(6, 7);
+    let (eigenvalues, vectors) = h.eigh().unwrap();
+    assert!(vectors.is_unitary(1e-12));
+    assert!(eigenvalues.windows(2).all(|pair| pair[0] <= pair[1]));
+    let diagonal : Vec<Complex> = eigenvalues.iter().map(|lambda| Complex::new(*lambda, 0f64)).collect();
+    assert!(Matrix::from_eigendecomposition(&diagonal, &vectors).max_deviation(&h) < 1e-12);
+    assert!((eigenvalues.iter().sum::<f64>() - h.trace().re()).abs() < 1e-12);
+
+    // Degenerate spectrum
+    let (eigenvalues, _) = Matrix::<Complex>::identity(3).scale(Complex::new(2f64, 0f64)).eigh().unwrap();
+    assert_eq!(vec![2f64, 2f64, 2f64], eigenvalues);
+
+    let mut not_hermitian = Matrix::<Complex>::identity(2);
+    not_hermitian.set(0, 1, &Complex::one());
+    assert!(not_hermitian.eigh().is_err());
+}
+
+#[test]
+fn eig_unitary_test() {
+    let u = test_unitary(5, 3);
+    let (eigenvalues, vectors) = u.eig_unitary().unwrap();
+    assert!(vectors.is_unitary(1e-10));
+    assert!(eigenvalues.iter().all(|lambda| (lambda.abs() - 1f64).abs() < 1e-10));
+    assert!(Matrix::from_eigendecomposition(&eigenvalues, &vectors).max_deviation(&u) < 1e-10);
+
+    // H diag(1, e^{i phi}) H with phi = 2 atan(0.618) gives 1 and e^{i phi} the
+    // same cos + 0.618 sin, which a fixed combination of the parts cannot split
+    let phi = 2f64 * 0.6180339887498949f64.atan();
+    let s = ::std::f64::consts::FRAC_1_SQRT_2;
+    let h = Matrix::new_from_row_slice(&[c!(s, 0f64), c!(s, 0f64), c!(s, 0f64), c!(-s, 0f64)]);
+    let diagonal = Matrix::new_from_row_slice(&[c!(1f64, 0f64), c!(0f64, 0f64), c!(0f64, 0f64), Complex::new_euler(1f64, phi)]);
+    let u = &(&h * &diagonal) * &h;
+    let (eigenvalues, vectors) = u.eig_unitary().unwrap();
+    assert!(Matrix::from_eigendecomposition(&eigenvalues, &vectors).max_deviation(&u) < 1e-10);
+    assert!(eigenvalues.iter().any(|lambda| (*lambda - c!(1f64, 0f64)).abs() < 1e-10));
+    assert!(eigenvalues.iter().any(|lambda| (*lambda - Complex::new_euler(1f64, phi)).abs() < 1e-10));
+
+    // e^{i theta} and e^{-i theta} share the eigenspace of the Hermitian part
+    let mirrored = Matrix::from_eigendecomposition(
+        &[Complex::new_euler(1f64, 0.9f64), Complex::new_euler(1f64, -0.9f64), c!(-1f64, 0f64), Complex::new_euler(1f64, 0.9f64)],
+        &test_unitary(4, 8));
+    let (eigenvalues, vectors) = mirrored.eig_unitary().unwrap();
+    assert!(vectors.is_unitary(1e-10));
+    assert!(Matrix::from_eigendecomposition(&eigenvalues, &vectors).max_deviation(&mirrored) < 1e-10);
+
+    let mut nan = Matrix::<Complex>::identity(2);
+    nan.set(0, 0, &c!(f64::NAN, 0f64));
+    assert!(nan.eigh().is_err());
+    assert!(nan.eig_unitary().is_err());
+}
+
+#[test]
+fn expm_test() {
+    // e^{-i theta X / 2} = Rx(theta)
+    let theta = 0.7f64;
+    let x = m_real![0, 1; 1, 0];
+    let rx = x.scale(Complex::new(0f64, -theta / 2f64)).expm();
+    let (c, s) = ((theta / 2f64).cos(), (theta / 2f64).sin());
+    assert!(rx.max_deviation(&Matrix::new_from_row_slice(&[c!(c, 0f64), c!(0f64, -s), c!(0f64, -s), c!(c, 0f64)])) < 1e-14);
+
+    // Large norms go through scaling and squaring, compare with the spectral form
+    let h = test_hermitian(4, 11).scale(Complex::new(30f64, 0f64));
+    let (eigenvalues, vectors) = h.eigh().unwrap();
+    let phases : Vec<Complex> = eigenvalues.iter().map(|lambda| Complex::new_euler(1f64, -lambda)).collect();
+    let expected = Matrix::from_eigendecomposition(&phases, &vectors);
+    let evolution = h.scale(Complex::new(0f64, -1f64)).expm();
+    assert!(evolution.max_deviation(&expected) < 1e-10);
+    assert!(evolution.is_unitary(1e-10));
+
+    assert!(Matrix::<Complex>::new(3).expm().max_deviation(&Matrix::identity(3)) < 1e-15);
+}
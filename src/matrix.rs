@@ -1,6 +1,6 @@
 //! Matrix library code (public for pedagogical reasons).
 
-use std::ops::{Add, Mul,Index, IndexMut};
+use std::ops::{Add, Sub, Mul,Index, IndexMut};
 use num::traits::{Zero, One};
 use std::cmp::PartialEq;
 use std::fmt;
@@ -23,6 +23,7 @@ use complex::Complex;
 
 /// Represents a square matrix
 // #[allow(missing_copy_implementations)]
+#[derive(Clone)]
 pub struct Matrix<T=Complex> {
     size: usize,
     elements : Vec<T>,
@@ -71,6 +72,11 @@ impl <T> Matrix<T>
         self.elements.as_slice()
     }
 
+    /// The elements in row-major order, without copying them.
+    pub fn into_vec(self) -> Vec<T> {
+        self.elements
+    }
+
     /// Construct a new identity matrix of given size.
     pub fn identity(size: usize) -> Matrix<T> {
         let mut elements = vec![T::zero(); size*size];
@@ -85,6 +91,17 @@ impl <T> Matrix<T>
         }
     }
 
+    /// Overwrites the matrix with the identity of the same size, without
+    /// allocating.
+    pub fn set_identity(&mut self) {
+        for element in self.elements.iter_mut() {
+            *element = T::zero();
+        }
+        for i in 0..self.size {
+            self.elements[i * self.size + i] = T::one();
+        }
+    }
+
     /// Embed another matrix into this one, overrising elements.
     ///
     /// Embed with top-left position at (i, j).
@@ -204,20 +221,20 @@ impl <T> Matrix<T>
         self.elements[i * self.size + j] = *value
     }
 
-    /// Approximately equal test.
-    pub fn approx_eq(&self, other: &Matrix<T>) -> bool {
-        if self.size != other.size {
-            return false;
-        }
-
+    /// The transpose of the matrix.
+    pub fn transpose(&self) -> Matrix<T> {
+        let mut m = Matrix::<T>::new(self.size);
         for i in 0..self.size {
             for j in 0..self.size {
-                if self.get(i, j) != other.get(i, j) {
-                    return false;
-                }
+                m.set(j, i, self.get(i, j));
             }
         }
-        true
+        m
+    }
+
+    /// Copy of the column `j`.
+    pub fn column(&self, j: usize) -> Vec<T> {
+        (0..self.size).map(|i| *self.get(i, j)).collect()
     }
 
     /// Dot product between a Matrix and a Vector
@@ -258,6 +275,242 @@ impl <T> Matrix<T>
     }
 }
 
+/// Element comparison used by `Matrix::approx_eq()`.
+pub trait ApproxEq {
+    /// Whether both values are within 1e-12 of each other.
+    fn approx_eq(&self, other: &Self) -> bool;
+}
+
+impl ApproxEq for Complex {
+    fn approx_eq(&self, other: &Complex) -> bool {
+        Complex::approx_eq(self, other)
+    }
+}
+
+impl ApproxEq for f64 {
+    fn approx_eq(&self, other: &f64) -> bool {
+        (self - other).abs() < 1e-12
+    }
+}
+
+impl <T> Matrix<T>
+    where T: PartialEq + Debug + Clone + Zero + One + Mul<Output = T> + Copy + ApproxEq {
+    /// Approximately equal test: every element within 1e-12 of the other one
+    /// (see `ApproxEq`). Use `max_deviation()` for other tolerances.
+    pub fn approx_eq(&self, other: &Matrix<T>) -> bool {
+        if self.size != other.size {
+            return false;
+        }
+        self.elements.iter().zip(other.elements.iter()).all(|(a, b)| a.approx_eq(b))
+    }
+}
+
+impl Matrix<Complex> {
+    /// The conjugate transpose (A^\dagger) of the matrix.
+    pub fn adjoint(&self) -> Matrix<Complex> {
+        let mut m = Matrix::<Complex>::new(self.size);
+        for i in 0..self.size {
+            for j in 0..self.size {
+                m.set(j, i, &self.get(i, j).conj());
+            }
+        }
+        m
+    }
+
+    /// Multiplies every element by `value`.
+    pub fn scale(&self, value: Complex) -> Matrix<Complex> {
+        Matrix {
+            size: self.size,
+            elements: self.elements.iter().map(|elem| *elem * value).collect(),
+        }
+    }
+
+    /// Sum of the diagonal elements.
+    pub fn trace(&self) -> Complex {
+        (0..self.size).fold(Complex::zero(), |acc, i| acc + *self.get(i, i))
+    }
+
+    /// Frobenius norm, i.e. _sqrt(sum(|a_ij|^2))_.
+    pub fn frobenius_norm(&self) -> f64 {
+        self.elements.iter().fold(0f64, |acc, elem| acc + elem.norm_sqr()).sqrt()
+    }
+
+    /// Operator (spectral) norm, i.e. the largest singular value.
+    ///
+    /// It is computed by power iteration on A^\dagger A, so it converges slowly
+    /// when the two largest singular values are very close.
+    pub fn operator_norm(&self) -> f64 {
+        if self.size == 0 {
+            return 0f64;
+        }
+        let gram = &self.adjoint() * self;
+        // Avoid starting orthogonal to the dominant singular vector by using a
+        // vector with distinct entries.
+        let mut v : Vec<Complex> = (0..self.size).map(|i| Complex::new(1f64, (i + 1) as f64 * 0.1f64)).collect();
+        let mut eigenvalue = 0f64;
+        for _ in 0..1000 {
+            let w : Vec<Complex> = (0..self.size)
+                .map(|i| (0..self.size).fold(Complex::zero(), |acc, j| acc + *gram.get(i, j) * v[j]))
+                .collect();
+            let norm = w.iter().fold(0f64, |acc, elem| acc + elem.norm_sqr()).sqrt();
+            if norm == 0f64 {
+                return 0f64;
+            }
+            v = w.iter().map(|elem| *elem / norm).collect();
+            let converged = (norm - eigenvalue).abs() <= 1e-15 * norm;
+            eigenvalue = norm;
+            if converged {
+                break;
+            }
+        }
+        eigenvalue.sqrt()
+    }
+
+    /// LU decomposition with partial pivoting, done in place on a copy of the
+    /// matrix. Returns the factors packed in one matrix (L has an implicit unit
+    /// diagonal), the row permutation and its sign, or `None` if singular.
+    fn lu(&self) -> Option<(Matrix<Complex>, Vec<usize>, f64)> {
+        let n = self.size;
+        let mut lu = self.clone();
+        let mut rows : Vec<usize> = (0..n).collect();
+        let mut sign = 1f64;
+        for k in 0..n {
+            let pivot = (k..n).fold(k, |best, i| if lu.get(i, k).abs() > lu.get(best, k).abs() { i } else { best });
+            // Relative to the column, as elimination only cancels within its scale
+            let column_scale = (0..n).fold(0f64, |acc, i| acc.max(self.get(i, k).abs()));
+            if lu.get(pivot, k).abs() <= 1e-14 * column_scale {
+                return None;
+            }
+            if pivot != k {
+                for j in 0..n {
+                    lu.elements.swap(pivot * n + j, k * n + j);
+                }
+                rows.swap(pivot, k);
+                sign = -sign;
+            }
+            let diagonal = *lu.get(k, k);
+            for i in k + 1..n {
+                let factor = *lu.get(i, k) / diagonal;
+                lu.set(i, k, &factor);
+                for j in k + 1..n {
+                    let value = *lu.get(i, j) - factor * *lu.get(k, j);
+                    lu.set(i, j, &value);
+                }
+            }
+        }
+        Some((lu, rows, sign))
+    }
+
+    /// The determinant of the matrix.
+    pub fn determinant(&self) -> Complex {
+        match self.lu() {
+            Some((lu, _, sign)) => (0..self.size).fold(Complex::new(sign, 0f64), |acc, i| acc * *lu.get(i, i)),
+            None => Complex::zero(),
+        }
+    }
+
+    /// The inverse of the matrix, or an error if it is singular.
+    pub fn inverse(&self) -> Result<Matrix<Complex>, String> {
+        let n = self.size;
+        let (lu, rows, _) = match self.lu() {
+            Some(lu) => lu,
+            None => return Err("Error: Cannot invert a singular matrix!!".to_string()),
+        };
+
+        // Solve L U x = P e_j for every column j
+        let mut m = Matrix::<Complex>::new(n);
+        for j in 0..n {
+            let mut x : Vec<Complex> = rows.iter().map(|&row| if row == j { Complex::one() } else { Complex::zero() }).collect();
+            for i in 0..n {
+                for k in 0..i {
+                    let value = *lu.get(i, k) * x[k];
+                    x[i] -= value;
+                }
+            }
+            for i in (0..n).rev() {
+                for k in i + 1..n {
+                    let value = *lu.get(i, k) * x[k];
+                    x[i] -= value;
+                }
+                x[i] /= *lu.get(i, i);
+            }
+            for (i, value) in x.iter().enumerate() {
+                m.set(i, j, value);
+            }
+        }
+        Ok(m)
+    }
+
+    /// Tests whether U^\dagger U == I, with every element within `tolerance`.
+    pub fn is_unitary(&self, tolerance: f64) -> bool {
+        (&self.adjoint() * self).max_deviation(&Matrix::identity(self.size)) <= tolerance
+    }
+
+    /// Process fidelity between two unitaries, _|Tr(U^\dagger V)|^2 / d^2_.
+    ///
+    /// It is 1 iff they are equal up to a global phase.
+    ///
+    /// # Panics
+    ///
+    /// We panic if the matrices have different sizes.
+    pub fn process_fidelity(&self, other: &Matrix<Complex>) -> f64 {
+        assert_eq!(self.size, other.size);
+        let overlap = self.elements.iter().zip(other.elements.iter())
+            .fold(Complex::zero(), |acc, (a, b)| acc + a.conj() * *b);
+        overlap.norm_sqr() / ((self.size * self.size) as f64)
+    }
+
+    /// Largest absolute difference between the elements of the two matrices.
+    ///
+    /// # Panics
+    ///
+    /// We panic if the matrices have different sizes.
+    pub fn max_deviation(&self, other: &Matrix<Complex>) -> f64 {
+        assert_eq!(self.size, other.size);
+        self.elements.iter().zip(other.elements.iter())
+            .map(|(a, b)| (*a - *b).abs())
+            .fold(0f64, f64::max)
+    }
+
+    /// Global phase `phi` that best maps this matrix onto `other`, i.e. the one
+    /// minimizing `|| e^{i phi} self - other ||`.
+    ///
+    /// # Panics
+    ///
+    /// We panic if the matrices have different sizes.
+    pub fn global_phase_to(&self, other: &Matrix<Complex>) -> f64 {
+        assert_eq!(self.size, other.size);
+        // arg(sum(conj(a_ij) * b_ij))
+        let overlap = self.elements.iter().zip(other.elements.iter())
+            .fold(Complex::zero(), |acc, (a, b)| acc + a.conj() * *b);
+        overlap.arg()
+    }
+
+    /// Global phase between this matrix and `other` (see `global_phase_to()`),
+    /// together with the largest element-wise deviation once it is removed.
+    ///
+    /// # Panics
+    ///
+    /// We panic if the matrices have different sizes.
+    pub fn deviation_up_to_global_phase(&self, other: &Matrix<Complex>) -> (f64, f64) {
+        let phase = self.global_phase_to(other);
+        let rotation = Complex::new_euler(1f64, phase);
+        let deviation = self.elements.iter().zip(other.elements.iter())
+            .map(|(a, b)| (*a * rotation - *b).abs())
+            .fold(0f64, f64::max);
+        (phase, deviation)
+    }
+
+    /// Tests whether `other == e^{i phi} self` for some global phase `phi`, with
+    /// every element within `tolerance`.
+    pub fn equiv_up_to_global_phase(&self, other: &Matrix<Complex>, tolerance: f64) -> bool {
+        if self.size != other.size {
+            return false;
+        }
+        self.deviation_up_to_global_phase(other).1 <= tolerance
+    }
+}
+
 ///
 /// Traits implementation
 ///
@@ -341,6 +594,17 @@ impl_ref_ops!(Matrix, Complex, Add, add, (self, rhs) {
     m
 });
 
+impl_ref_ops!(Matrix, Complex, Sub, sub, (self, rhs) {
+    assert_eq!(self.size, rhs.size);
+    let mut m = Matrix::<Complex>::new(self.size);
+    for i in 0..self.size {
+        for j in 0..self.size {
+            m.set(i, j, &(*self.get(i, j) - *rhs.get(i, j)));
+        }
+    }
+    m
+});
+
 impl_ref_ops!(Matrix, Complex, Mul, mul, (self, rhs){
     assert_eq!(self.size, rhs.size);
     let mut m = Matrix::<Complex>::new(self.size);
@@ -439,6 +703,14 @@ fn matrix_test() {
     assert_eq!(added, &m + &m);
     assert_eq!(squared, &m * &m);
     assert_eq!(expected, &m * &v);
+
+    let nudged = &m + &m_real![1e-14, 0; 0, 0];
+    assert!(m.approx_eq(&nudged));
+    assert!(!m.approx_eq(&(&m + &m_real![0, 1e-9; 0, 0])));
+    let mut f = Matrix::<f64>::identity(2);
+    assert!(f.approx_eq(&Matrix::identity(2)));
+    f.set(1, 0, &1e-9f64);
+    assert!(!f.approx_eq(&Matrix::identity(2)));
 }
 
 #[test]
@@ -517,3 +789,58 @@ fn dot_test() {
     assert_eq!(res, expected);
 
 }
+
+#[test]
+fn global_phase_test() {
+    let m = Matrix::new_from_row_slice(&[
+        Complex::new(0.6f64,0f64), Complex::new(0f64,0.8f64),
+        Complex::new(0f64,0.8f64), Complex::new(0.6f64,0f64),
+    ]);
+    let phase = Complex::new_euler(1f64, 2f64);
+    let rotated = Matrix::new_from_row_slice(&[
+        *m.get(0, 0) * phase, *m.get(0, 1) * phase,
+        *m.get(1, 0) * phase, *m.get(1, 1) * phase,
+    ]);
+
+    assert!((m.global_phase_to(&rotated) - 2f64).abs() < 1e-12);
+    assert!(m.equiv_up_to_global_phase(&rotated, 1e-12));
+    assert!(m.max_deviation(&rotated) > 0.1f64);
+    assert!(!m.equiv_up_to_global_phase(&Matrix::identity(2), 1e-6));
+    assert!(!m.equiv_up_to_global_phase(&Matrix::identity(4), 1e-6));
+}
+
+#[test]
+fn linear_algebra_test() {
+    let m = Matrix::new_from_row_slice(&[
+        c!(1f64, 1f64), c!(2f64, 0f64), c!(0f64, 0f64),
+        c!(0f64, -1f64), c!(3f64, 0f64), c!(1f64, 2f64),
+        c!(4f64, 0f64), c!(0f64, 0f64), c!(1f64, 0f64),
+    ]);
+
+    assert_eq!(c!(0f64, 1f64), *m.adjoint().get(0, 1));
+    assert_eq!(c!(0f64, -1f64), *m.transpose().get(0, 1));
+    assert_eq!(c!(5f64, 1f64), m.trace());
+    assert_eq!(&m_real![2, 4; 6, 8] - &m_real![1, 2; 3, 4], m_real![1, 2; 3, 4]);
+    assert!((m.frobenius_norm() - 38f64.sqrt()).abs() < 1e-12);
+    assert!((m_real![3, 0; 0, -5].operator_norm() - 5f64).abs() < 1e-9);
+
+    // det computed by hand with the rule of Sarrus
+    assert_eq!(c!(11f64, 21f64), m.determinant());
+    assert_eq!(Complex::zero(), m_real![1, 2; 2, 4].determinant());
+
+    let inverse = m.inverse().unwrap();
+    assert!((&inverse * &m).max_deviation(&Matrix::identity(3)) < 1e-12);
+    assert!(m_real![1, 2; 2, 4].inverse().is_err());
+    // Badly scaled but far from singular
+    let scaled = m_real![1e-7, 0; 0, 1e8];
+    assert!((scaled.determinant() - c!(10f64, 0f64)).abs() < 1e-12);
+    assert!(scaled.inverse().unwrap().max_deviation(&m_real![1e7, 0; 0, 1e-8]) < 1e-6);
+    assert_eq!(Complex::zero(), m_real![1e-7, 2e-7; 3, 6].determinant());
+
+    let s = 0.5f64.sqrt();
+    let h = m_real![s, s; s, -s];
+    assert!(h.is_unitary(1e-12));
+    assert!(!m.is_unitary(1e-6));
+    assert!((h.process_fidelity(&h.scale(c!(0f64, 1f64))) - 1f64).abs() < 1e-12);
+    assert!(h.process_fidelity(&Matrix::identity(2)) < 1e-12);
+}
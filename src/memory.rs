@@ -0,0 +1,210 @@
+//! Memory estimates, to refuse circuits before allocating their state.
+//!
+//! The unitary of n qubits takes 16 * 4^n bytes, so a 16-qubit header asks for
+//! 64 GiB and the allocation aborts the whole process. The simulators compare
+//! the estimated peak memory of their state with a `MemoryLimits` first, and
+//! return a `SimulatorError::Memory` instead.
+
+use std::fmt;
+use std::mem;
+
+use serde_json;
+
+use complex::Complex;
+
+/// Default memory budget: 4 GiB.
+pub const DEFAULT_MAX_MEMORY: usize = 4 << 30;
+
+/// How big a simulation is allowed to be.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryLimits {
+    /// Maximum estimated peak memory, in bytes.
+    pub max_memory: usize,
+    /// Maximum number of qubits, if any.
+    pub max_qubits: Option<usize>,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> MemoryLimits {
+        MemoryLimits {
+            max_memory: DEFAULT_MAX_MEMORY,
+            max_qubits: None,
+        }
+    }
+}
+
+/// Why a simulation was refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryError {
+    TooManyQubits {
+        number_of_qubits: usize,
+        max_qubits: usize,
+    },
+    /// `required` is `None` when the estimate does not even fit in a `usize`.
+    OverBudget {
+        number_of_qubits: usize,
+        required: Option<usize>,
+        max_memory: usize,
+    },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryError::TooManyQubits { number_of_qubits, max_qubits } =>
+                write!(f, "Error: {} qubits is over the limit of {} qubits!!", number_of_qubits, max_qubits),
+            MemoryError::OverBudget { number_of_qubits, required: Some(required), max_memory } =>
+                write!(f, "Error: Simulating {} qubits needs {} bytes, over the limit of {} bytes!!",
+                       number_of_qubits, required, max_memory),
+            MemoryError::OverBudget { number_of_qubits, required: None, max_memory } =>
+                write!(f, "Error: Simulating {} qubits needs more than {} bytes, over the limit of {} bytes!!",
+                       number_of_qubits, usize::MAX, max_memory),
+        }
+    }
+}
+
+impl From<MemoryError> for String {
+    fn from(error: MemoryError) -> String {
+        error.to_string()
+    }
+}
+
+/// Errors of the simulator constructors and runs, to tell a refused circuit from
+/// a wrong one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulatorError {
+    /// The circuit is over the memory limits.
+    Memory(MemoryError),
+    /// Anything else, like a malformed circuit.
+    Circuit(String),
+}
+
+impl fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SimulatorError::Memory(ref error) => write!(f, "{}", error),
+            SimulatorError::Circuit(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<MemoryError> for SimulatorError {
+    fn from(error: MemoryError) -> SimulatorError {
+        SimulatorError::Memory(error)
+    }
+}
+
+impl From<String> for SimulatorError {
+    fn from(message: String) -> SimulatorError {
+        SimulatorError::Circuit(message)
+    }
+}
+
+impl From<SimulatorError> for String {
+    fn from(error: SimulatorError) -> String {
+        error.to_string()
+    }
+}
+
+impl MemoryLimits {
+    /// Reads the optional "max_memory" (in bytes) and "max_qubits" config of a
+    /// compiled circuit, the defaults are `DEFAULT_MAX_MEMORY` and no qubit limit.
+    pub fn from_circuit(circuit: &serde_json::Value) -> Result<MemoryLimits, String> {
+        let mut limits = MemoryLimits::default();
+        let config = &circuit["config"];
+        if !config["max_memory"].is_null() {
+            limits.max_memory = match config["max_memory"].as_u64() {
+                Some(max_memory) => max_memory as usize,
+                None => return Err("Error: max_memory must be a number of bytes!!".to_string()),
+            };
+        }
+        if !config["max_qubits"].is_null() {
+            limits.max_qubits = match config["max_qubits"].as_u64() {
+                Some(max_qubits) => Some(max_qubits as usize),
+                None => return Err("Error: max_qubits must be a number!!".to_string()),
+            };
+        }
+        Ok(limits)
+    }
+
+    /// Checks a simulation of `number_of_qubits` with the estimated peak memory
+    /// `required` (`None` when it overflows).
+    pub fn check(&self, number_of_qubits: usize, required: Option<usize>) -> Result<(), MemoryError> {
+        if let Some(max_qubits) = self.max_qubits {
+            if number_of_qubits > max_qubits {
+                return Err(MemoryError::TooManyQubits {
+                    number_of_qubits: number_of_qubits,
+                    max_qubits: max_qubits,
+                });
+            }
+        }
+        match required {
+            Some(required) if required <= self.max_memory => Ok(()),
+            _ => Err(MemoryError::OverBudget {
+                number_of_qubits: number_of_qubits,
+                required: required,
+                max_memory: self.max_memory,
+            }),
+        }
+    }
+}
+
+/// Peak memory of the unitary simulator: the unitary, the product being
+/// computed and the copy returned in the results, plus the sparse product that
+/// is converted when it fills up (a column index next to every value).
+pub fn unitary_memory(number_of_qubits: usize) -> Option<usize> {
+    if number_of_qubits >= 4 * mem::size_of::<usize>() {
+        return None;
+    }
+    let elements = 1usize << (2 * number_of_qubits);
+    let dense = mem::size_of::<Complex>().checked_mul(elements)?;
+    let sparse = (mem::size_of::<Complex>() + mem::size_of::<usize>()).checked_mul(elements)?;
+    dense.checked_mul(3)?.checked_add(sparse)
+}
+
+/// Memory of the stabilizer tableau: 2n + 1 rows of packed X and Z bits.
+pub fn stabilizer_memory(number_of_qubits: usize) -> Option<usize> {
+    #[allow(clippy::manual_div_ceil)]
+    let words = (number_of_qubits + 63) / 64;
+    let row = words.checked_mul(2 * mem::size_of::<u64>())?.checked_add(1)?;
+    row.checked_mul(number_of_qubits.checked_mul(2)?.checked_add(1)?)
+}
+
+/// Peak memory of a matrix product state whose bond between qubits q and q + 1
+/// is at most `bond_dimensions[q]`: the site tensors, plus the two-site tensor
+/// and its SVD for the largest bond.
+pub fn mps_memory(bond_dimensions: &[usize]) -> Option<usize> {
+    let bond = |q: usize| if q == 0 || q > bond_dimensions.len() { 1 } else { bond_dimensions[q - 1] };
+    let mut elements = 0usize;
+    for q in 0..bond_dimensions.len() + 1 {
+        elements = elements.checked_add(bond(q).checked_mul(bond(q + 1))?.checked_mul(2)?)?;
+    }
+    // theta, U and V^dagger are (2 chi) x (2 chi)
+    let largest = bond_dimensions.iter().cloned().max().unwrap_or(1);
+    let two_site = largest.checked_mul(2)?.checked_pow(2)?.checked_mul(3)?;
+    elements.checked_add(two_site)?.checked_mul(mem::size_of::<Complex>())
+}
+
+#[test]
+fn memory_test() {
+    assert_eq!(Some(72 * 16), unitary_memory(2));
+    assert_eq!(None, unitary_memory(40));
+    assert_eq!(None, unitary_memory(64));
+    assert_eq!(Some(5 * 17), stabilizer_memory(2));
+    // Product state of 3 qubits: 3 sites of 1x2x1 and a 2x2 two-site tensor
+    assert_eq!(Some((6 + 12) * 16), mps_memory(&[1, 1]));
+    assert!(mps_memory(&[64; 999]).unwrap() < 1 << 30);
+    assert!(mps_memory(&[1 << 40, 1 << 40]).is_none());
+
+    let limits = MemoryLimits::default();
+    assert!(limits.check(12, unitary_memory(12)).is_ok());
+    match limits.check(16, unitary_memory(16)) {
+        Err(MemoryError::OverBudget { number_of_qubits: 16, required: Some(required), .. }) =>
+            assert_eq!(72 << 32, required),
+        other => panic!("Unexpected {:?}", other),
+    }
+    let limits = MemoryLimits::from_circuit(&json!({"config": {"max_qubits": 3, "max_memory": 1024}})).unwrap();
+    assert_eq!(Err(MemoryError::TooManyQubits { number_of_qubits: 4, max_qubits: 3 }), limits.check(4, Some(0)));
+    assert!(limits.check(3, unitary_memory(3)).is_err());
+    assert!(MemoryLimits::from_circuit(&json!({"config": {"max_memory": "lots"}})).is_err());
+}
@@ -0,0 +1,663 @@
+//! Matrix product state simulation of low-entanglement circuits.
+//!
+//! The state is a chain of tensors A_q[l][s][r], one per qubit, and an amplitude
+//! is the product of the matrices A_q[.][x_q][.] for the bits x_q of the basis
+//! state. Memory and time are polynomial in the number of qubits and in the bond
+//! dimension (the size of the l and r indices), which only grows with the
+//! entanglement between the two halves of the chain.
+//!
+//! Two-qubit gates contract the tensors of both qubits, apply the gate and split
+//! them back with an SVD, keeping at most `max_bond_dimension` singular values.
+//! The state is kept in mixed canonical form, so the singular values are the
+//! Schmidt coefficients and the discarded weight is the truncation error. CX
+//! gates on non-adjacent qubits are applied by swapping the qubits next to each
+//! other and back.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use rand::{Rng, SeedableRng, StdRng};
+use serde_json;
+
+use complex::Complex;
+use expression::Expression;
+use gate::Gate;
+use matrix::Matrix;
+use memory::{MemoryLimits, SimulatorError, mps_memory};
+use result::SimulationResult;
+use num::{Zero, One};
+use simulatortools::Endianness;
+use stabilizer::circuit_size;
+
+/// Singular values below this (relative to the largest one) are always dropped.
+const SINGULAR_VALUE_CUTOFF: f64 = 1e-14;
+
+/// SVD of the `rows` x `cols` row-major matrix `a`, with one-sided Jacobi
+/// rotations. Returns U (rows x k), the singular values in descending order and
+/// V^dagger (k x cols), where k = min(rows, cols).
+fn svd(rows: usize, cols: usize, a: &[Complex]) -> (Vec<Complex>, Vec<f64>, Vec<Complex>) {
+    if rows < cols {
+        // A^dagger = U' S V'^dagger, so A = V' S U'^dagger
+        let adjoint : Vec<Complex> = (0..cols * rows).map(|k| a[(k % rows) * cols + k / rows].conj()).collect();
+        let (u, s, vh) = svd(cols, rows, &adjoint);
+        let k = s.len();
+        let new_u = (0..rows * k).map(|index| vh[(index % k) * rows + index / k].conj()).collect();
+        let new_vh = (0..k * cols).map(|index| u[(index % cols) * k + index / cols].conj()).collect();
+        return (new_u, s, new_vh);
+    }
+
+    // Rotate pairs of columns of W = A V until they are orthogonal
+    let mut w = a.to_vec();
+    let mut v = vec![Complex::zero(); cols * cols];
+    for j in 0..cols {
+        v[j * cols + j] = Complex::one();
+    }
+    for _ in 0..100 {
+        let mut rotated = false;
+        for p in 0..cols {
+            for q in p + 1..cols {
+                let (mut alpha, mut beta, mut gamma) = (0f64, 0f64, Complex::zero());
+                for i in 0..rows {
+                    alpha += w[i * cols + p].norm_sqr();
+                    beta += w[i * cols + q].norm_sqr();
+                    gamma += w[i * cols + p].conj() * w[i * cols + q];
+                }
+                if gamma.abs() <= 1e-15 * (alpha * beta).sqrt() || gamma.abs() < 1e-300 {
+                    continue;
+                }
+                rotated = true;
+
+                // Multiply column q by e^{-i arg(gamma)} so the overlap is real,
+                // then it is the real Jacobi rotation
+                let phase = (gamma / gamma.abs()).conj();
+                let zeta = (beta - alpha) / (2f64 * gamma.abs());
+                let sign = if zeta >= 0f64 { 1f64 } else { -1f64 };
+                let t = sign / (zeta.abs() + (1f64 + zeta * zeta).sqrt());
+                let c = 1f64 / (1f64 + t * t).sqrt();
+                let s = c * t;
+                for (m, size) in [(&mut w, rows), (&mut v, cols)].iter_mut() {
+                    for i in 0..*size {
+                        let (x, y) = (m[i * cols + p], m[i * cols + q] * phase);
+                        m[i * cols + p] = x * c - y * s;
+                        m[i * cols + q] = x * s + y * c;
+                    }
+                }
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+
+    let norms : Vec<f64> = (0..cols)
+        .map(|j| (0..rows).map(|i| w[i * cols + j].norm_sqr()).sum::<f64>().sqrt())
+        .collect();
+    let mut order : Vec<usize> = (0..cols).collect();
+    order.sort_by(|&i, &j| norms[j].partial_cmp(&norms[i]).unwrap_or(Ordering::Equal));
+
+    let mut u = vec![Complex::zero(); rows * cols];
+    let mut vh = vec![Complex::zero(); cols * cols];
+    for (k, &j) in order.iter().enumerate() {
+        for i in 0..rows {
+            if norms[j] > 0f64 {
+                u[i * cols + k] = w[i * cols + j] / norms[j];
+            }
+        }
+        for i in 0..cols {
+            vh[k * cols + i] = v[i * cols + j].conj();
+        }
+    }
+    (u, order.iter().map(|&j| norms[j]).collect(), vh)
+}
+
+/// Tensor of one qubit, indexed [left][physical][right].
+#[derive(Debug, Clone, PartialEq)]
+struct Site {
+    left: usize,
+    right: usize,
+    data: Vec<Complex>,
+}
+
+impl Site {
+    fn index(&self, l: usize, s: usize, r: usize) -> usize {
+        (l * 2 + s) * self.right + r
+    }
+}
+
+/// A matrix product state, with qubit q on site q of the chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mps {
+    sites: Vec<Site>,
+    /// Sites left of it are left-orthonormal, sites right of it right-orthonormal.
+    center: usize,
+    max_bond_dimension: usize,
+    truncation_error: f64,
+}
+
+impl Mps {
+    /// The state |0...0>, with no limit on the bond dimension.
+    pub fn new(number_of_qubits: usize) -> Mps {
+        let site = Site {
+            left: 1,
+            right: 1,
+            data: vec![Complex::one(), Complex::zero()],
+        };
+        Mps {
+            sites: vec![site; number_of_qubits],
+            center: 0,
+            max_bond_dimension: usize::MAX,
+            truncation_error: 0f64,
+        }
+    }
+
+    pub fn number_of_qubits(&self) -> usize {
+        self.sites.len()
+    }
+
+    /// Limits the bond dimension of the following two-qubit gates.
+    pub fn set_max_bond_dimension(&mut self, max_bond_dimension: usize) {
+        self.max_bond_dimension = max_bond_dimension.max(1);
+    }
+
+    /// Bond dimensions between sites q and q + 1.
+    pub fn bond_dimensions(&self) -> Vec<usize> {
+        self.sites.iter().skip(1).map(|site| site.left).collect()
+    }
+
+    /// Sum of the weights discarded by the truncations so far: the fidelity with
+    /// the exact state is at least 1 - truncation_error (to first order).
+    pub fn truncation_error(&self) -> f64 {
+        self.truncation_error
+    }
+
+    /// Applies the 2x2 unitary `matrix` on `qubit`.
+    pub fn apply_single(&mut self, matrix: &Matrix<Complex>, qubit: usize) {
+        let site = &mut self.sites[qubit];
+        for l in 0..site.left {
+            for r in 0..site.right {
+                let (i0, i1) = (site.index(l, 0, r), site.index(l, 1, r));
+                let (a0, a1) = (site.data[i0], site.data[i1]);
+                site.data[i0] = *matrix.get(0, 0) * a0 + *matrix.get(0, 1) * a1;
+                site.data[i1] = *matrix.get(1, 0) * a0 + *matrix.get(1, 1) * a1;
+            }
+        }
+    }
+
+    /// Theta[(l, s1), (s2, r)] for sites q and q + 1.
+    fn merge(&self, q: usize) -> Vec<Complex> {
+        let (a, b) = (&self.sites[q], &self.sites[q + 1]);
+        let mut theta = vec![Complex::zero(); a.left * 4 * b.right];
+        for l in 0..a.left {
+            for s1 in 0..2 {
+                for m in 0..a.right {
+                    let x = a.data[a.index(l, s1, m)];
+                    for s2 in 0..2 {
+                        for r in 0..b.right {
+                            theta[((l * 2 + s1) * 2 + s2) * b.right + r] += x * b.data[b.index(m, s2, r)];
+                        }
+                    }
+                }
+            }
+        }
+        theta
+    }
+
+    /// Splits theta back into sites q and q + 1, leaving the center on q + 1 when
+    /// `center_right`, or on q otherwise.
+    fn split(&mut self, q: usize, theta: &[Complex], truncate: bool, center_right: bool) {
+        let (left, right) = (self.sites[q].left, self.sites[q + 1].right);
+        let (rows, cols) = (left * 2, 2 * right);
+        let (u, s, vh) = svd(rows, cols, theta);
+        let k_all = s.len();
+
+        let total : f64 = s.iter().map(|x| x * x).sum();
+        let mut keep = s.iter().take_while(|&&x| x > SINGULAR_VALUE_CUTOFF * s[0]).count().max(1);
+        if truncate {
+            keep = keep.min(self.max_bond_dimension);
+        }
+        let kept : f64 = s[..keep].iter().map(|x| x * x).sum();
+        if truncate {
+            self.truncation_error += (total - kept) / total;
+        }
+        // Renormalize what we keep
+        let scale = (total / kept).sqrt();
+
+        let mut a = vec![Complex::zero(); rows * keep];
+        let mut b = vec![Complex::zero(); keep * cols];
+        for k in 0..keep {
+            let (sa, sb) = if center_right { (1f64, s[k] * scale) } else { (s[k] * scale, 1f64) };
+            for i in 0..rows {
+                a[i * keep + k] = u[i * k_all + k] * sa;
+            }
+            for j in 0..cols {
+                b[k * cols + j] = vh[k * cols + j] * sb;
+            }
+        }
+        self.sites[q] = Site { left: left, right: keep, data: a };
+        self.sites[q + 1] = Site { left: keep, right: right, data: b };
+        self.center = if center_right { q + 1 } else { q };
+    }
+
+    /// Moves the orthogonality center to `site`.
+    fn move_center(&mut self, site: usize) {
+        while self.center < site {
+            let (q, theta) = (self.center, self.merge(self.center));
+            self.split(q, &theta, false, true);
+        }
+        while self.center > site {
+            let (q, theta) = (self.center - 1, self.merge(self.center - 1));
+            self.split(q, &theta, false, false);
+        }
+    }
+
+    /// Applies a two-qubit gate that permutes the basis states of sites q and
+    /// q + 1, mapping (s1, s2) to `permutation(s1, s2)`.
+    fn apply_permutation<F: Fn(usize, usize) -> (usize, usize)>(&mut self, q: usize, permutation: F) {
+        self.move_center(q);
+        let theta = self.merge(q);
+        let right = self.sites[q + 1].right;
+        let mut permuted = vec![Complex::zero(); theta.len()];
+        for l in 0..self.sites[q].left {
+            for s1 in 0..2 {
+                for s2 in 0..2 {
+                    let (t1, t2) = permutation(s1, s2);
+                    for r in 0..right {
+                        permuted[((l * 2 + t1) * 2 + t2) * right + r] = theta[((l * 2 + s1) * 2 + s2) * right + r];
+                    }
+                }
+            }
+        }
+        self.split(q, &permuted, true, true);
+    }
+
+    /// Swaps qubits q and q + 1.
+    pub fn swap(&mut self, q: usize) {
+        self.apply_permutation(q, |s1, s2| (s2, s1));
+    }
+
+    /// Applies CX, swapping the target next to the control if they are not adjacent.
+    pub fn cx(&mut self, control: usize, target: usize) {
+        let (low, high) = (control.min(target), control.max(target));
+        // The qubit at `low` travels to high - 1
+        for q in low..high - 1 {
+            self.swap(q);
+        }
+        if control < target {
+            self.apply_permutation(high - 1, |s1, s2| (s1, s2 ^ s1));
+        } else {
+            self.apply_permutation(high - 1, |s1, s2| (s1 ^ s2, s2));
+        }
+        for q in (low..high - 1).rev() {
+            self.swap(q);
+        }
+    }
+
+    /// Amplitude of the basis state `index`, where bit q is qubit q.
+    pub fn amplitude(&self, index: usize) -> Complex {
+        let mut env = vec![Complex::one()];
+        for (q, site) in self.sites.iter().enumerate() {
+            let s = (index >> q) & 1;
+            let mut next = vec![Complex::zero(); site.right];
+            for (l, x) in env.iter().enumerate() {
+                for (r, value) in next.iter_mut().enumerate() {
+                    *value += *x * site.data[site.index(l, s, r)];
+                }
+            }
+            env = next;
+        }
+        env[0]
+    }
+
+    /// Samples a basis state, where bit q is qubit q.
+    pub fn sample<R: Rng>(&mut self, rng: &mut R) -> usize {
+        // With the center on the first site, the rest of the chain is
+        // right-orthonormal and the marginals only need the left environment.
+        self.move_center(0);
+        let mut env = vec![Complex::one()];
+        let mut index = 0;
+        for (q, site) in self.sites.iter().enumerate() {
+            let mut branches = Vec::with_capacity(2);
+            for s in 0..2 {
+                let mut next = vec![Complex::zero(); site.right];
+                for (l, x) in env.iter().enumerate() {
+                    for (r, value) in next.iter_mut().enumerate() {
+                        *value += *x * site.data[site.index(l, s, r)];
+                    }
+                }
+                let weight : f64 = next.iter().map(|x| x.norm_sqr()).sum();
+                branches.push((next, weight));
+            }
+            let total = branches[0].1 + branches[1].1;
+            let s = if rng.gen::<f64>() * total < branches[0].1 { 0 } else { 1 };
+            index |= s << q;
+            let (next, weight) = branches.swap_remove(s);
+            env = next.iter().map(|x| *x / weight.sqrt()).collect();
+        }
+        index
+    }
+}
+
+/// Runs compiled circuits on a matrix product state.
+pub struct MpsSimulator {
+    circuit: serde_json::Value,
+    number_of_qubits: usize,
+    number_of_clbits: usize,
+    result: SimulationResult,
+    state: Mps,
+    max_bond_dimension: usize,
+    parameters: HashMap<String, f64>,
+    seed: Option<usize>,
+    endianness: Endianness,
+    memory_limits: MemoryLimits,
+}
+
+impl MpsSimulator {
+    pub fn new(compiled_circuit: String) -> Result<MpsSimulator, SimulatorError> {
+        let circuit : serde_json::Value = match serde_json::from_str(compiled_circuit.as_str()) {
+            Ok(_circuit) => _circuit,
+            Err(err) => return Err(SimulatorError::Circuit(format!("Error: parsing compiled circuit!!: {}", err))),
+        };
+        MpsSimulator::from_json(circuit)
+    }
+
+    /// Same as `new()` but takes an already parsed compiled circuit.
+    pub fn from_json(circuit: serde_json::Value) -> Result<MpsSimulator, SimulatorError> {
+        let (number_of_qubits, number_of_clbits) = circuit_size(&circuit)?;
+        let endianness = Endianness::from_circuit(&circuit)?;
+        let memory_limits = MemoryLimits::from_circuit(&circuit)?;
+        if circuit["operations"].as_array().is_none() {
+            return Err(SimulatorError::Circuit("No operations field in the circuit!!".to_string()));
+        }
+        let result = SimulationResult::new(circuit["header"].clone());
+        Ok(MpsSimulator {
+            circuit: circuit,
+            number_of_qubits: number_of_qubits,
+            number_of_clbits: number_of_clbits,
+            result: result,
+            state: Mps::new(number_of_qubits),
+            max_bond_dimension: usize::MAX,
+            parameters: HashMap::new(),
+            seed: None,
+            endianness: endianness,
+            memory_limits: memory_limits,
+        })
+    }
+
+    /// Caps the bond dimension, trading accuracy for memory and time.
+    pub fn set_max_bond_dimension(&mut self, max_bond_dimension: usize) {
+        self.max_bond_dimension = max_bond_dimension;
+    }
+
+    /// Binds values to the named parameters used in symbolic gate parameters.
+    pub fn set_parameters(&mut self, parameters: HashMap<String, f64>) {
+        self.parameters = parameters;
+    }
+
+    /// Seeds the random number generator, to get reproducible counts.
+    pub fn set_seed(&mut self, seed: usize) {
+        self.seed = Some(seed);
+    }
+
+    /// Order of the bits in the counts, overriding the circuit config.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// Limits checked by `run()` against the worst case memory of the circuit,
+    /// overriding the circuit config.
+    pub fn set_memory_limits(&mut self, memory_limits: MemoryLimits) {
+        self.memory_limits = memory_limits;
+    }
+
+    /// The state after `run()`.
+    pub fn state(&self) -> &Mps {
+        &self.state
+    }
+
+    /// Upper bounds of the bond dimensions reached by the circuit. Every CX across
+    /// a bond at most doubles it, and the swaps bringing the qubits together at
+    /// most double it again.
+    fn max_bond_dimensions(&self) -> Vec<usize> {
+        let n = self.number_of_qubits;
+        let mut crossings = vec![0usize; n.saturating_sub(1)];
+        for c_qasm in self.circuit["operations"].as_array().unwrap() {
+            if c_qasm["name"] == "CX" {
+                let qubit = |index: usize| (c_qasm["qubits"][index].as_u64().unwrap_or(0) as usize).min(n.saturating_sub(1));
+                let (low, high) = (qubit(0).min(qubit(1)), qubit(0).max(qubit(1)));
+                for crossing in crossings[low..high].iter_mut() {
+                    *crossing += 1;
+                }
+            }
+        }
+        crossings.iter().enumerate().map(|(q, &crossing)| {
+            let exponent = (2 * crossing).min(q + 1).min(n - q - 1);
+            if exponent >= 63 { self.max_bond_dimension } else { (1usize << exponent).min(self.max_bond_dimension) }
+        }).collect()
+    }
+
+    fn param(&self, c_qasm: &serde_json::Value, index: usize) -> Result<f64, String> {
+        let value = Expression::from_json(&c_qasm["params"][index])?.eval(&self.parameters)?;
+        if !value.is_finite() {
+            return Err(format!("Error: Parameter {} is not a finite number!!", c_qasm["params"][index]));
+        }
+        Ok(value)
+    }
+
+    /// Simulates the circuit and samples the measurements `shots` times.
+    ///
+    /// Measurements must be at the end of the circuit (no gates on a measured
+    /// qubit), and the results are data.counts, data.truncation_error and
+    /// data.bond_dimensions.
+    ///
+    /// Fails with `SimulatorError::Memory` if the tensors could grow over the
+    /// memory limits with the current bond dimension cap.
+    pub fn run(&mut self, shots: usize) -> Result<SimulationResult, SimulatorError> {
+        self.memory_limits.check(self.number_of_qubits, mps_memory(&self.max_bond_dimensions()))?;
+        Ok(self.simulate(shots)?)
+    }
+
+    fn simulate(&mut self, shots: usize) -> Result<SimulationResult, String> {
+        let start = Instant::now();
+        self.state = Mps::new(self.number_of_qubits);
+        self.state.set_max_bond_dimension(self.max_bond_dimension);
+        let number_of_operations = self.circuit["operations"].as_array().unwrap().len();
+        let mut measurements : Vec<(usize, usize)> = Vec::new();
+
+        for j in 0..number_of_operations {
+            let c_qasm = self.circuit["operations"][j].clone();
+            let qubits : Vec<usize> = c_qasm["qubits"].as_array().map_or(vec![], |qubits| {
+                qubits.iter().filter_map(|qubit| qubit.as_u64()).map(|qubit| qubit as usize).collect()
+            });
+            if qubits.iter().any(|&qubit| qubit >= self.number_of_qubits) {
+                return Err(format!("Error: Operation {} out of the circuit registers!!", c_qasm));
+            }
+            // Modified gates are not supported, like unknown ones
+            let name = if c_qasm["modifiers"].is_null() { c_qasm["name"].as_str().unwrap_or("") } else { "" };
+            if name != "measure" && name != "barrier" && measurements.iter().any(|&(qubit, _)| qubits.contains(&qubit)) {
+                return Err("Error: Gates after a measurement are not supported by the MPS simulator!!".to_string());
+            }
+            match (name, &qubits[..]) {
+                ("U", &[qubit]) => {
+                    let gate = Gate::<Complex>::u(self.param(&c_qasm, 0)?, self.param(&c_qasm, 1)?, self.param(&c_qasm, 2)?);
+                    self.state.apply_single(&gate.matrix, qubit);
+                },
+                ("CX", &[control, target]) if control != target => self.state.cx(control, target),
+                ("measure", &[qubit]) => match c_qasm["clbits"][0].as_u64() {
+                    Some(clbit) if (clbit as usize) < self.number_of_clbits => measurements.push((qubit, clbit as usize)),
+                    _ => return Err(format!("Error: Measure needs a qubit and a clbit: {}!!", c_qasm)),
+                },
+                ("U", _) | ("CX", _) | ("measure", _) => return Err(format!("Error: Wrong qubits in operation {}!!", c_qasm)),
+                ("barrier", _) => (),
+                _ => {
+                    error!("Error: Unsupported gate type for the MPS simulator: {}!!", name);
+                    self.result = SimulationResult::new(self.circuit["header"].clone());
+                    return Ok(self.result.clone());
+                }
+            }
+        }
+
+        let mut counts : BTreeMap<String, usize> = BTreeMap::new();
+        if !measurements.is_empty() {
+            let mut rng = match self.seed {
+                Some(seed) => StdRng::from_seed(&[seed][..]),
+                None => match StdRng::new() {
+                    Ok(rng) => rng,
+                    Err(err) => return Err(format!("Error: Cannot initialize the random number generator!!: {}", err)),
+                },
+            };
+            for _ in 0..shots {
+                let state = self.state.sample(&mut rng);
+                let mut clbits = vec![false; self.number_of_clbits];
+                for &(qubit, clbit) in measurements.iter() {
+                    clbits[clbit] = (state >> qubit) & 1 == 1;
+                }
+                *counts.entry(self.endianness.bitstring(&clbits)).or_insert(0) += 1;
+            }
+        }
+        debug!("run: truncation_error={} bond_dimensions={:?}",
+               self.state.truncation_error(), self.state.bond_dimensions());
+
+        self.result.data.counts = Some(counts);
+        self.result.data.truncation_error = Some(self.state.truncation_error());
+        self.result.data.bond_dimensions = Some(self.state.bond_dimensions());
+        self.result.seed = self.seed;
+        self.result.done(start);
+        Ok(self.result.clone())
+    }
+}
+
+
+#[test]
+fn svd_test() {
+    // Rectangular matrices both ways, with a repeated singular value
+    for &(rows, cols) in [(3, 5), (6, 2), (4, 4)].iter() {
+        let a : Vec<Complex> = (0..rows * cols)
+            .map(|k| Complex::new((k as f64 * 1.3).sin(), (k as f64 * 0.7).cos()))
+            .collect();
+        let (u, s, vh) = svd(rows, cols, &a);
+        let k = s.len();
+        assert_eq!(rows.min(cols), k);
+        assert!(s.windows(2).all(|pair| pair[0] >= pair[1]));
+        for i in 0..rows {
+            for j in 0..cols {
+                let value = (0..k).fold(Complex::zero(), |acc, m| acc + u[i * k + m] * vh[m * cols + j] * s[m]);
+                assert!((value - a[i * cols + j]).abs() < 1e-12);
+            }
+        }
+    }
+}
+
+#[test]
+fn mps_simulator_test() {
+    use UnitarySimulator;
+
+    // Entangling circuit with non-adjacent CX, in both directions
+    let mut operations = Vec::new();
+    for layer in 0..3 {
+        for qubit in 0..5 {
+            let angle = |k: usize| ((layer * 5 + qubit) as f64 * 0.37 + k as f64).sin() * 3f64;
+            operations.push(json!({"name": "U", "qubits": [qubit], "params": [angle(0), angle(1), angle(2)]}));
+        }
+        operations.push(json!({"name": "CX", "qubits": [0, 3]}));
+        operations.push(json!({"name": "CX", "qubits": [4, 1]}));
+        operations.push(json!({"name": "CX", "qubits": [2, 3]}));
+    }
+    let circuit = json!({"header": {"number_of_qubits": 5}, "operations": operations});
+
+    let mut us = UnitarySimulator::from_json(circuit.clone()).unwrap();
+    us.run().unwrap();
+    let expected = us.unitary().column(0);
+    let mut mps = MpsSimulator::from_json(circuit.clone()).unwrap();
+    let result = mps.run(0).unwrap();
+    assert!(result.is_done());
+    assert!(result.data.truncation_error.unwrap() < 1e-12);
+    for (index, amplitude) in expected.iter().enumerate() {
+        assert!((mps.state().amplitude(index) - *amplitude).abs() < 1e-10);
+    }
+
+    // Truncating to bond dimension 2 loses some weight, and says so
+    mps.set_max_bond_dimension(2);
+    let result = mps.run(0).unwrap();
+    let error = result.data.truncation_error.unwrap();
+    assert!(error > 1e-6 && error < 1f64);
+    assert!(result.data.bond_dimensions.unwrap().iter().all(|&d| d <= 2));
+}
+
+#[test]
+fn mps_sampling_test() {
+    // GHZ state on 60 qubits only needs bond dimension 2
+    let n = 60;
+    let mut operations = vec![json!({"name": "U", "qubits": [0], "params": ["pi/2", 0, "pi"]})];
+    for qubit in 1..n {
+        operations.push(json!({"name": "CX", "qubits": [qubit - 1, qubit]}));
+    }
+    for qubit in 0..n {
+        operations.push(json!({"name": "measure", "qubits": [qubit], "clbits": [qubit]}));
+    }
+    let circuit = json!({"header": {"number_of_qubits": n, "number_of_clbits": n}, "operations": operations});
+    let mut mps = MpsSimulator::from_json(circuit).unwrap();
+    mps.set_seed(11);
+    let result = mps.run(200).unwrap();
+    let counts = result.counts().unwrap();
+    assert_eq!(2, counts.len());
+    let zeros = counts[&"0".repeat(n)];
+    assert!(zeros > 60 && zeros < 140);
+    assert_eq!(json!(vec![2; n - 1]), result.to_json()["data"]["bond_dimensions"]);
+
+    // Without a bond dimension cap, 40 layers of CX across the middle could need
+    // 2^30 x 2^30 tensors
+    let operations : Vec<_> = (0..40).map(|_| json!({"name": "CX", "qubits": [0, n - 1]})).collect();
+    let circuit = json!({"header": {"number_of_qubits": n}, "operations": operations});
+    let mut mps = MpsSimulator::from_json(circuit).unwrap();
+    match mps.run(0).unwrap_err() {
+        SimulatorError::Memory(::memory::MemoryError::OverBudget { .. }) => (),
+        err => panic!("Unexpected error {}", err),
+    }
+    mps.set_max_bond_dimension(16);
+    assert!(mps.run(0).is_ok());
+
+    // A Bell pair truncated to a product state keeps half of the weight
+    let circuit = json!({
+        "header": {"number_of_qubits": 2},
+        "operations": [
+            {"name": "U", "qubits": [0], "params": ["pi/2", 0, "pi"]},
+            {"name": "CX", "qubits": [0, 1]}
+        ]
+    });
+    let mut mps = MpsSimulator::from_json(circuit).unwrap();
+    mps.set_max_bond_dimension(1);
+    let result = mps.run(0).unwrap();
+    assert!((result.data.truncation_error.unwrap() - 0.5).abs() < 1e-12);
+    assert!((mps.state().amplitude(0).abs() - 1f64).abs() < 1e-12);
+
+    let circuit = json!({
+        "header": {"number_of_qubits": 1, "number_of_clbits": 1},
+        "operations": [
+            {"name": "measure", "qubits": [0], "clbits": [0]},
+            {"name": "U", "qubits": [0], "params": ["pi/3", 0, 0]}
+        ]
+    });
+    assert!(MpsSimulator::from_json(circuit).unwrap().run(1).is_err());
+}
+
+#[test]
+fn mps_malformed_operations_test() {
+    let run = |operations: serde_json::Value| {
+        let circuit = json!({"header": {"number_of_qubits": 2, "number_of_clbits": 1}, "operations": operations});
+        MpsSimulator::from_json(circuit).unwrap().run(1)
+    };
+    assert!(run(json!([{"name": "U", "qubits": [], "params": [0, 0, 0]}])).is_err());
+    assert!(run(json!([{"name": "CX", "qubits": [0]}])).is_err());
+    assert!(run(json!([{"name": "CX", "qubits": [0, 0]}])).is_err());
+    assert!(run(json!([{"name": "measure", "qubits": [0]}])).is_err());
+    assert!(run(json!([{"name": "measure", "qubits": [0], "clbits": [0]}])).is_ok());
+    assert!(run(json!([
+        {"name": "U", "qubits": [0], "params": ["0/0", 0, 0]},
+        {"name": "CX", "qubits": [0, 1]}
+    ])).is_err());
+
+    // No qubits, so no bonds
+    let circuit = json!({"header": {"number_of_qubits": 0}, "operations": [{"name": "CX", "qubits": [0, 1]}]});
+    assert!(MpsSimulator::from_json(circuit).unwrap().run(0).is_err());
+}
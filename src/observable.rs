@@ -0,0 +1,68 @@
+//! Observables and expectation values.
+
+use complex::Complex;
+use matrix::Matrix;
+use num::Zero;
+
+/// Anything we can compute an expectation value <psi|O|psi> of.
+pub trait Observable {
+    /// Number of qubits the observable acts on.
+    fn number_of_qubits(&self) -> usize;
+
+    /// Expectation value on the state vector `state`, where the basis state index
+    /// is q_{n-1} ... q_1 q_0 (same order as the simulated unitaries).
+    fn expectation(&self, state: &[Complex]) -> Result<f64, String>;
+
+    /// Expectation value on the state `unitary |0...0>`.
+    fn expectation_unitary(&self, unitary: &Matrix) -> Result<f64, String> {
+        self.expectation(unitary.column(0).as_slice())
+    }
+}
+
+/// Dense observables: `O` is a 2^n x 2^n Hermitian matrix.
+impl Observable for Matrix<Complex> {
+    fn number_of_qubits(&self) -> usize {
+        self.size().trailing_zeros() as usize
+    }
+
+    fn expectation(&self, state: &[Complex]) -> Result<f64, String> {
+        if state.len() != self.size() {
+            return Err(format!("Error: Observable of size {} applied to a state of size {}!!",
+                               self.size(), state.len()));
+        }
+
+        let mut value = Complex::zero();
+        for i in 0..self.size() {
+            let mut row = Complex::zero();
+            for (j, amplitude) in state.iter().enumerate() {
+                row += *self.get(i, j) * *amplitude;
+            }
+            // conj(psi_i) * (O psi)_i
+            value += state[i].conj() * row;
+        }
+        Ok(value.re())
+    }
+}
+
+
+#[test]
+fn dense_observable_test() {
+    // Z on a single qubit
+    let z = Matrix::new_from_row_slice(&[
+        Complex::new(1f64, 0f64), Complex::new(0f64, 0f64),
+        Complex::new(0f64, 0f64), Complex::new(-1f64, 0f64),
+    ]);
+    let s = 0.5f64.sqrt();
+
+    assert_eq!(1, z.number_of_qubits());
+    assert!((z.expectation(&[Complex::new(1f64, 0f64), Complex::zero()]).unwrap() - 1f64).abs() < 1e-12);
+    assert!(z.expectation(&[Complex::new(s, 0f64), Complex::new(0f64, s)]).unwrap().abs() < 1e-12);
+    assert!(z.expectation(&[Complex::zero(); 4]).is_err());
+
+    // X|0> = |1>
+    let x = Matrix::new_from_row_slice(&[
+        Complex::new(0f64, 0f64), Complex::new(1f64, 0f64),
+        Complex::new(1f64, 0f64), Complex::new(0f64, 0f64),
+    ]);
+    assert!((z.expectation_unitary(&x).unwrap() + 1f64).abs() < 1e-12);
+}
@@ -0,0 +1,265 @@
+//! Parameterized circuits with late binding.
+//!
+//! Variational algorithms simulate the same circuit over and over with different
+//! angles. A `ParameterizedCircuit` parses the compiled circuit and all the
+//! symbolic gate parameters once, so binding a new set of values only needs to
+//! evaluate the expressions and patch the operation parameters.
+
+use std::collections::HashMap;
+
+use serde_json;
+
+use expression::Expression;
+use result::SimulationResult;
+use UnitarySimulator;
+
+/// Operations whose "params" are angles. The rest (custom unitaries, snapshot
+/// observables...) are passed through untouched.
+const ANGLE_GATES: &[&str] = &["U", "mcu"];
+
+pub struct ParameterizedCircuit {
+    circuit: serde_json::Value,
+    params: Vec<Vec<Expression>>,
+    exponents: Vec<Vec<(usize, Expression)>>,
+    parameters: Vec<String>,
+}
+
+impl ParameterizedCircuit {
+    pub fn new(compiled_circuit: String) -> Result<ParameterizedCircuit, String> {
+        let circuit : serde_json::Value = match serde_json::from_str(compiled_circuit.as_str()) {
+            Ok(_circuit) => _circuit,
+            Err(err) => return Err(format!("Error: parsing compiled circuit!!: {}", err)),
+        };
+        ParameterizedCircuit::from_json(circuit)
+    }
+
+    /// Same as `new()` but takes an already parsed compiled circuit.
+    pub fn from_json(circuit: serde_json::Value) -> Result<ParameterizedCircuit, String> {
+        let mut params = Vec::new();
+        let mut exponents = Vec::new();
+        let mut parameters = Vec::new();
+        {
+            let operations = match circuit["operations"].as_array() {
+                Some(operations) => operations,
+                None => return Err("No operations field in the circuit!!".to_string())
+            };
+
+            for operation in operations {
+                let mut op_params = Vec::new();
+                let is_angle_gate = ANGLE_GATES.iter().any(|name| operation["name"] == *name);
+                if let (true, Some(values)) = (is_angle_gate, operation["params"].as_array()) {
+                    for value in values {
+                        op_params.push(ParameterizedCircuit::parse(value, &mut parameters)?);
+                    }
+                }
+                params.push(op_params);
+
+                let mut op_exponents = Vec::new();
+                if let Some(modifiers) = operation["modifiers"].as_array() {
+                    for (m, modifier) in modifiers.iter().enumerate() {
+                        if modifier["name"] == "pow" {
+                            op_exponents.push((m, ParameterizedCircuit::parse(&modifier["exponent"], &mut parameters)?));
+                        }
+                    }
+                }
+                exponents.push(op_exponents);
+            }
+        }
+
+        debug!("ParameterizedCircuit: operations={} parameters={:?}", params.len(), parameters);
+
+        Ok(ParameterizedCircuit {
+            circuit: circuit,
+            params: params,
+            exponents: exponents,
+            parameters: parameters,
+        })
+    }
+
+    fn parse(value: &serde_json::Value, parameters: &mut Vec<String>) -> Result<Expression, String> {
+        let expression = Expression::from_json(value)?;
+        for name in expression.parameters() {
+            if !parameters.contains(&name) {
+                parameters.push(name);
+            }
+        }
+        Ok(expression)
+    }
+
+    /// Names of the free parameters of the circuit, in order of appearance.
+    pub fn parameters(&self) -> &[String] {
+        self.parameters.as_slice()
+    }
+
+    /// Symbolic parameters of every operation, indexed by operation. Only angle
+    /// gates have any.
+    pub fn operation_params(&self) -> &[Vec<Expression>] {
+        self.params.as_slice()
+    }
+
+    /// Symbolic exponents of the "pow" modifiers of every operation, indexed by
+    /// operation, along with the index of the modifier.
+    pub fn modifier_exponents(&self) -> &[Vec<(usize, Expression)>] {
+        self.exponents.as_slice()
+    }
+
+    /// The compiled circuit, with the parameters as they were given.
+    pub fn circuit(&self) -> &serde_json::Value {
+        &self.circuit
+    }
+
+    /// Produces a runnable compiled circuit where every gate parameter and "pow"
+    /// exponent has been evaluated with `binding`.
+    ///
+    /// Returns an error if any of the circuit parameters is not bound.
+    pub fn bind(&self, binding: &HashMap<String, f64>) -> Result<serde_json::Value, String> {
+        self.bind_shifted(binding, None)
+    }
+
+    /// Like `bind()`, but adds `shift` to the parameter `param` of operation `op`
+    /// when `shift` is `Some((op, param, shift))`.
+    pub(crate) fn bind_shifted(&self, binding: &HashMap<String, f64>, shift: Option<(usize, usize, f64)>)
+        -> Result<serde_json::Value, String> {
+        let mut circuit = self.circuit.clone();
+        for (j, op_params) in self.params.iter().enumerate() {
+            if op_params.is_empty() {
+                continue;
+            }
+            let mut values = Vec::with_capacity(op_params.len());
+            for (k, expression) in op_params.iter().enumerate() {
+                let mut value = expression.eval(binding)?;
+                if let Some((op, param, delta)) = shift {
+                    if op == j && param == k {
+                        value += delta;
+                    }
+                }
+                values.push(json!(value));
+            }
+            circuit["operations"][j]["params"] = serde_json::Value::Array(values);
+        }
+        for (j, op_exponents) in self.exponents.iter().enumerate() {
+            for &(m, ref expression) in op_exponents.iter() {
+                circuit["operations"][j]["modifiers"][m]["exponent"] = json!(expression.eval(binding)?);
+            }
+        }
+        Ok(circuit)
+    }
+
+    /// Binds the parameters and simulates the resulting circuit.
+    pub fn run(&self, binding: &HashMap<String, f64>) -> Result<SimulationResult, String> {
+        let mut us = UnitarySimulator::from_json(self.bind(binding)?)?;
+        Ok(us.run()?)
+    }
+
+    /// Simulates the circuit once per binding, returning the results in the same
+    /// order as `bindings`.
+    pub fn sweep(&self, bindings: &[HashMap<String, f64>]) -> Result<Vec<SimulationResult>, String> {
+        bindings.iter().map(|binding| self.run(binding)).collect()
+    }
+
+    /// Builds the bindings for every point of the cartesian product of the given
+    /// parameter values, to be used with `sweep()`.
+    ///
+    /// The last axis changes fastest.
+    pub fn grid(axes: &[(String, Vec<f64>)]) -> Vec<HashMap<String, f64>> {
+        let mut bindings = vec![HashMap::new()];
+        for (name, values) in axes {
+            let mut next = Vec::with_capacity(bindings.len() * values.len());
+            for binding in bindings.iter() {
+                for value in values {
+                    let mut point = binding.clone();
+                    point.insert(name.clone(), *value);
+                    next.push(point);
+                }
+            }
+            bindings = next;
+        }
+        bindings
+    }
+}
+
+
+#[test]
+fn parameterized_bind_test() {
+    let circuit = ParameterizedCircuit::new(r#"{
+        "header": {"number_of_qubits": 2},
+        "operations": [
+            {"name": "U", "qubits": [0], "params": ["theta", 0, "pi"]},
+            {"name": "CX", "qubits": [0, 1]},
+            {"name": "U", "qubits": [1], "params": ["theta/2", "phi", 0]}
+        ]
+    }"#.to_string()).unwrap();
+
+    assert_eq!(&["theta".to_string(), "phi".to_string()], circuit.parameters());
+
+    let mut binding = HashMap::new();
+    binding.insert("theta".to_string(), 1f64);
+    assert!(circuit.bind(&binding).is_err());
+
+    binding.insert("phi".to_string(), 2f64);
+    let bound = circuit.bind(&binding).unwrap();
+    assert_eq!(json!([1f64, 0f64, ::std::f64::consts::PI]), bound["operations"][0]["params"]);
+    assert_eq!(json!([0.5f64, 2f64, 0f64]), bound["operations"][2]["params"]);
+    assert!(bound["operations"][1].get("params").is_none());
+
+    let shifted = circuit.bind_shifted(&binding, Some((2, 1, 0.25f64))).unwrap();
+    assert_eq!(json!([0.5f64, 2.25f64, 0f64]), shifted["operations"][2]["params"]);
+
+    // Only angle gates are parsed, other params are left as they are
+    let circuit = ParameterizedCircuit::new(r#"{
+        "header": {"number_of_qubits": 2},
+        "operations": [
+            {"name": "unitary", "qubits": [0], "params": [[0, 0], [1, 0], [1, 0], [0, 0]]},
+            {"name": "mcu", "qubits": [0, 1], "params": ["theta", 0, 0]},
+            {"name": "snapshot", "label": "energy", "snapshot_type": "expectation_value_pauli",
+             "params": ["X0 + 0.5 * Z1"]}
+        ]
+    }"#.to_string()).unwrap();
+    assert_eq!(&["theta".to_string()], circuit.parameters());
+    let mut binding = HashMap::new();
+    binding.insert("theta".to_string(), 0.5f64);
+    let bound = circuit.bind(&binding).unwrap();
+    assert_eq!(circuit.circuit()["operations"][0], bound["operations"][0]);
+    assert_eq!(json!(["X0 + 0.5 * Z1"]), bound["operations"][2]["params"]);
+    let result = circuit.run(&binding).unwrap();
+    assert!(result.is_done());
+
+    // pow exponents are bound too
+    let circuit = ParameterizedCircuit::new(r#"{
+        "header": {"number_of_qubits": 2},
+        "operations": [
+            {"name": "U", "qubits": [0, 1], "params": ["pi", 0, "pi"],
+             "modifiers": [{"name": "ctrl"}, {"name": "pow", "exponent": "theta"}]}
+        ]
+    }"#.to_string()).unwrap();
+    assert_eq!(&["theta".to_string()], circuit.parameters());
+    let bound = circuit.bind(&binding).unwrap();
+    assert_eq!(json!(0.5f64), bound["operations"][0]["modifiers"][1]["exponent"]);
+    assert_eq!(json!({"name": "ctrl"}), bound["operations"][0]["modifiers"][0]);
+    assert!(circuit.run(&binding).unwrap().is_done());
+}
+
+#[test]
+fn parameterized_sweep_test() {
+    let circuit = ParameterizedCircuit::new(r#"{
+        "header": {"number_of_qubits": 1},
+        "operations": [{"name": "U", "qubits": [0], "params": ["theta", "phi", 0]}]
+    }"#.to_string()).unwrap();
+
+    let bindings = ParameterizedCircuit::grid(&[
+        ("theta".to_string(), vec![0f64, ::std::f64::consts::PI]),
+        ("phi".to_string(), vec![0f64, 1f64, 2f64]),
+    ]);
+    assert_eq!(6, bindings.len());
+    assert_eq!(Some(&0f64), bindings[2].get("theta"));
+    assert_eq!(Some(&2f64), bindings[2].get("phi"));
+
+    let results = circuit.sweep(&bindings).unwrap();
+    assert_eq!(6, results.len());
+    for (binding, result) in bindings.iter().zip(results.iter()) {
+        assert!(result.is_done());
+        // <0|U|0> = cos(theta/2)
+        let u00 = result.unitary().unwrap().get(0, 0).re();
+        assert!((u00 - (binding["theta"] / 2f64).cos()).abs() < 1e-12);
+    }
+}
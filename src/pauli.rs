@@ -0,0 +1,306 @@
+//! Pauli-string observables.
+//!
+//! A `PauliString` is a real coefficient times a tensor product of I, X, Y, Z
+//! operators on some qubits, and a `PauliSum` is a linear combination of them.
+//! Expectation values are computed directly on the amplitudes, without building
+//! the dense 2^n x 2^n operator: a Pauli string maps the basis state |x> to
+//! phase(x) |x ^ flips>, where `flips` has the bits of the qubits with X or Y.
+
+use std::fmt;
+use std::mem;
+
+use complex::Complex;
+use matrix::Matrix;
+use num::Zero;
+use observable::Observable;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pauli {
+    I,
+    X,
+    Y,
+    Z,
+}
+
+impl Pauli {
+    fn from_char(c: char) -> Option<Pauli> {
+        match c {
+            'I' => Some(Pauli::I),
+            'X' => Some(Pauli::X),
+            'Y' => Some(Pauli::Y),
+            'Z' => Some(Pauli::Z),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Pauli {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// coefficient * P_{q_0} P_{q_1} ...
+#[derive(Debug, Clone, PartialEq)]
+pub struct PauliString {
+    pub coefficient: f64,
+    /// (qubit, operator) pairs, sorted by qubit and without identities.
+    pub operators: Vec<(usize, Pauli)>,
+}
+
+impl PauliString {
+    pub fn new(coefficient: f64, operators: Vec<(usize, Pauli)>) -> Result<PauliString, String> {
+        let mut operators : Vec<(usize, Pauli)> = operators.into_iter()
+            .filter(|&(_, pauli)| pauli != Pauli::I)
+            .collect();
+        operators.sort_by_key(|&(qubit, _)| qubit);
+        for pair in operators.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(format!("Error: Qubit {} appears twice in a Pauli string!!", pair[0].0));
+            }
+        }
+        // The masks have one bit per qubit
+        if let Some(&(qubit, _)) = operators.last() {
+            if qubit >= 8 * mem::size_of::<usize>() {
+                return Err(format!("Error: Qubit {} is too large for a Pauli string!!", qubit));
+            }
+        }
+        Ok(PauliString {
+            coefficient: coefficient,
+            operators: operators,
+        })
+    }
+
+    /// Parses a single term like "0.5 * X0 Z2", "-Y1" or "2".
+    pub fn parse(text: &str) -> Result<PauliString, String> {
+        let text = text.trim();
+        let (sign, text) = if let Some(stripped) = text.strip_prefix('-') {
+            (-1f64, stripped.trim())
+        } else if let Some(stripped) = text.strip_prefix('+') {
+            (1f64, stripped.trim())
+        } else {
+            (1f64, text)
+        };
+        if text.is_empty() {
+            return Err("Error: Empty Pauli string!!".to_string());
+        }
+
+        let mut coefficient = sign;
+        let mut operators = Vec::new();
+        for (i, word) in text.split(|c: char| c.is_whitespace() || c == '*').filter(|w| !w.is_empty()).enumerate() {
+            let first = word.chars().next().unwrap();
+            match Pauli::from_char(first) {
+                Some(pauli) => {
+                    let qubit = match word[1..].parse::<usize>() {
+                        Ok(qubit) => qubit,
+                        Err(_) => return Err(format!("Error: Invalid Pauli operator '{}'!!", word)),
+                    };
+                    operators.push((qubit, pauli));
+                },
+                None => {
+                    if i != 0 {
+                        return Err(format!("Error: Coefficient '{}' must come first in a Pauli string!!", word));
+                    }
+                    match word.parse::<f64>() {
+                        Ok(value) => coefficient *= value,
+                        Err(_) => return Err(format!("Error: Invalid Pauli string term '{}'!!", word)),
+                    }
+                },
+            }
+        }
+        PauliString::new(coefficient, operators)
+    }
+
+    fn masks(&self) -> (usize, usize, usize) {
+        let mut flips = 0usize;
+        let mut signs = 0usize;
+        let mut number_of_ys = 0usize;
+        for &(qubit, pauli) in self.operators.iter() {
+            match pauli {
+                Pauli::X => flips |= 1 << qubit,
+                Pauli::Y => {
+                    flips |= 1 << qubit;
+                    signs |= 1 << qubit;
+                    number_of_ys += 1;
+                },
+                Pauli::Z => signs |= 1 << qubit,
+                Pauli::I => (),
+            }
+        }
+        (flips, signs, number_of_ys)
+    }
+
+    /// Computes `P|x> = phase(x) |x ^ flips>` for every basis state.
+    fn for_each_element<F: FnMut(usize, usize, Complex)>(&self, dim: usize, mut f: F) {
+        let (flips, signs, number_of_ys) = self.masks();
+        // Y = i X Z, so every Y contributes a factor i.
+        let y_phase = Complex::i().pow((number_of_ys % 4) as u32) * self.coefficient;
+        for x in 0..dim {
+            let phase = if (x & signs).count_ones() % 2 == 0 { y_phase } else { -y_phase };
+            f(x, x ^ flips, phase);
+        }
+    }
+
+    /// Dense matrix of the operator on `number_of_qubits` qubits.
+    ///
+    /// This is exponential in the number of qubits, prefer `expectation()`.
+    pub fn to_matrix(&self, number_of_qubits: usize) -> Matrix {
+        let dim = 1usize << number_of_qubits;
+        let mut m = Matrix::new(dim);
+        self.for_each_element(dim, |x, y, phase| m.set(y, x, &phase));
+        m
+    }
+}
+
+impl Observable for PauliString {
+    fn number_of_qubits(&self) -> usize {
+        self.operators.last().map(|&(qubit, _)| qubit + 1).unwrap_or(0)
+    }
+
+    fn expectation(&self, state: &[Complex]) -> Result<f64, String> {
+        let dim = 1usize.checked_shl(self.number_of_qubits() as u32).unwrap_or(0);
+        if dim == 0 || state.len() < dim {
+            return Err(format!("Error: Pauli string on {} qubits applied to a state of size {}!!",
+                               self.number_of_qubits(), state.len()));
+        }
+
+        let mut value = Complex::zero();
+        self.for_each_element(state.len(), |x, y, phase| {
+            // conj(psi_y) * phase * psi_x
+            value += state[y].conj() * phase * state[x];
+        });
+        Ok(value.re())
+    }
+}
+
+impl fmt::Display for PauliString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.coefficient)?;
+        if !self.operators.is_empty() {
+            write!(f, " *")?;
+        }
+        for &(qubit, pauli) in self.operators.iter() {
+            write!(f, " {}{}", pauli, qubit)?;
+        }
+        Ok(())
+    }
+}
+
+/// A linear combination of Pauli strings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PauliSum {
+    pub terms: Vec<PauliString>,
+}
+
+impl PauliSum {
+    pub fn new(terms: Vec<PauliString>) -> PauliSum {
+        PauliSum { terms: terms }
+    }
+
+    /// Parses a sum of terms like "0.5 * X0 Z2 - 0.25 * Y1 + Z0".
+    pub fn parse(text: &str) -> Result<PauliSum, String> {
+        let mut terms = Vec::new();
+        let mut start = 0;
+        let chars : Vec<(usize, char)> = text.char_indices().collect();
+        for (k, &(i, c)) in chars.iter().enumerate() {
+            // A sign splits terms, unless it is the sign of an exponent (1e-3)
+            let exponent = k > 1 && (chars[k - 1].1 == 'e' || chars[k - 1].1 == 'E') && chars[k - 2].1.is_ascii_digit();
+            if (c == '+' || c == '-') && !exponent && !text[start..i].trim().is_empty() {
+                terms.push(PauliString::parse(&text[start..i])?);
+                start = i;
+            }
+        }
+        if text[start..].trim().is_empty() {
+            return Err(format!("Error: Empty Pauli sum term in '{}'!!", text));
+        }
+        terms.push(PauliString::parse(&text[start..])?);
+        Ok(PauliSum::new(terms))
+    }
+
+    /// Dense matrix of the operator on `number_of_qubits` qubits.
+    ///
+    /// This is exponential in the number of qubits, prefer `expectation()`.
+    pub fn to_matrix(&self, number_of_qubits: usize) -> Matrix {
+        let dim = 1usize << number_of_qubits;
+        let mut m = Matrix::new(dim);
+        for term in self.terms.iter() {
+            term.for_each_element(dim, |x, y, phase| {
+                let value = *m.get(y, x) + phase;
+                m.set(y, x, &value);
+            });
+        }
+        m
+    }
+}
+
+impl Observable for PauliSum {
+    fn number_of_qubits(&self) -> usize {
+        self.terms.iter().map(|term| term.number_of_qubits()).max().unwrap_or(0)
+    }
+
+    fn expectation(&self, state: &[Complex]) -> Result<f64, String> {
+        let mut value = 0f64;
+        for term in self.terms.iter() {
+            value += term.expectation(state)?;
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for PauliSum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            if i != 0 {
+                write!(f, " + ")?;
+            }
+            write!(f, "{}", term)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[test]
+fn pauli_parse_test() {
+    let sum = PauliSum::parse("0.5 * X0 Z2 - 0.25 * Y1 + Z0 -2e-1 I3").unwrap();
+    assert_eq!(4, sum.terms.len());
+    assert_eq!(PauliString::new(0.5, vec![(0, Pauli::X), (2, Pauli::Z)]).unwrap(), sum.terms[0]);
+    assert_eq!(PauliString::new(-0.25, vec![(1, Pauli::Y)]).unwrap(), sum.terms[1]);
+    assert_eq!(PauliString::new(1.0, vec![(0, Pauli::Z)]).unwrap(), sum.terms[2]);
+    assert_eq!(PauliString::new(-0.2, vec![]).unwrap(), sum.terms[3]);
+    assert_eq!(3, sum.number_of_qubits());
+
+    assert!(PauliString::parse("X0 Z0").is_err());
+    assert!(PauliString::parse("X0 0.5").is_err());
+    assert!(PauliString::parse("Xa").is_err());
+    assert!(PauliSum::parse("X0 +").is_err());
+}
+
+#[test]
+fn pauli_expectation_test() {
+    use num::One;
+
+    let s = 0.5f64.sqrt();
+    // Bell state (|00> + |11>) / sqrt(2)
+    let bell = [Complex::new(s, 0f64), Complex::zero(), Complex::zero(), Complex::new(s, 0f64)];
+    let observable = PauliSum::parse("X0 X1 - Y0 Y1 + 0.5 * Z0 Z1 + 2 * Z0").unwrap();
+    assert!((observable.expectation(&bell).unwrap() - 2.5f64).abs() < 1e-12);
+
+    // |+i> = (|0> + i|1>) / sqrt(2) is the +1 eigenstate of Y
+    let plus_i = [Complex::new(s, 0f64), Complex::new(0f64, s)];
+    assert!((PauliString::parse("Y0").unwrap().expectation(&plus_i).unwrap() - 1f64).abs() < 1e-12);
+    assert!(PauliString::parse("Z1").unwrap().expectation(&plus_i).is_err());
+    assert!(PauliString::new(1.0, vec![(64, Pauli::X)]).is_err());
+    assert!(PauliString::new(1.0, vec![(63, Pauli::X)]).unwrap().expectation(&plus_i).is_err());
+
+    // Must agree with the dense operator
+    let state = [Complex::new(0.1, 0.2), Complex::new(-0.3, 0.4), Complex::new(0.5, -0.1), Complex::new(0.2, 0.6)];
+    let observable = PauliSum::parse("0.7 * X0 Y1 - 1.3 * Y0 Z1 + 0.2 * Y0").unwrap();
+    let dense = observable.to_matrix(2);
+    assert!((observable.expectation(&state).unwrap() - dense.expectation(&state).unwrap()).abs() < 1e-12);
+
+    let y = PauliString::parse("Y0").unwrap().to_matrix(1);
+    assert_eq!(Complex::new(0f64, -1f64), *y.get(0, 1));
+    assert_eq!(Complex::new(0f64, 1f64), *y.get(1, 0));
+    assert_eq!(Complex::one(), *PauliString::parse("Z0").unwrap().to_matrix(1).get(0, 0));
+}
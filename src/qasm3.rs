@@ -0,0 +1,1222 @@
+//! OpenQASM 3 circuits.
+//!
+//! Parses the static subset of OpenQASM 3 and lowers it to the U and CX
+//! operations of a `Circuit`, the same list `UnitarySimulator::run()` consumes:
+//!
+//! ```ignore
+//! OPENQASM 3;
+//! include "stdgates.inc";
+//! qubit[3] q;
+//! bit[3] c;
+//! h q[0];
+//! for uint i in [0:1] { cx q[i], q[i + 1]; }
+//! ctrl @ rz(pi/4) q[0], q[2];
+//! c = measure q;
+//! ```
+//!
+//! Gate modifiers (`ctrl @`, `negctrl @`, `inv @` and `pow(k) @` with an integer
+//! |k| <= 1024) and `gphase` are expanded exactly, global phase included, as it becomes a
+//! relative phase once the gate is controlled. Everything only known at runtime
+//! (`if`, `while`, `input`, classical variables...) is refused with the line it
+//! appears on.
+
+use std::collections::HashMap;
+use std::f64::consts::{E, PI};
+use std::fmt;
+
+use serde_json;
+
+use circuit::Circuit;
+use expression::{BinaryOp, Expression, Function};
+
+/// The gates of "stdgates.inc", written with the U and gphase builtins. The
+/// phases follow the OpenQASM 3 definitions, so that `ctrl @ rz` and `crz` are
+/// the same gate.
+const STANDARD_GATES: &str = "
+gate p(λ) a { ctrl @ gphase(λ) a; }
+gate x a { U(π, 0, π) a; }
+gate y a { U(π, π/2, π/2) a; }
+gate z a { p(π) a; }
+gate h a { U(π/2, 0, π) a; }
+gate s a { p(π/2) a; }
+gate sdg a { p(-π/2) a; }
+gate t a { p(π/4) a; }
+gate tdg a { p(-π/4) a; }
+gate sx a { gphase(π/4); U(π/2, -π/2, π/2) a; }
+gate rx(θ) a { U(θ, -π/2, π/2) a; }
+gate ry(θ) a { U(θ, 0, 0) a; }
+gate rz(λ) a { gphase(-λ/2); U(0, 0, λ) a; }
+gate cx a, b { ctrl @ x a, b; }
+gate cy a, b { ctrl @ y a, b; }
+gate cz a, b { ctrl @ z a, b; }
+gate cp(λ) a, b { ctrl @ p(λ) a, b; }
+gate crx(θ) a, b { ctrl @ rx(θ) a, b; }
+gate cry(θ) a, b { ctrl @ ry(θ) a, b; }
+gate crz(θ) a, b { ctrl @ rz(θ) a, b; }
+gate ch a, b { ctrl @ h a, b; }
+gate swap a, b { cx a, b; cx b, a; cx a, b; }
+gate ccx a, b, c { ctrl @ ctrl @ x a, b, c; }
+gate cswap a, b, c { ctrl @ swap a, b, c; }
+gate CX a, b { ctrl @ U(π, 0, π) a, b; }
+gate phase(λ) a { U(0, 0, λ) a; }
+gate cphase(λ) a, b { ctrl @ phase(λ) a, b; }
+gate id a { U(0, 0, 0) a; }
+gate u1(λ) a { U(0, 0, λ) a; }
+gate u2(φ, λ) a { gphase(-(φ + λ + π/2)/2); U(π/2, φ, λ) a; }
+gate u3(θ, φ, λ) a { gphase(-(φ + λ + θ)/2); U(θ, φ, λ) a; }
+";
+
+/// Parses an OpenQASM 3 program into a circuit of U, CX, measure, reset and
+/// barrier operations.
+pub fn parse(source: &str) -> Result<Circuit, String> {
+    let statements = Parser::new(source)?.program()?;
+    let mut lowering = Lowering::new();
+    lowering.statements(&statements, &HashMap::new())?;
+    Ok(lowering.finish())
+}
+
+///
+/// Tokenizer
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(f64),
+    Str(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Identifier(ref name) => write!(f, "{}", name),
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Str(ref text) => write!(f, "\"{}\"", text),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+/// Largest |k| of `pow(k) @`, which repeats the gate |k| times.
+const MAX_POWER: f64 = 1024f64;
+
+/// Longest first, so that "->" is not read as "-" and ">".
+const SYMBOLS: [&str; 39] = [
+    "->", "**", "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "++", "+=", "-=", "*=", "/=",
+    ";", ",", "(", ")", "[", "]", "{", "}", ":", "=", "+", "-", "*", "/", "%", "@", "<", ">",
+    "!", "~", "^", "&", "|", ".",
+];
+
+fn error(line: usize, message: String) -> String {
+    format!("Error: line {}: {}!!", line, message)
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err(error(line, "unterminated comment".to_string()));
+            }
+            i += 2;
+        } else if c.is_ascii_digit() || (c == '.' && matches!(chars.get(i + 1), Some(c) if c.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let literal: String = chars[start..i].iter().collect();
+            // Durations (10ns) and imaginary numbers (2im) have a unit right after
+            if i < chars.len() && (chars[i].is_alphabetic() || chars[i] == '_') {
+                let end = (i..chars.len()).find(|&j| !chars[j].is_alphanumeric()).unwrap_or(chars.len());
+                let literal: String = chars[start..end].iter().collect();
+                return Err(error(line, format!("unsupported literal '{}'", literal)));
+            }
+            match literal.parse::<f64>() {
+                Ok(value) => tokens.push((Token::Number(value), line)),
+                Err(_) => return Err(error(line, format!("invalid number '{}'", literal))),
+            }
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Identifier(chars[start..i].iter().collect()), line));
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' && chars[i] != '\n' {
+                i += 1;
+            }
+            if i == chars.len() || chars[i] != '"' {
+                return Err(error(line, "unterminated string".to_string()));
+            }
+            tokens.push((Token::Str(chars[start..i].iter().collect()), line));
+            i += 1;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                Some(symbol) => {
+                    tokens.push((Token::Symbol(symbol), line));
+                    i += symbol.chars().count();
+                },
+                None => return Err(error(line, format!("unexpected character '{}'", c))),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+///
+/// Syntax tree
+///
+/// Expressions stay unevaluated until lowering, as loop variables and gate
+/// parameters take different values every time a statement is expanded.
+///
+#[derive(Debug, Clone)]
+struct Argument {
+    name: String,
+    index: Option<Expression>,
+}
+
+#[derive(Debug, Clone)]
+enum Modifier {
+    Ctrl(Expression),
+    NegCtrl(Expression),
+    Inv,
+    Pow(Expression),
+}
+
+#[derive(Debug, Clone)]
+enum LoopValues {
+    /// [start:step:end], the end included.
+    Range(Expression, Expression, Expression),
+    Set(Vec<Expression>),
+}
+
+#[derive(Debug, Clone)]
+struct GateDefinition {
+    params: Vec<String>,
+    qubits: Vec<String>,
+    body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Include(String),
+    /// `size` is `None` for a single qubit or bit.
+    Register { name: String, size: Option<Expression>, quantum: bool },
+    Const { name: String, value: Expression },
+    Gate { name: String, definition: GateDefinition },
+    Call { modifiers: Vec<Modifier>, name: String, params: Vec<Expression>, arguments: Vec<Argument> },
+    Measure { qubits: Argument, clbits: Argument },
+    Reset(Argument),
+    Barrier(Vec<Argument>),
+    For { variable: String, values: LoopValues, body: Vec<Statement> },
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    line: usize,
+    kind: Kind,
+}
+
+///
+/// Recursive descent parser.
+///
+/// Expressions follow the grammar of `expression::Parser` with `**` as the
+/// power operator:
+///
+/// expression := term (('+' | '-') term)*
+/// term       := unary (('*' | '/') unary)*
+/// unary      := ('-' | '+') unary | power
+/// power      := primary ('**' unary)?
+/// primary    := number | constant | identifier | function '(' expression ')' | '(' expression ')'
+///
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Parser, String> {
+        Ok(Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|token| &token.0)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.position + offset).map(|token| &token.0)
+    }
+
+    /// Line of the next token, or of the last one at the end of the source.
+    fn line(&self) -> usize {
+        match self.tokens.get(self.position).or_else(|| self.tokens.last()) {
+            Some(token) => token.1,
+            None => 1,
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn fail<T>(&self, message: String) -> Result<T, String> {
+        Err(error(self.line(), message))
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, String> {
+        match self.peek() {
+            Some(token) => self.fail(format!("expected {}, found '{}'", expected, token)),
+            None => self.fail(format!("expected {}, found the end of the program", expected)),
+        }
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Symbol(found)) => found == symbol,
+            _ => false,
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Identifier(found)) => found == keyword,
+            _ => false,
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("'{}'", symbol))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Identifier(name)) if name.starts_with('$') =>
+                return self.fail(format!("physical qubits like '{}' are not supported", name)),
+            Some(&Token::Identifier(_)) => {},
+            _ => return self.unexpected("an identifier"),
+        }
+        match self.next() {
+            Some(Token::Identifier(name)) => Ok(name),
+            _ => unreachable!(),
+        }
+    }
+
+    fn program(&mut self) -> Result<Vec<Statement>, String> {
+        if self.is_keyword("OPENQASM") {
+            self.position += 1;
+            match self.next() {
+                Some(Token::Number(version)) if (3f64..4f64).contains(&version) => {},
+                Some(token) => return Err(error(self.tokens[self.position - 1].1,
+                                                format!("OpenQASM version {} is not supported, only 3", token))),
+                None => return self.unexpected("a version"),
+            }
+            self.expect_symbol(";")?;
+        }
+        let mut statements = Vec::new();
+        while self.peek().is_some() {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    /// `{ statements }`, or a single statement.
+    fn block(&mut self) -> Result<Vec<Statement>, String> {
+        if !self.eat_symbol("{") {
+            return Ok(vec![self.statement()?]);
+        }
+        let mut statements = Vec::new();
+        while !self.eat_symbol("}") {
+            if self.peek().is_none() {
+                return self.unexpected("'}'");
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, String> {
+        let line = self.line();
+        let keyword = match self.peek() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return self.unexpected("a statement"),
+        };
+        let kind = match keyword.as_str() {
+            "OPENQASM" => return self.fail("the OPENQASM version must come first".to_string()),
+            "include" => {
+                self.position += 1;
+                let file = match self.next() {
+                    Some(Token::Str(file)) => file,
+                    _ => return Err(error(line, "expected the file name to include".to_string())),
+                };
+                self.expect_symbol(";")?;
+                Kind::Include(file)
+            },
+            "qubit" | "bit" => {
+                self.position += 1;
+                let size = if self.eat_symbol("[") {
+                    let size = self.expression()?;
+                    self.expect_symbol("]")?;
+                    Some(size)
+                } else {
+                    None
+                };
+                let name = self.identifier()?;
+                if self.is_symbol("=") {
+                    return self.fail("declarations with an initial value are not supported".to_string());
+                }
+                self.expect_symbol(";")?;
+                Kind::Register { name: name, size: size, quantum: keyword == "qubit" }
+            },
+            "qreg" | "creg" => {
+                self.position += 1;
+                let name = self.identifier()?;
+                let size = if self.eat_symbol("[") {
+                    let size = self.expression()?;
+                    self.expect_symbol("]")?;
+                    Some(size)
+                } else {
+                    None
+                };
+                self.expect_symbol(";")?;
+                Kind::Register { name: name, size: size, quantum: keyword == "qreg" }
+            },
+            "const" => {
+                self.position += 1;
+                match self.identifier()?.as_str() {
+                    "int" | "uint" | "float" | "angle" => {},
+                    other => return Err(error(line, format!("constants of type '{}' are not supported", other))),
+                }
+                if self.eat_symbol("[") {
+                    self.expression()?;
+                    self.expect_symbol("]")?;
+                }
+                let name = self.identifier()?;
+                self.expect_symbol("=")?;
+                let value = self.expression()?;
+                self.expect_symbol(";")?;
+                Kind::Const { name: name, value: value }
+            },
+            "gate" => {
+                self.position += 1;
+                let name = self.identifier()?;
+                let mut params = Vec::new();
+                if self.eat_symbol("(") && !self.eat_symbol(")") {
+                    loop {
+                        params.push(self.identifier()?);
+                        if self.eat_symbol(")") {
+                            break;
+                        }
+                        self.expect_symbol(",")?;
+                    }
+                }
+                let mut qubits = vec![self.identifier()?];
+                while self.eat_symbol(",") {
+                    qubits.push(self.identifier()?);
+                }
+                if !self.is_symbol("{") {
+                    return self.unexpected("'{'");
+                }
+                let body = self.block()?;
+                Kind::Gate { name: name, definition: GateDefinition { params: params, qubits: qubits, body: body } }
+            },
+            "for" => {
+                self.position += 1;
+                // The type of the loop variable is optional, like in "for uint i in"
+                match self.peek_at(1) {
+                    Some(Token::Symbol("[")) => return self.fail("sized loop variables are not supported".to_string()),
+                    Some(Token::Identifier(name)) if name != "in" => self.position += 1,
+                    _ => (),
+                }
+                let variable = self.identifier()?;
+                if !self.is_keyword("in") {
+                    return self.unexpected("'in'");
+                }
+                self.position += 1;
+                let values = if self.eat_symbol("[") {
+                    let start = self.expression()?;
+                    self.expect_symbol(":")?;
+                    let mut end = self.expression()?;
+                    let mut step = Expression::Number(1f64);
+                    if self.eat_symbol(":") {
+                        step = end;
+                        end = self.expression()?;
+                    }
+                    self.expect_symbol("]")?;
+                    LoopValues::Range(start, step, end)
+                } else if self.eat_symbol("{") {
+                    let mut values = vec![self.expression()?];
+                    while self.eat_symbol(",") {
+                        values.push(self.expression()?);
+                    }
+                    self.expect_symbol("}")?;
+                    LoopValues::Set(values)
+                } else {
+                    return self.fail("loops can only run over constant ranges [start:end] or sets {a, b}".to_string());
+                };
+                Kind::For { variable: variable, values: values, body: self.block()? }
+            },
+            "measure" => {
+                self.position += 1;
+                let qubits = self.argument()?;
+                if !self.eat_symbol("->") {
+                    return self.fail("measurements must store their result, as in 'c = measure q;'".to_string());
+                }
+                let clbits = self.argument()?;
+                self.expect_symbol(";")?;
+                Kind::Measure { qubits: qubits, clbits: clbits }
+            },
+            "reset" => {
+                self.position += 1;
+                let qubits = self.argument()?;
+                self.expect_symbol(";")?;
+                Kind::Reset(qubits)
+            },
+            "barrier" => {
+                self.position += 1;
+                let mut arguments = Vec::new();
+                if !self.eat_symbol(";") {
+                    arguments.push(self.argument()?);
+                    while self.eat_symbol(",") {
+                        arguments.push(self.argument()?);
+                    }
+                    self.expect_symbol(";")?;
+                }
+                Kind::Barrier(arguments)
+            },
+            "if" | "else" | "while" | "switch" | "break" | "continue" | "end" =>
+                return self.fail(format!("'{}' depends on values only known at runtime, only static circuits are supported", keyword)),
+            "input" | "output" =>
+                return self.fail(format!("'{}' parameters are not supported, declare them 'const'", keyword)),
+            "int" | "uint" | "float" | "angle" | "bool" | "complex" | "duration" | "stretch" | "array" | "let" =>
+                return self.fail(format!("classical variables ('{}') are not supported, declare them 'const'", keyword)),
+            "def" | "return" | "extern" =>
+                return self.fail(format!("subroutines ('{}') are not supported, use 'gate' definitions", keyword)),
+            "delay" | "box" | "cal" | "defcal" | "defcalgrammar" | "durationof" =>
+                return self.fail(format!("timing and calibrations ('{}') are not supported", keyword)),
+            _ if self.is_assignment() => {
+                let clbits = self.argument()?;
+                self.expect_symbol("=")?;
+                if !self.is_keyword("measure") {
+                    return Err(error(line, "classical assignments are not supported, only 'c = measure q;'".to_string()));
+                }
+                self.position += 1;
+                let qubits = self.argument()?;
+                self.expect_symbol(";")?;
+                Kind::Measure { qubits: qubits, clbits: clbits }
+            },
+            _ => self.call()?,
+        };
+        Ok(Statement { line: line, kind: kind })
+    }
+
+    /// `c = ...` or `c[i] = ...`, as gate calls never have a '[' after the name.
+    fn is_assignment(&self) -> bool {
+        match self.peek_at(1) {
+            Some(&Token::Symbol(symbol)) => symbol == "[" || symbol.ends_with('='),
+            _ => false,
+        }
+    }
+
+    fn call(&mut self) -> Result<Kind, String> {
+        let mut modifiers = Vec::new();
+        loop {
+            let keyword = match self.peek() {
+                Some(Token::Identifier(name)) if ["ctrl", "negctrl", "inv", "pow"].contains(&name.as_str()) => name.clone(),
+                _ => break,
+            };
+            self.position += 1;
+            let argument = if self.eat_symbol("(") {
+                let argument = self.expression()?;
+                self.expect_symbol(")")?;
+                Some(argument)
+            } else {
+                None
+            };
+            let count = || argument.clone().unwrap_or(Expression::Number(1f64));
+            modifiers.push(match keyword.as_str() {
+                "ctrl" => Modifier::Ctrl(count()),
+                "negctrl" => Modifier::NegCtrl(count()),
+                "inv" if argument.is_none() => Modifier::Inv,
+                "pow" if argument.is_some() => Modifier::Pow(count()),
+                _ => return self.fail(format!("wrong arguments for the '{}' modifier", keyword)),
+            });
+            self.expect_symbol("@")?;
+        }
+
+        let name = self.identifier()?;
+        let mut params = Vec::new();
+        if self.eat_symbol("(") && !self.eat_symbol(")") {
+            loop {
+                params.push(self.expression()?);
+                if self.eat_symbol(")") {
+                    break;
+                }
+                self.expect_symbol(",")?;
+            }
+        }
+        let mut arguments = Vec::new();
+        if !self.is_symbol(";") {
+            arguments.push(self.argument()?);
+            while self.eat_symbol(",") {
+                arguments.push(self.argument()?);
+            }
+        }
+        self.expect_symbol(";")?;
+        Ok(Kind::Call { modifiers: modifiers, name: name, params: params, arguments: arguments })
+    }
+
+    /// `q` or `q[index]`.
+    fn argument(&mut self) -> Result<Argument, String> {
+        let name = self.identifier()?;
+        let mut index = None;
+        if self.eat_symbol("[") {
+            index = Some(self.expression()?);
+            if self.is_symbol(":") || self.is_symbol(",") {
+                return self.fail(format!("slices of '{}' are not supported, index single bits", name));
+            }
+            self.expect_symbol("]")?;
+        }
+        Ok(Argument { name: name, index: index })
+    }
+
+    fn expression(&mut self) -> Result<Expression, String> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                BinaryOp::Add
+            } else if self.eat_symbol("-") {
+                BinaryOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.term()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expression, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                BinaryOp::Mul
+            } else if self.eat_symbol("/") {
+                BinaryOp::Div
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.unary()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.eat_symbol("-") {
+            Ok(Expression::Neg(Box::new(self.unary()?)))
+        } else if self.eat_symbol("+") {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Result<Expression, String> {
+        let base = self.primary()?;
+        if self.eat_symbol("**") {
+            let exponent = self.unary()?;
+            return Ok(Expression::Binary(BinaryOp::Pow, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        match self.peek().cloned() {
+            Some(Token::Number(value)) => {
+                self.position += 1;
+                Ok(Expression::Number(value))
+            },
+            Some(Token::Symbol("(")) => {
+                self.position += 1;
+                let inner = self.expression()?;
+                self.expect_symbol(")")?;
+                Ok(inner)
+            },
+            Some(Token::Identifier(_)) => {
+                let name = self.identifier()?;
+                let function = match name.as_str() {
+                    "pi" | "π" => return Ok(Expression::Pi),
+                    "tau" | "τ" => return Ok(Expression::Binary(BinaryOp::Mul, Box::new(Expression::Number(2f64)),
+                                                                Box::new(Expression::Pi))),
+                    "euler" | "ℯ" => return Ok(Expression::Number(E)),
+                    "measure" => return self.fail("measurements can only be assigned to bits".to_string()),
+                    "sin" => Function::Sin,
+                    "cos" => Function::Cos,
+                    "tan" => Function::Tan,
+                    "exp" => Function::Exp,
+                    "ln" | "log" => Function::Ln,
+                    "sqrt" => Function::Sqrt,
+                    _ => return Ok(Expression::Parameter(name)),
+                };
+                self.expect_symbol("(")?;
+                let argument = self.expression()?;
+                self.expect_symbol(")")?;
+                Ok(Expression::Call(function, Box::new(argument)))
+            },
+            _ => self.unexpected("an expression"),
+        }
+    }
+}
+
+///
+/// Lowering to U and CX
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    /// U(theta, phi, lam) on a qubit.
+    U(usize, f64, f64, f64),
+    CX(usize, usize),
+    /// Global phase e^{i gamma}.
+    Phase(f64),
+}
+
+fn is_x(theta: f64, phi: f64, lam: f64) -> bool {
+    (theta - PI).abs() < 1e-12 && phi.abs() < 1e-12 && (lam - PI).abs() < 1e-12
+}
+
+fn inverse(ops: &[Op]) -> Vec<Op> {
+    ops.iter().rev().map(|op| match *op {
+        Op::U(qubit, theta, phi, lam) => Op::U(qubit, -theta, -lam, -phi),
+        Op::CX(control, target) => Op::CX(control, target),
+        Op::Phase(gamma) => Op::Phase(-gamma),
+    }).collect()
+}
+
+/// Toffoli gate from the qelib1.inc definition.
+fn toffoli(a: usize, b: usize, target: usize) -> Vec<Op> {
+    let h = |qubit| Op::U(qubit, PI / 2f64, 0f64, PI);
+    let t = |qubit| Op::U(qubit, 0f64, 0f64, PI / 4f64);
+    let tdg = |qubit| Op::U(qubit, 0f64, 0f64, -PI / 4f64);
+    vec![h(target), Op::CX(b, target), tdg(target), Op::CX(a, target), t(target), Op::CX(b, target),
+         tdg(target), Op::CX(a, target), t(b), t(target), h(target), Op::CX(a, b), t(a), tdg(b), Op::CX(a, b)]
+}
+
+/// The operations controlled by `control`, which none of them may act on.
+fn controlled(ops: &[Op], control: usize) -> Vec<Op> {
+    let mut result = Vec::new();
+    for op in ops.iter() {
+        match *op {
+            Op::U(target, theta, phi, lam) if is_x(theta, phi, lam) => result.push(Op::CX(control, target)),
+            // cu3 of qelib1.inc, which keeps the phase of U
+            Op::U(target, theta, phi, lam) => result.extend_from_slice(&[
+                Op::U(control, 0f64, 0f64, (lam + phi) / 2f64),
+                Op::U(target, 0f64, 0f64, (lam - phi) / 2f64),
+                Op::CX(control, target),
+                Op::U(target, -theta / 2f64, 0f64, -(phi + lam) / 2f64),
+                Op::CX(control, target),
+                Op::U(target, theta / 2f64, phi, 0f64),
+            ]),
+            Op::CX(a, target) => result.extend(toffoli(control, a, target)),
+            Op::Phase(gamma) => result.push(Op::U(control, 0f64, 0f64, gamma)),
+        }
+    }
+    result
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Register {
+    quantum: bool,
+    offset: usize,
+    size: usize,
+}
+
+struct Lowering {
+    qubit_labels: Vec<(String, usize)>,
+    clbit_labels: Vec<(String, usize)>,
+    registers: HashMap<String, Register>,
+    constants: HashMap<String, f64>,
+    gates: HashMap<String, GateDefinition>,
+    /// Whether "stdgates.inc" was included.
+    standard: bool,
+    operations: Vec<serde_json::Value>,
+    /// Global phase, applied at the end.
+    phase: f64,
+}
+
+impl Lowering {
+    fn new() -> Lowering {
+        Lowering {
+            qubit_labels: Vec::new(),
+            clbit_labels: Vec::new(),
+            registers: HashMap::new(),
+            constants: HashMap::new(),
+            gates: HashMap::new(),
+            standard: false,
+            operations: Vec::new(),
+            phase: 0f64,
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.registers.contains_key(name) || self.constants.contains_key(name) || self.gates.contains_key(name)
+    }
+
+    /// Evaluates an expression with the constants and `scope` (loop variables or
+    /// gate parameters).
+    fn eval(&self, line: usize, expression: &Expression, scope: &HashMap<String, f64>) -> Result<f64, String> {
+        let mut values = self.constants.clone();
+        values.extend(scope.iter().map(|(name, value)| (name.clone(), *value)));
+        if let Some(name) = expression.parameters().into_iter().find(|name| !values.contains_key(name)) {
+            return Err(error(line, format!("'{}' is not a constant, only values known before running are supported", name)));
+        }
+        expression.eval(&values).map_err(|err| error(line, err))
+    }
+
+    fn eval_integer(&self, line: usize, expression: &Expression, scope: &HashMap<String, f64>) -> Result<i64, String> {
+        let value = self.eval(line, expression, scope)?;
+        if value.fract() != 0f64 || !value.is_finite() {
+            return Err(error(line, format!("expected an integer, found {}", value)));
+        }
+        Ok(value as i64)
+    }
+
+    /// The bits `argument` refers to, all of the register without an index.
+    fn resolve(&self, line: usize, argument: &Argument, quantum: bool, scope: &HashMap<String, f64>) -> Result<Vec<usize>, String> {
+        let register = match self.registers.get(&argument.name) {
+            Some(register) if register.quantum == quantum => *register,
+            Some(_) if quantum => return Err(error(line, format!("'{}' is not a qubit register", argument.name))),
+            Some(_) => return Err(error(line, format!("'{}' is not a bit register", argument.name))),
+            None => return Err(error(line, format!("'{}' is not declared", argument.name))),
+        };
+        match argument.index {
+            None => Ok((register.offset..register.offset + register.size).collect()),
+            Some(ref index) => {
+                let index = self.eval_integer(line, index, scope)?;
+                // Negative indices count from the end
+                let position = if index < 0 { register.size as i64 + index } else { index };
+                if position < 0 || position >= register.size as i64 {
+                    return Err(error(line, format!("index {} is out of range for '{}' of size {}",
+                                                   index, argument.name, register.size)));
+                }
+                Ok(vec![register.offset + position as usize])
+            },
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement], scope: &HashMap<String, f64>) -> Result<(), String> {
+        for statement in statements.iter() {
+            self.statement(statement, scope)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement, scope: &HashMap<String, f64>) -> Result<(), String> {
+        let line = statement.line;
+        match statement.kind {
+            Kind::Include(ref file) => {
+                if file != "stdgates.inc" {
+                    return Err(error(line, format!("cannot include '{}', only \"stdgates.inc\" is available", file)));
+                }
+                if !self.standard {
+                    let standard = Parser::new(STANDARD_GATES)?.program()?;
+                    self.statements(&standard, scope)?;
+                    self.standard = true;
+                }
+            },
+            Kind::Register { ref name, ref size, quantum } => {
+                if !scope.is_empty() {
+                    return Err(error(line, "registers can only be declared in the global scope".to_string()));
+                }
+                if self.is_defined(name) {
+                    return Err(error(line, format!("'{}' is already declared", name)));
+                }
+                let size = match *size {
+                    Some(ref size) => self.eval_integer(line, size, scope)?,
+                    None => 1,
+                };
+                if size < 1 {
+                    return Err(error(line, format!("'{}' must have at least one bit", name)));
+                }
+                let labels = if quantum { &mut self.qubit_labels } else { &mut self.clbit_labels };
+                let register = Register { quantum: quantum, offset: labels.len(), size: size as usize };
+                labels.extend((0..register.size).map(|index| (name.clone(), index)));
+                self.registers.insert(name.clone(), register);
+            },
+            Kind::Const { ref name, ref value } => {
+                if self.is_defined(name) || scope.contains_key(name) {
+                    return Err(error(line, format!("'{}' is already declared", name)));
+                }
+                let value = self.eval(line, value, scope)?;
+                self.constants.insert(name.clone(), value);
+            },
+            Kind::Gate { ref name, ref definition } => {
+                if self.is_defined(name) || name == "U" || name == "gphase" {
+                    return Err(error(line, format!("'{}' is already declared", name)));
+                }
+                self.check_definition(definition)?;
+                self.gates.insert(name.clone(), definition.clone());
+            },
+            Kind::Call { ref modifiers, ref name, ref params, ref arguments } => {
+                let params = params.iter().map(|param| self.eval(line, param, scope)).collect::<Result<Vec<_>, _>>()?;
+                let qubits = arguments.iter().map(|argument| self.resolve(line, argument, true, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // Registers apply the gate to each of their qubits in turn
+                let count = qubits.iter().map(|qubits| qubits.len()).max().unwrap_or(1);
+                if qubits.iter().any(|qubits| qubits.len() != 1 && qubits.len() != count) {
+                    return Err(error(line, format!("registers of different sizes given to '{}'", name)));
+                }
+                for instance in 0..count {
+                    let qubits : Vec<usize> = qubits.iter().map(|qubits| qubits[instance % qubits.len()]).collect();
+                    let ops = self.gate(line, modifiers, name, &params, &qubits, scope)?;
+                    self.emit(&ops);
+                }
+            },
+            Kind::Measure { ref qubits, ref clbits } => {
+                let qubits = self.resolve(line, qubits, true, scope)?;
+                let clbits = self.resolve(line, clbits, false, scope)?;
+                if qubits.len() != clbits.len() {
+                    return Err(error(line, format!("measuring {} qubits into {} bits", qubits.len(), clbits.len())));
+                }
+                for (qubit, clbit) in qubits.into_iter().zip(clbits) {
+                    self.operations.push(json!({"name": "measure", "qubits": [qubit], "clbits": [clbit]}));
+                }
+            },
+            Kind::Reset(ref qubits) => {
+                for qubit in self.resolve(line, qubits, true, scope)? {
+                    self.operations.push(json!({"name": "reset", "qubits": [qubit]}));
+                }
+            },
+            Kind::Barrier(ref arguments) => {
+                let mut qubits = Vec::new();
+                for argument in arguments.iter() {
+                    qubits.extend(self.resolve(line, argument, true, scope)?);
+                }
+                if arguments.is_empty() {
+                    qubits = (0..self.qubit_labels.len()).collect();
+                }
+                self.operations.push(json!({"name": "barrier", "qubits": qubits}));
+            },
+            Kind::For { ref variable, ref values, ref body } => {
+                let values = match *values {
+                    LoopValues::Range(ref start, ref step, ref end) => {
+                        let start = self.eval_integer(line, start, scope)?;
+                        let step = self.eval_integer(line, step, scope)?;
+                        let end = self.eval_integer(line, end, scope)?;
+                        if step == 0 {
+                            return Err(error(line, "the loop step cannot be 0".to_string()));
+                        }
+                        let mut values = Vec::new();
+                        let mut value = start;
+                        while (step > 0 && value <= end) || (step < 0 && value >= end) {
+                            values.push(value as f64);
+                            value += step;
+                        }
+                        values
+                    },
+                    LoopValues::Set(ref values) => values.iter().map(|value| self.eval(line, value, scope))
+                        .collect::<Result<Vec<_>, _>>()?,
+                };
+                let mut scope = scope.clone();
+                for value in values {
+                    scope.insert(variable.clone(), value);
+                    self.statements(body, &scope)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Gate bodies can only apply gates defined before them to their own qubits,
+    /// which also rules out recursion.
+    fn check_definition(&self, definition: &GateDefinition) -> Result<(), String> {
+        for statement in definition.body.iter() {
+            let line = statement.line;
+            match statement.kind {
+                Kind::Call { ref name, ref arguments, .. } => {
+                    if name != "U" && name != "gphase" && !self.gates.contains_key(name) {
+                        return Err(error(line, format!("unknown gate '{}'", name)));
+                    }
+                    for argument in arguments.iter() {
+                        if argument.index.is_some() || !definition.qubits.contains(&argument.name) {
+                            return Err(error(line, format!("gates can only act on their qubit arguments, not '{}'",
+                                                           argument.name)));
+                        }
+                    }
+                },
+                _ => return Err(error(line, "gate bodies can only apply gates".to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    fn gate(&self, line: usize, modifiers: &[Modifier], name: &str, params: &[f64], qubits: &[usize],
+            scope: &HashMap<String, f64>) -> Result<Vec<Op>, String> {
+        if let Some(qubit) = qubits.iter().find(|&qubit| qubits.iter().filter(|&other| other == qubit).count() > 1) {
+            return Err(error(line, format!("qubit {} is used twice by '{}'", qubit, name)));
+        }
+        let mut counts = Vec::new();
+        for modifier in modifiers.iter() {
+            counts.push(match *modifier {
+                Modifier::Ctrl(ref count) | Modifier::NegCtrl(ref count) => {
+                    let count = self.eval_integer(line, count, scope)?;
+                    if count < 1 {
+                        return Err(error(line, "gates need at least one control".to_string()));
+                    }
+                    count as usize
+                },
+                _ => 0,
+            });
+        }
+        let controls : usize = counts.iter().sum();
+        if qubits.len() < controls {
+            return Err(error(line, format!("'{}' needs {} control qubits", name, controls)));
+        }
+
+        let mut ops = self.base_gate(line, name, params, &qubits[controls..])?;
+        // Modifiers apply from the gate outwards, and take the control qubits in order
+        let mut end = controls;
+        for (modifier, count) in modifiers.iter().zip(counts).rev() {
+            let start = end - count;
+            match *modifier {
+                Modifier::Inv => ops = inverse(&ops),
+                Modifier::Pow(ref exponent) => {
+                    let exponent = self.eval(line, exponent, scope)?;
+                    if exponent.fract() != 0f64 {
+                        return Err(error(line, format!("only integer powers of gates are supported, not {}", exponent)));
+                    }
+                    if exponent.abs() > MAX_POWER {
+                        return Err(error(line, format!("powers of gates are limited to {}, not {}", MAX_POWER, exponent)));
+                    }
+                    let base = if exponent < 0f64 { inverse(&ops) } else { ops };
+                    ops = (0..exponent.abs() as usize).flat_map(|_| base.iter().cloned()).collect();
+                },
+                Modifier::Ctrl(_) => for &control in qubits[start..end].iter().rev() {
+                    ops = controlled(&ops, control);
+                },
+                Modifier::NegCtrl(_) => for &control in qubits[start..end].iter().rev() {
+                    let x = Op::U(control, PI, 0f64, PI);
+                    let mut negated = vec![x];
+                    negated.extend(controlled(&ops, control));
+                    negated.push(x);
+                    ops = negated;
+                },
+            }
+            end = start;
+        }
+        Ok(ops)
+    }
+
+    fn base_gate(&self, line: usize, name: &str, params: &[f64], qubits: &[usize]) -> Result<Vec<Op>, String> {
+        let arity = |expected_params: usize, expected_qubits: usize| if params.len() != expected_params || qubits.len() != expected_qubits {
+            Err(error(line, format!("'{}' takes {} parameters and {} qubits, got {} and {}",
+                                    name, expected_params, expected_qubits, params.len(), qubits.len())))
+        } else {
+            Ok(())
+        };
+        match name {
+            "U" => {
+                arity(3, 1)?;
+                Ok(vec![Op::U(qubits[0], params[0], params[1], params[2])])
+            },
+            "gphase" => {
+                arity(1, 0)?;
+                Ok(vec![Op::Phase(params[0])])
+            },
+            "cx" | "CX" if self.standard => {
+                arity(0, 2)?;
+                Ok(vec![Op::CX(qubits[0], qubits[1])])
+            },
+            _ => {
+                let definition = match self.gates.get(name) {
+                    Some(definition) => definition,
+                    None if self.standard => return Err(error(line, format!("unknown gate '{}'", name))),
+                    None => return Err(error(line, format!("unknown gate '{}' (missing include \"stdgates.inc\"?)", name))),
+                };
+                arity(definition.params.len(), definition.qubits.len())?;
+                let scope : HashMap<String, f64> = definition.params.iter().cloned().zip(params.iter().cloned()).collect();
+                let mut ops = Vec::new();
+                for statement in definition.body.iter() {
+                    if let Kind::Call { ref modifiers, ref name, ref params, ref arguments } = statement.kind {
+                        let params = params.iter().map(|param| self.eval(statement.line, param, &scope))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let qubits : Vec<usize> = arguments.iter()
+                            .map(|argument| qubits[definition.qubits.iter().position(|qubit| *qubit == argument.name).unwrap()])
+                            .collect();
+                        ops.extend(self.gate(statement.line, modifiers, name, &params, &qubits, &scope)?);
+                    }
+                }
+                Ok(ops)
+            },
+        }
+    }
+
+    fn emit(&mut self, ops: &[Op]) {
+        for op in ops.iter() {
+            match *op {
+                Op::U(qubit, theta, phi, lam) =>
+                    self.operations.push(json!({"name": "U", "qubits": [qubit], "params": [theta, phi, lam]})),
+                Op::CX(control, target) => self.operations.push(json!({"name": "CX", "qubits": [control, target]})),
+                Op::Phase(gamma) => self.phase += gamma,
+            }
+        }
+    }
+
+    fn finish(mut self) -> Circuit {
+        // e^{i gamma} I = X P(gamma) X P(gamma), on any qubit
+        let phase = self.phase - 2f64 * PI * (self.phase / (2f64 * PI)).round();
+        if phase.abs() > 1e-12 && !self.qubit_labels.is_empty() {
+            let p = Op::U(0, 0f64, 0f64, phase);
+            let x = Op::U(0, PI, 0f64, PI);
+            self.emit(&[p, x, p, x]);
+        }
+        Circuit {
+            number_of_qubits: self.qubit_labels.len(),
+            number_of_clbits: self.clbit_labels.len(),
+            operations: self.operations,
+            qubit_labels: self.qubit_labels,
+            clbit_labels: self.clbit_labels,
+            config: serde_json::Value::Null,
+        }
+    }
+}
+
+
+#[test]
+fn qasm3_test() {
+    use complex::Complex;
+    use gate::Gate;
+    use matrix::Matrix;
+    use stabilizer::sample_counts;
+    use UnitarySimulator;
+
+    let unitary = |source: &str| -> Matrix<Complex> {
+        let circuit = parse(&format!("OPENQASM 3.0;\ninclude \"stdgates.inc\";\n{}", source)).unwrap();
+        let mut simulator = UnitarySimulator::from_circuit(&circuit).unwrap();
+        simulator.run().unwrap().unitary().unwrap()
+    };
+
+    let circuit = parse("OPENQASM 3;
+        include \"stdgates.inc\";
+        qubit[2] q;
+        bit[2] c;
+        h q[0];
+        cx q[0], q[1];
+        c = measure q;").unwrap();
+    assert_eq!(2, circuit.number_of_clbits);
+    assert_eq!(vec![("q".to_string(), 0), ("q".to_string(), 1)], circuit.qubit_labels);
+    assert_eq!(json!({"name": "CX", "qubits": [0, 1]}), circuit.operations[1]);
+    assert_eq!(json!({"name": "measure", "qubits": [1], "clbits": [1]}), circuit.operations[3]);
+    let counts = sample_counts(circuit.to_json(), 100, 7).unwrap();
+    assert!(counts.counts().unwrap().keys().all(|key| key == "00" || key == "11"));
+
+    // Loops, constants, broadcasting and gate definitions
+    let circuit = parse("include \"stdgates.inc\";
+        const int n = 4;
+        qubit[n] q;
+        gate entangle(θ) a, b { ry(θ) a; cx a, b; }
+        for uint i in [0:n - 2] { entangle(pi / (i + 1)) q[i], q[i + 1]; }
+        for i in {0, 3} { reset q[i]; }
+        h q;
+        barrier;").unwrap();
+    assert_eq!(3 * 2 + 2 + 4 + 1, circuit.operations.len());
+    assert_eq!(json!([PI / 2f64, 0f64, 0f64]), circuit.operations[2]["params"]);
+    assert_eq!(json!({"name": "reset", "qubits": [3]}), circuit.operations[7]);
+    assert_eq!(json!([0, 1, 2, 3]), circuit.operations[12]["qubits"]);
+    assert_eq!(3, parse("qubit[3] q; for i in [4:-2:0] U(0, 0, i) q[i / 2];").unwrap().operations.len());
+
+    // Modifiers keep the phases exactly
+    let (theta, phi, lam) = (0.3f64, 0.7f64, -1.1f64);
+    let u = Gate::<Complex>::u(theta, phi, lam).matrix;
+    let mut expected = Matrix::<Complex>::identity(4);
+    for &(i, j) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter() {
+        expected.set(2 * i + 1, 2 * j + 1, u.get(i, j));
+    }
+    assert!(unitary("qubit[2] q; ctrl @ U(0.3, 0.7, -1.1) q[0], q[1];").approx_eq(&expected));
+    let rz = unitary("qubit[2] q; ctrl @ rz(0.3) q[0], q[1];");
+    assert!((*rz.get(1, 1) - c!(0f64, -0.15f64).exp()).norm_sqr() < 1e-24);
+    assert!((*rz.get(3, 3) - c!(0f64, 0.15f64).exp()).norm_sqr() < 1e-24);
+    assert!((*rz.get(0, 0) - c!(1f64, 0f64)).norm_sqr() < 1e-24);
+    assert!(unitary("qubit q; inv @ s q;").approx_eq(&unitary("qubit q; sdg q;")));
+    assert!(unitary("qubit q; pow(3) @ t q;").approx_eq(&unitary("qubit q; p(3 * pi / 4) q;")));
+    assert!(unitary("qubit q; pow(-2) @ t q;").approx_eq(&unitary("qubit q; s q; inv @ z q;")));
+    assert!(unitary("qubit[2] q; negctrl @ x q[0], q[1];")
+            .approx_eq(&unitary("qubit[2] q; x q[0]; cx q[0], q[1]; x q[0];")));
+    assert!(unitary("qubit q; gphase(pi / 2);").approx_eq(&Matrix::<Complex>::identity(2).scale(c!(0f64, 1f64))));
+    let toffoli = unitary("qubit[3] q; ctrl(2) @ x q[0], q[1], q[2];");
+    for column in 0..8 {
+        let row = if column & 3 == 3 { column ^ 4 } else { column };
+        assert!((*toffoli.get(row, column) - c!(1f64, 0f64)).norm_sqr() < 1e-24);
+    }
+    assert!(toffoli.approx_eq(&unitary("qubit[3] q; ccx q[0], q[1], q[2];")));
+
+    // Dynamic features and mistakes are reported with their line
+    let error = |source: &str| parse(source).unwrap_err();
+    assert!(error("qubit q;\nbit c;\nc = measure q;\nif (c == 1) U(pi, 0, pi) q;").contains("line 4"));
+    assert!(error("input float theta;").contains("'input'"));
+    assert!(error("float theta = 0.5;").contains("const"));
+    assert!(error("qubit q;\nh q;").contains("stdgates.inc"));
+    assert!(error("include \"stdgates.inc\";\nqubit q;\npow(0.5) @ x q;").contains("integer powers"));
+    assert!(error("include \"stdgates.inc\";\nqubit q;\npow(1e12) @ x q;").contains("limited to 1024"));
+    assert!(error("include \"stdgates.inc\";\nqubit q;\npow(-1e30) @ x q;").contains("limited to 1024"));
+    assert!(error("include \"stdgates.inc\";\nqubit[2] q;\nx q[2];").contains("out of range"));
+    assert!(error("include \"stdgates.inc\";\nqubit[2] q;\ncx q[0], q[0];").contains("twice"));
+    assert!(error("qubit q;\nbit c;\nc = 1;").contains("classical assignments"));
+    assert!(error("qubit[2] q;\nU(theta, 0, 0) q[0];").contains("'theta'"));
+    assert!(error("include \"stdgates.inc\";\nx $0;").contains("physical qubits"));
+    assert!(error("qubit q;\ndelay[10ns] q;").contains("line 2"));
+    assert!(error("OPENQASM 2.0;").contains("version"));
+    assert!(error("qubit q;\nfor int[32] i in [0:2] {\n  U(i, 0, 0) q;\n}").contains("sized loop variables are not supported"));
+}
@@ -0,0 +1,152 @@
+//! Simulation results.
+//!
+//! Serializing a `SimulationResult` gives the JSON the simulators always
+//! returned, {"status": "DONE", "data": {"unitary": [...]}, "result": {}} or
+//! {"status": "DONE", "data": {"counts": {"01": 10}}, "result": {}}, with the
+//! optional fields only present when they are set. Failed simulations keep
+//! their {"unitary": {}} placeholder in "data".
+
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use serde::ser::{Error, Serialize, SerializeMap, Serializer};
+use serde_json;
+
+use complex::Complex;
+use matrix::Matrix;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Status {
+    #[serde(rename = "DONE")]
+    Done,
+    #[serde(rename = "ERROR")]
+    Error,
+}
+
+/// What the simulation computed, depending on the simulator.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct ResultData {
+    /// Row-major unitary, in the configured endianness.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unitary: Option<Vec<Complex>>,
+    /// Number of shots of every measured bitstring.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counts: Option<BTreeMap<String, usize>>,
+    /// data.snapshots.<snapshot_type>.<label>, see the snapshot operation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshots: Option<serde_json::Value>,
+    /// Weight discarded by the bond dimension truncation of matrix product states.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncation_error: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bond_dimensions: Option<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationResult {
+    pub status: Status,
+    pub data: ResultData,
+    /// Seconds spent simulating.
+    pub time_taken: Option<f64>,
+    /// Header of the compiled circuit.
+    pub header: serde_json::Value,
+    /// Seed of the random number generator, for sampling simulators.
+    pub seed: Option<usize>,
+}
+
+impl Serialize for SimulationResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut data = serde_json::to_value(&self.data).map_err(S::Error::custom)?;
+        if self.status == Status::Error && self.data.unitary.is_none() {
+            data["unitary"] = json!({});
+        }
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("status", &self.status)?;
+        map.serialize_entry("data", &data)?;
+        map.serialize_entry("result", &json!({}))?;
+        if let Some(time_taken) = self.time_taken {
+            map.serialize_entry("time_taken", &time_taken)?;
+        }
+        if !self.header.is_null() {
+            map.serialize_entry("header", &self.header)?;
+        }
+        if let Some(seed) = self.seed {
+            map.serialize_entry("seed", &seed)?;
+        }
+        map.end()
+    }
+}
+
+impl SimulationResult {
+    /// An empty result of the circuit with this `header`, in the error status
+    /// until the simulation is done.
+    pub fn new(header: serde_json::Value) -> SimulationResult {
+        SimulationResult {
+            status: Status::Error,
+            data: ResultData::default(),
+            time_taken: None,
+            header: header,
+            seed: None,
+        }
+    }
+
+    /// Marks the result as done, taking the time since `start`.
+    pub fn done(&mut self, start: Instant) {
+        self.status = Status::Done;
+        self.time_taken = Some(start.elapsed().as_secs_f64());
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.status == Status::Done
+    }
+
+    pub fn unitary(&self) -> Option<Matrix<Complex>> {
+        self.data.unitary.as_ref().map(|unitary| {
+            let size = (unitary.len() as f64).sqrt().round() as usize;
+            Matrix::new_from_vector(size, unitary.clone())
+        })
+    }
+
+    pub fn counts(&self) -> Option<&BTreeMap<String, usize>> {
+        self.data.counts.as_ref()
+    }
+
+    /// The snapshot of `snapshot_type` recorded under `label`.
+    pub fn snapshot(&self, snapshot_type: &str, label: &str) -> Option<&serde_json::Value> {
+        self.data.snapshots.as_ref()
+            .map(|snapshots| &snapshots[snapshot_type][label])
+            .filter(|snapshot| !snapshot.is_null())
+    }
+
+    /// The JSON form of the result.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap()
+    }
+}
+
+
+#[test]
+fn simulation_result_test() {
+    let mut result = SimulationResult::new(json!({"number_of_qubits": 1}));
+    assert_eq!(json!({"status": "ERROR", "data": {"unitary": {}}, "result": {}, "header": {"number_of_qubits": 1}}),
+               result.to_json());
+    assert!(result.unitary().is_none());
+
+    result.data.unitary = Some(vec![c!(0f64, 0f64), c!(1f64, 0f64), c!(1f64, 0f64), c!(0f64, 0f64)]);
+    result.done(Instant::now());
+    assert!(result.is_done());
+    let json = result.to_json();
+    assert_eq!(json!("DONE"), json["status"]);
+    assert_eq!(json!({"re": 1.0, "im": 0.0}), json["data"]["unitary"][1]);
+    assert!(json["time_taken"].as_f64().unwrap() >= 0f64);
+    assert!(result.unitary().unwrap().approx_eq(&m_real![0, 1; 1, 0]));
+
+    let mut counts = BTreeMap::new();
+    counts.insert("1".to_string(), 7);
+    result.data.counts = Some(counts);
+    result.data.snapshots = Some(json!({"statevector": {"start": [1, 0]}}));
+    assert_eq!(json!({"1": 7}), result.to_json()["data"]["counts"]);
+    assert_eq!(Some(&json!([1, 0])), result.snapshot("statevector", "start"));
+    assert_eq!(None, result.snapshot("statevector", "end"));
+}
@@ -0,0 +1,118 @@
+//! Reusable unitary simulator.
+//!
+//! `UnitarySimulator` is built for a single circuit and keeps its state between
+//! calls. A `Simulator` only owns the configuration, and runs every circuit from
+//! the identity, reusing the unitaries it allocated as long as the number of
+//! qubits does not change.
+
+use std::collections::HashMap;
+
+use circuit::Circuit;
+use memory::{MemoryLimits, SimulatorError, unitary_memory};
+use result::SimulationResult;
+use simulatortools::Endianness;
+use UnitarySimulator;
+
+pub struct Simulator {
+    parameters: HashMap<String, f64>,
+    /// Overrides of the circuit config, when set.
+    endianness: Option<Endianness>,
+    memory_limits: Option<MemoryLimits>,
+    /// The simulator of the last circuit, holding the buffers to reuse.
+    engine: Option<UnitarySimulator>,
+}
+
+impl Default for Simulator {
+    fn default() -> Simulator {
+        Simulator::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Simulator {
+        Simulator {
+            parameters: HashMap::new(),
+            endianness: None,
+            memory_limits: None,
+            engine: None,
+        }
+    }
+
+    /// Binds values to the named parameters used in symbolic gate parameters.
+    pub fn set_parameters(&mut self, parameters: HashMap<String, f64>) {
+        self.parameters = parameters;
+    }
+
+    /// Order of the qubits in the results, overriding the circuit config.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = Some(endianness);
+    }
+
+    /// Limits checked before every run, overriding the circuit config.
+    pub fn set_memory_limits(&mut self, memory_limits: MemoryLimits) {
+        self.memory_limits = Some(memory_limits);
+    }
+
+    /// Simulates `circuit` from the identity and returns its unitary.
+    pub fn run(&mut self, circuit: &Circuit) -> Result<SimulationResult, SimulatorError> {
+        let json = circuit.to_json();
+        let limits = match self.memory_limits {
+            Some(limits) => limits,
+            None => MemoryLimits::from_circuit(&json)?,
+        };
+        limits.check(circuit.number_of_qubits, unitary_memory(circuit.number_of_qubits))?;
+
+        let reusable = match self.engine {
+            Some(ref engine) => engine.number_of_qubits == circuit.number_of_qubits,
+            None => false,
+        };
+        if reusable {
+            self.engine.as_mut().unwrap().load(json)?;
+        } else {
+            // Free the old buffers before allocating the new ones
+            self.engine = None;
+            self.engine = Some(UnitarySimulator::from_json_with_limits(json, &limits)?);
+        }
+
+        let engine = self.engine.as_mut().unwrap();
+        engine.set_parameters(self.parameters.clone());
+        if let Some(endianness) = self.endianness {
+            engine.set_endianness(endianness);
+        }
+        engine.run()
+    }
+}
+
+
+#[test]
+fn simulator_test() {
+    let bell = Circuit::from_json(json!({
+        "header": {"number_of_qubits": 2},
+        "operations": [
+            {"name": "U", "qubits": [0], "params": ["pi/2", 0, "pi"]},
+            {"name": "CX", "qubits": [0, 1]}
+        ]
+    })).unwrap();
+    let mut simulator = Simulator::new();
+    let expected = simulator.run(&bell).unwrap();
+    assert!(expected.is_done());
+    // Every run starts over, instead of multiplying onto the last unitary
+    assert_eq!(expected.data, simulator.run(&bell).unwrap().data);
+
+    let rotation = Circuit::from_json(json!({
+        "header": {"number_of_qubits": 2},
+        "operations": [{"name": "U", "qubits": [1], "params": ["theta", 0, 0]}]
+    })).unwrap();
+    let mut parameters = HashMap::new();
+    parameters.insert("theta".to_string(), ::std::f64::consts::PI);
+    simulator.set_parameters(parameters);
+    let unitary = simulator.run(&rotation).unwrap().unitary().unwrap();
+    // Ry(pi)|00> = |10>
+    assert!((unitary.get(2, 0).re() - 1f64).abs() < 1e-12);
+
+    let larger = Circuit::from_json(json!({"header": {"number_of_qubits": 3}, "operations": []})).unwrap();
+    assert_eq!(8, simulator.run(&larger).unwrap().unitary().unwrap().size());
+    simulator.set_memory_limits(MemoryLimits { max_memory: 1 << 30, max_qubits: Some(2) });
+    assert!(simulator.run(&larger).is_err());
+    assert_eq!(expected.data, simulator.run(&bell).unwrap().data);
+}
@@ -1,6 +1,8 @@
 
 use complex::Complex;
 use matrix::*;
+use sparse::SparseMatrix;
+use serde_json;
 
 
 use gate::Gate;
@@ -66,3 +68,196 @@ pub fn enlarge_two_opt(gate: &Gate<f64>, qubit0: usize, qubit1: usize, num: usiz
     }
     enlarge_gate
 }
+
+/// Same as `enlarge_single_opt()`, as a sparse matrix with at most two non-zeros
+/// per row.
+pub fn enlarge_single_sparse(gate: &Gate<Complex>, qubit: usize, number_of_qubits: usize) -> SparseMatrix {
+    let mask = 1usize << qubit;
+    let rows = (0..1usize << number_of_qubits).map(|row| {
+        let b = (row >> qubit) & 1;
+        vec![(row & !mask, *gate.matrix.get(b, 0)), (row | mask, *gate.matrix.get(b, 1))]
+    }).collect();
+    SparseMatrix::from_rows(1 << number_of_qubits, rows)
+}
+
+/// Same as `enlarge_two_opt()`, as a sparse matrix with at most four non-zeros
+/// per row.
+pub fn enlarge_two_sparse(gate: &Gate<f64>, qubit0: usize, qubit1: usize, num: usize) -> SparseMatrix {
+    let rows = (0..1usize << num).map(|row| {
+        let local_row = ((row >> qubit0) & 1) + 2 * ((row >> qubit1) & 1);
+        let rest = row & !(1 << qubit0) & !(1 << qubit1);
+        (0..4).map(|local_column| {
+            let column = rest | ((local_column & 1) << qubit0) | ((local_column >> 1) << qubit1);
+            (column, Complex::new(*gate.matrix.get(local_row, local_column), 0f64))
+        }).collect()
+    }).collect();
+    SparseMatrix::from_rows(1 << num, rows)
+}
+
+
+/// Sparse operator of the k-qubit `gate` on `targets` (the first one being the
+/// lowest bit of the gate basis index), applied when all the `controls` are 1.
+/// Rows with a control at 0 stay those of the identity, so the controlled gate
+/// is never built: there are at most 2^k non-zeros per row.
+pub fn enlarge_controlled_sparse(gate: &Gate<Complex>, controls: &[usize], targets: &[usize], number_of_qubits: usize) -> SparseMatrix {
+    let control_mask = controls.iter().fold(0usize, |mask, &qubit| mask | (1 << qubit));
+    let target_mask = targets.iter().fold(0usize, |mask, &qubit| mask | (1 << qubit));
+    let rows = (0..1usize << number_of_qubits).map(|row| {
+        if row & control_mask != control_mask {
+            return vec![(row, Complex::new(1f64, 0f64))];
+        }
+        let local_row = targets.iter().enumerate().fold(0, |local, (bit, &qubit)| local | (((row >> qubit) & 1) << bit));
+        (0..gate.matrix.size()).map(|local_column| {
+            let column = targets.iter().enumerate()
+                .fold(row & !target_mask, |column, (bit, &qubit)| column | (((local_column >> bit) & 1) << qubit));
+            (column, *gate.matrix.get(local_row, local_column))
+        }).collect()
+    }).collect();
+    SparseMatrix::from_rows(1 << number_of_qubits, rows)
+}
+
+
+/// Left-multiplies `unitary` in place by the single-qubit `gate` on `target`,
+/// controlled by `controls`. Only the rows whose control bits are all 1 are
+/// touched, and diagonal gates skip the rows they leave unchanged.
+pub fn apply_controlled_single(unitary: &mut Matrix, gate: &Gate<Complex>, controls: &[usize], target: usize) {
+    let control_mask = controls.iter().fold(0usize, |mask, &qubit| mask | (1 << qubit));
+    let target_bit = 1usize << target;
+    let (g00, g01) = (*gate.matrix.get(0, 0), *gate.matrix.get(0, 1));
+    let (g10, g11) = (*gate.matrix.get(1, 0), *gate.matrix.get(1, 1));
+    let diagonal = g01.abs() == 0f64 && g10.abs() == 0f64;
+    let one = Complex::new(1f64, 0f64);
+    for row0 in 0..unitary.size() {
+        if row0 & target_bit != 0 || row0 & control_mask != control_mask {
+            continue;
+        }
+        let row1 = row0 | target_bit;
+        for column in 0..unitary.size() {
+            let (a, b) = (*unitary.get(row0, column), *unitary.get(row1, column));
+            if diagonal {
+                if g00 != one {
+                    unitary.set(row0, column, &(g00 * a));
+                }
+                unitary.set(row1, column, &(g11 * b));
+            } else {
+                unitary.set(row0, column, &(g00 * a + g01 * b));
+                unitary.set(row1, column, &(g10 * a + g11 * b));
+            }
+        }
+    }
+}
+
+/// Order of the qubits in the simulator outputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endianness {
+    /// Qubit 0 is the least significant bit of the basis state index and the
+    /// rightmost bit of the counts, as in q_{n-1} ... q_1 q_0. This is the order
+    /// used internally.
+    Little,
+    /// Qubit 0 is the most significant bit of the basis state index and the
+    /// leftmost bit of the counts, as in most textbooks.
+    Big,
+}
+
+impl Endianness {
+    /// Reads the optional "endianness" config of a compiled circuit, "little" (the
+    /// default) or "big".
+    pub fn from_circuit(circuit: &serde_json::Value) -> Result<Endianness, String> {
+        match circuit["config"]["endianness"].as_str() {
+            None => Ok(Endianness::Little),
+            Some("little") => Ok(Endianness::Little),
+            Some("big") => Ok(Endianness::Big),
+            Some(other) => Err(format!("Error: Unknown endianness '{}'!!", other)),
+        }
+    }
+
+    /// Where the basis state `index` (in little-endian order) goes in this order.
+    pub fn index(&self, index: usize, number_of_qubits: usize) -> usize {
+        match *self {
+            Endianness::Little => index,
+            Endianness::Big => (0..number_of_qubits).fold(0, |acc, q| acc | (((index >> q) & 1) << (number_of_qubits - 1 - q))),
+        }
+    }
+
+    /// The unitary in this order, from the little-endian `unitary`.
+    pub fn reorder_unitary(&self, unitary: &Matrix, number_of_qubits: usize) -> Matrix {
+        let permutation : Vec<usize> = (0..unitary.size()).map(|i| self.index(i, number_of_qubits)).collect();
+        // In one pass, permuting rows and then columns would need two copies
+        let mut reordered = Matrix::new(unitary.size());
+        for (i, &row) in permutation.iter().enumerate() {
+            for (j, &column) in permutation.iter().enumerate() {
+                reordered.set(row, column, unitary.get(i, j));
+            }
+        }
+        reordered
+    }
+
+    /// The state vector in this order, from the little-endian `state`.
+    pub fn reorder_state(&self, state: &[Complex], number_of_qubits: usize) -> Vec<Complex> {
+        let mut reordered = state.to_vec();
+        for (index, amplitude) in state.iter().enumerate() {
+            reordered[self.index(index, number_of_qubits)] = *amplitude;
+        }
+        reordered
+    }
+
+    /// Counts key of the classical bits.
+    pub fn bitstring(&self, clbits: &[bool]) -> String {
+        let bits = clbits.iter().map(|&bit| if bit { '1' } else { '0' });
+        match *self {
+            Endianness::Little => bits.rev().collect(),
+            Endianness::Big => bits.collect(),
+        }
+    }
+}
+
+#[test]
+fn enlarge_sparse_test() {
+    let u = Gate::<Complex>::u(0.3, 1.1, -0.4);
+    for qubit in 0..3 {
+        let dense = enlarge_single_opt(&u, qubit, 3);
+        assert!(enlarge_single_sparse(&u, qubit, 3).to_dense().approx_eq(&dense));
+        assert!(enlarge_controlled_sparse(&u, &[], &[qubit], 3).to_dense().approx_eq(&dense));
+    }
+    let mut dense = ::linalg::test_unitary(8, 5);
+    let expected = &enlarge_controlled_sparse(&u, &[2, 0], &[1], 3) * &dense;
+    apply_controlled_single(&mut dense, &u, &[2, 0], 1);
+    assert!(dense.max_deviation(&expected) < 1e-12);
+    // The controlled gate on [control, target] is the same operator
+    let controlled = u.controlled(1);
+    assert!(enlarge_controlled_sparse(&u, &[2], &[0], 3).to_dense()
+            .approx_eq(&enlarge_controlled_sparse(&controlled, &[], &[2, 0], 3).to_dense()));
+    let cx = Gate::<f64>::cx();
+    for &(control, target) in [(0, 1), (1, 0), (0, 2), (2, 1)].iter() {
+        let dense = enlarge_two_opt(&cx, control, target, 3);
+        let sparse = enlarge_two_sparse(&cx, control, target, 3);
+        assert!(sparse.is_monomial());
+        for i in 0..8 {
+            for j in 0..8 {
+                assert_eq!(Complex::new(*dense.get(i, j), 0f64), sparse.get(i, j));
+            }
+        }
+        let x = Gate::<Complex>::u(::std::f64::consts::PI, 0f64, ::std::f64::consts::PI);
+        assert_eq!(sparse, enlarge_controlled_sparse(&x, &[control], &[target], 3));
+    }
+}
+
+#[test]
+fn endianness_test() {
+    // X on qubit 0 of 3: |001> in little-endian is |100> in big-endian
+    let x = Gate::<Complex>::u(::std::f64::consts::PI, 0f64, ::std::f64::consts::PI);
+    let unitary = enlarge_single_opt(&x, 0, 3);
+    let big = Endianness::Big.reorder_unitary(&unitary, 3);
+    assert!(big.approx_eq(&enlarge_single_opt(&x, 2, 3)));
+    assert!(Endianness::Little.reorder_unitary(&unitary, 3).approx_eq(&unitary));
+
+    let state = unitary.column(0);
+    assert_eq!(Complex::new(1f64, 0f64), Endianness::Big.reorder_state(&state, 3)[4]);
+    assert_eq!(6, Endianness::Big.index(3, 3));
+
+    assert_eq!("011", Endianness::Little.bitstring(&[true, true, false]));
+    assert_eq!("110", Endianness::Big.bitstring(&[true, true, false]));
+    assert_eq!(Endianness::Big, Endianness::from_circuit(&json!({"config": {"endianness": "big"}})).unwrap());
+    assert_eq!(Endianness::Little, Endianness::from_circuit(&json!({})).unwrap());
+    assert!(Endianness::from_circuit(&json!({"config": {"endianness": "middle"}})).is_err());
+}
@@ -0,0 +1,254 @@
+//! Sparse matrices in compressed sparse row (CSR) format.
+//!
+//! Enlarged gates have at most 2 (single-qubit) or 4 (two-qubit) non-zeros per
+//! row, and circuits of X, CX and friends keep the whole unitary a permutation
+//! with phases (a monomial matrix, with a single non-zero per row and column).
+//! Multiplying those in CSR costs O(non-zeros) instead of the O(4^n) memory and
+//! O(8^n) time of the dense product.
+
+use std::fmt;
+use std::ops::Mul;
+
+use complex::Complex;
+use matrix::Matrix;
+use num::Zero;
+
+/// Products smaller than this are dropped, as they come from cancellations.
+const ZERO_TOLERANCE: f64 = 1e-15;
+
+/// Square sparse matrix: the non-zeros of row i are `columns[row_offsets[i]..row_offsets[i+1]]`
+/// and `values[..]` in the same range, sorted by column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseMatrix {
+    size: usize,
+    row_offsets: Vec<usize>,
+    columns: Vec<usize>,
+    values: Vec<Complex>,
+}
+
+impl SparseMatrix {
+    /// Builds the matrix from the (column, value) non-zeros of every row.
+    pub fn from_rows(size: usize, rows: Vec<Vec<(usize, Complex)>>) -> SparseMatrix {
+        assert_eq!(size, rows.len());
+        let mut row_offsets = Vec::with_capacity(size + 1);
+        let mut columns = Vec::new();
+        let mut values = Vec::new();
+        row_offsets.push(0);
+        for mut row in rows {
+            row.sort_by_key(|&(column, _)| column);
+            for (column, value) in row {
+                assert!(column < size);
+                if value.abs() > ZERO_TOLERANCE {
+                    columns.push(column);
+                    values.push(value);
+                }
+            }
+            row_offsets.push(columns.len());
+        }
+        SparseMatrix {
+            size: size,
+            row_offsets: row_offsets,
+            columns: columns,
+            values: values,
+        }
+    }
+
+    pub fn identity(size: usize) -> SparseMatrix {
+        SparseMatrix {
+            size: size,
+            row_offsets: (0..size + 1).collect(),
+            columns: (0..size).collect(),
+            values: vec![Complex::new(1f64, 0f64); size],
+        }
+    }
+
+    pub fn from_dense(matrix: &Matrix<Complex>) -> SparseMatrix {
+        let size = matrix.size();
+        let rows = (0..size)
+            .map(|i| (0..size).map(|j| (j, *matrix.get(i, j))).collect())
+            .collect();
+        SparseMatrix::from_rows(size, rows)
+    }
+
+    pub fn to_dense(&self) -> Matrix<Complex> {
+        let mut m = Matrix::<Complex>::new(self.size);
+        self.to_dense_into(&mut m);
+        m
+    }
+
+    /// Same as `to_dense()`, but writes into `out`, which is only reallocated if
+    /// it has a different size.
+    pub fn to_dense_into(&self, out: &mut Matrix<Complex>) {
+        if out.size() != self.size {
+            *out = Matrix::new(self.size);
+        }
+        for i in 0..self.size {
+            for j in 0..self.size {
+                out.set(i, j, &Complex::zero());
+            }
+            let (columns, values) = self.row(i);
+            for (j, value) in columns.iter().zip(values.iter()) {
+                out.set(i, *j, value);
+            }
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of non-zero elements.
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Fraction of non-zero elements.
+    pub fn density(&self) -> f64 {
+        self.nnz() as f64 / (self.size as f64 * self.size as f64)
+    }
+
+    /// Columns and values of the non-zeros of row `i`.
+    pub fn row(&self, i: usize) -> (&[usize], &[Complex]) {
+        let range = self.row_offsets[i]..self.row_offsets[i + 1];
+        (&self.columns[range.clone()], &self.values[range])
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> Complex {
+        let (columns, values) = self.row(i);
+        match columns.binary_search(&j) {
+            Ok(k) => values[k],
+            Err(_) => Complex::zero(),
+        }
+    }
+
+    /// Writes the product with the dense `rhs` into `out`, so the caller can reuse
+    /// its allocation.
+    pub fn mul_dense_into(&self, rhs: &Matrix<Complex>, out: &mut Matrix<Complex>) {
+        assert_eq!(self.size, rhs.size());
+        assert_eq!(self.size, out.size());
+        for i in 0..self.size {
+            let (columns, values) = self.row(i);
+            for j in 0..self.size {
+                let value = columns.iter().zip(values.iter())
+                    .fold(Complex::zero(), |acc, (k, a)| acc + *a * *rhs.get(*k, j));
+                out.set(i, j, &value);
+            }
+        }
+    }
+
+    /// Whether every row and column has exactly one non-zero, as the unitaries of
+    /// permutations with phases.
+    pub fn is_monomial(&self) -> bool {
+        if self.nnz() != self.size {
+            return false;
+        }
+        let mut seen = vec![false; self.size];
+        for i in 0..self.size {
+            let (columns, _) = self.row(i);
+            if columns.len() != 1 || seen[columns[0]] {
+                return false;
+            }
+            seen[columns[0]] = true;
+        }
+        true
+    }
+}
+
+impl<'a> Mul<&'a SparseMatrix> for &'a SparseMatrix {
+    type Output = SparseMatrix;
+
+    /// Row by row (Gustavson) product, accumulating every row in a dense buffer.
+    fn mul(self, rhs: &'a SparseMatrix) -> SparseMatrix {
+        assert_eq!(self.size, rhs.size);
+        let mut accumulator = vec![Complex::zero(); rhs.size];
+        let mut occupied = vec![false; rhs.size];
+        let mut rows = Vec::with_capacity(self.size);
+        for i in 0..self.size {
+            let mut touched = Vec::new();
+            let (columns, values) = self.row(i);
+            for (k, a) in columns.iter().zip(values.iter()) {
+                let (rhs_columns, rhs_values) = rhs.row(*k);
+                for (j, b) in rhs_columns.iter().zip(rhs_values.iter()) {
+                    if !occupied[*j] {
+                        occupied[*j] = true;
+                        touched.push(*j);
+                    }
+                    accumulator[*j] += *a * *b;
+                }
+            }
+            let row = touched.iter().map(|&j| {
+                let value = accumulator[j];
+                accumulator[j] = Complex::zero();
+                occupied[j] = false;
+                (j, value)
+            }).collect();
+            rows.push(row);
+        }
+        SparseMatrix::from_rows(self.size, rows)
+    }
+}
+
+impl<'a> Mul<&'a Matrix<Complex>> for &'a SparseMatrix {
+    type Output = Matrix<Complex>;
+
+    fn mul(self, rhs: &'a Matrix<Complex>) -> Matrix<Complex> {
+        let mut m = Matrix::<Complex>::new(self.size);
+        self.mul_dense_into(rhs, &mut m);
+        m
+    }
+}
+
+impl fmt::Display for SparseMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\nSparseMatrix {}x{} nnz={}\n", self.size, self.size, self.nnz())?;
+        for i in 0..self.size {
+            let (columns, values) = self.row(i);
+            for (j, value) in columns.iter().zip(values.iter()) {
+                writeln!(f, "({}, {}): {:?}", i, j, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[test]
+fn sparse_matrix_test() {
+    use num::One;
+
+    let dense = Matrix::new_from_row_slice(&[
+        c!(1f64, 0f64), c!(0f64, 0f64), c!(2f64, -1f64),
+        c!(0f64, 0f64), c!(0f64, 0f64), c!(0f64, 3f64),
+        c!(0f64, 0f64), c!(-1f64, 0f64), c!(0f64, 0f64)]);
+    let other = Matrix::new_from_row_slice(&[
+        c!(0f64, 0f64), c!(1f64, 1f64), c!(0f64, 0f64),
+        c!(4f64, 0f64), c!(0f64, 0f64), c!(0f64, 0f64),
+        c!(0f64, 0f64), c!(0f64, 0f64), c!(0f64, -2f64)]);
+    let sparse = SparseMatrix::from_dense(&dense);
+    assert_eq!(4, sparse.nnz());
+    assert_eq!(c!(2f64, -1f64), sparse.get(0, 2));
+    assert_eq!(Complex::zero(), sparse.get(1, 1));
+    assert!(sparse.to_dense().approx_eq(&dense));
+    let mut out = Matrix::<Complex>::identity(sparse.size());
+    let buffer = out.as_slice().as_ptr();
+    sparse.to_dense_into(&mut out);
+    assert!(out.approx_eq(&dense));
+    assert_eq!(buffer, out.as_slice().as_ptr());
+    assert!(!sparse.is_monomial());
+
+    // Sparse x sparse and sparse x dense agree with the dense product
+    let expected = &dense * &other;
+    assert!((&sparse * &SparseMatrix::from_dense(&other)).to_dense().approx_eq(&expected));
+    assert!((&sparse * &other).approx_eq(&expected));
+
+    let monomial = SparseMatrix::from_dense(&other);
+    assert!(monomial.is_monomial());
+    assert!((&monomial * &SparseMatrix::identity(3)).is_monomial());
+
+    // Cancellations are dropped: H H = I
+    let s = 0.5f64.sqrt();
+    let h = SparseMatrix::from_dense(&m_real![s, s; s, -s]);
+    let identity = &h * &h;
+    assert_eq!(2, identity.nnz());
+    assert_eq!(Complex::one(), identity.get(1, 1));
+}
@@ -0,0 +1,601 @@
+//! Stabilizer simulation of Clifford circuits.
+//!
+//! Circuits made only of H, S, CX and Pauli gates map Pauli operators to Pauli
+//! operators, so the state can be tracked by its n stabilizer generators instead
+//! of 2^n amplitudes. This is the CHP tableau of Aaronson and Gottesman
+//! (Phys. Rev. A 70, 052328, 2004): gates are O(n) and measurements O(n^2), which
+//! simulates hundreds of qubits with measurements in the middle of the circuit.
+//!
+//! Compiled circuits only have U and CX gates, U gates are recognized as Clifford
+//! when all their angles are multiples of pi/2. `sample_counts()` picks this
+//! simulator when it can, and samples the dense state otherwise.
+
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts::PI;
+use std::time::Instant;
+
+use rand::{Rng, SeedableRng, StdRng};
+use serde_json;
+
+use expression::Expression;
+use memory::{MemoryLimits, SimulatorError, stabilizer_memory};
+use result::SimulationResult;
+use simulatortools::Endianness;
+use UnitarySimulator;
+
+/// How far U angles can be from a multiple of pi/2 to be considered Clifford.
+const ANGLE_TOLERANCE: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CliffordOp {
+    H(usize),
+    S(usize),
+    Z(usize),
+    CX(usize, usize),
+    /// (qubit, clbit)
+    Measure(usize, usize),
+    Reset(usize),
+}
+
+/// Number of quarter turns of `angle`, if it is a multiple of pi/2.
+fn quarter_turns(angle: f64) -> Option<usize> {
+    let turns = (angle / (PI / 2f64)).round();
+    if (angle - turns * PI / 2f64).abs() > ANGLE_TOLERANCE {
+        return None;
+    }
+    Some(((turns as i64 % 4 + 4) % 4) as usize)
+}
+
+/// Clifford gates for U(theta, phi, lam) = Rz(phi) Ry(theta) Rz(lam), up to a
+/// global phase, where Rz(pi/2) = S and Ry(pi/2) = H Z.
+fn u_to_clifford(qubit: usize, theta: f64, phi: f64, lam: f64) -> Option<Vec<CliffordOp>> {
+    let (theta, phi, lam) = (quarter_turns(theta)?, quarter_turns(phi)?, quarter_turns(lam)?);
+    let mut ops = vec![CliffordOp::S(qubit); lam];
+    for _ in 0..theta {
+        ops.push(CliffordOp::Z(qubit));
+        ops.push(CliffordOp::H(qubit));
+    }
+    ops.extend(vec![CliffordOp::S(qubit); phi]);
+    Some(ops)
+}
+
+/// Translates the operations of a compiled circuit, or returns `None` if some
+/// gate is not Clifford or has modifiers.
+fn clifford_operations(circuit: &serde_json::Value) -> Result<Option<Vec<CliffordOp>>, String> {
+    let operations = match circuit["operations"].as_array() {
+        Some(operations) => operations,
+        None => return Err("No operations field in the circuit!!".to_string())
+    };
+    let no_parameters = HashMap::new();
+
+    let mut ops = Vec::new();
+    for op in operations {
+        let qubit = |index: usize| op["qubits"][index].as_u64().map(|qubit| qubit as usize)
+            .ok_or_else(|| format!("Error: Missing qubit in operation {}!!", op));
+        if !op["modifiers"].is_null() {
+            return Ok(None);
+        }
+        match op["name"].as_str() {
+            Some("U") => {
+                let mut angles = Vec::with_capacity(3);
+                for index in 0..3 {
+                    angles.push(Expression::from_json(&op["params"][index])?.eval(&no_parameters)?);
+                }
+                match u_to_clifford(qubit(0)?, angles[0], angles[1], angles[2]) {
+                    Some(clifford) => ops.extend(clifford),
+                    None => return Ok(None),
+                }
+            },
+            Some("CX") => ops.push(CliffordOp::CX(qubit(0)?, qubit(1)?)),
+            Some("measure") => {
+                let clbit = match op["clbits"][0].as_u64() {
+                    Some(clbit) => clbit as usize,
+                    None => return Err(format!("Error: Missing clbit in operation {}!!", op)),
+                };
+                ops.push(CliffordOp::Measure(qubit(0)?, clbit));
+            },
+            Some("reset") => ops.push(CliffordOp::Reset(qubit(0)?)),
+            Some("barrier") => (),
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(ops))
+}
+
+/// Whether `circuit` can be simulated with `StabilizerSimulator`.
+pub fn is_clifford(circuit: &serde_json::Value) -> bool {
+    match clifford_operations(circuit) {
+        Ok(ops) => ops.is_some(),
+        Err(_) => false,
+    }
+}
+
+/// Number of qubits and classical bits of a compiled circuit. Circuits without a
+/// "number_of_clbits" header use one bit past the largest measured clbit.
+pub(crate) fn circuit_size(circuit: &serde_json::Value) -> Result<(usize, usize), String> {
+    let number_of_qubits = match circuit["header"]["number_of_qubits"].as_u64() {
+        Some(val) => val as usize,
+        None => return Err("No number_of_qubits field in the circuit!!".to_string()),
+    };
+    let number_of_clbits = match circuit["header"]["number_of_clbits"].as_u64() {
+        Some(val) => val as usize,
+        None => circuit["operations"].as_array().unwrap_or(&vec![]).iter()
+            .filter_map(|op| op["clbits"].as_array())
+            .flat_map(|clbits| clbits.iter().filter_map(|clbit| clbit.as_u64()))
+            .map(|clbit| clbit as usize + 1)
+            .max()
+            .unwrap_or(0),
+    };
+    Ok((number_of_qubits, number_of_clbits))
+}
+
+/// CHP tableau: rows 0..n are the destabilizers, n..2n the stabilizers and 2n a
+/// scratch row. Row i is the Pauli operator (-1)^r[i] prod X^x[i][j] Z^z[i][j],
+/// with the x and z bits of each row packed in 64-bit words.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tableau {
+    number_of_qubits: usize,
+    x: Vec<Vec<u64>>,
+    z: Vec<Vec<u64>>,
+    r: Vec<bool>,
+}
+
+/// Word and mask of the bit for `qubit` in a packed row.
+fn bit(qubit: usize) -> (usize, u64) {
+    (qubit / 64, 1u64 << (qubit % 64))
+}
+
+impl Tableau {
+    /// The state |0...0>, stabilized by Z_j and destabilized by X_j.
+    pub fn new(number_of_qubits: usize) -> Tableau {
+        let n = number_of_qubits;
+        #[allow(clippy::manual_div_ceil)]
+        let words = (n + 63) / 64;
+        let mut x = vec![vec![0u64; words]; 2 * n + 1];
+        let mut z = vec![vec![0u64; words]; 2 * n + 1];
+        for j in 0..n {
+            let (word, mask) = bit(j);
+            x[j][word] |= mask;
+            z[n + j][word] |= mask;
+        }
+        Tableau {
+            number_of_qubits: n,
+            x: x,
+            z: z,
+            r: vec![false; 2 * n + 1],
+        }
+    }
+
+    pub fn number_of_qubits(&self) -> usize {
+        self.number_of_qubits
+    }
+
+    fn x_bit(&self, row: usize, qubit: usize) -> bool {
+        let (word, mask) = bit(qubit);
+        self.x[row][word] & mask != 0
+    }
+
+    fn z_bit(&self, row: usize, qubit: usize) -> bool {
+        let (word, mask) = bit(qubit);
+        self.z[row][word] & mask != 0
+    }
+
+    pub fn h(&mut self, qubit: usize) {
+        let (word, mask) = bit(qubit);
+        for i in 0..2 * self.number_of_qubits {
+            let (x, z) = (self.x[i][word] & mask, self.z[i][word] & mask);
+            self.r[i] ^= x != 0 && z != 0;
+            self.x[i][word] ^= x ^ z;
+            self.z[i][word] ^= x ^ z;
+        }
+    }
+
+    pub fn s(&mut self, qubit: usize) {
+        let (word, mask) = bit(qubit);
+        for i in 0..2 * self.number_of_qubits {
+            let x = self.x[i][word] & mask;
+            self.r[i] ^= x != 0 && self.z[i][word] & mask != 0;
+            self.z[i][word] ^= x;
+        }
+    }
+
+    pub fn x(&mut self, qubit: usize) {
+        for i in 0..2 * self.number_of_qubits {
+            self.r[i] ^= self.z_bit(i, qubit);
+        }
+    }
+
+    pub fn y(&mut self, qubit: usize) {
+        for i in 0..2 * self.number_of_qubits {
+            self.r[i] ^= self.x_bit(i, qubit) ^ self.z_bit(i, qubit);
+        }
+    }
+
+    pub fn z(&mut self, qubit: usize) {
+        for i in 0..2 * self.number_of_qubits {
+            self.r[i] ^= self.x_bit(i, qubit);
+        }
+    }
+
+    pub fn cx(&mut self, control: usize, target: usize) {
+        let ((cw, cm), (tw, tm)) = (bit(control), bit(target));
+        for i in 0..2 * self.number_of_qubits {
+            let (xc, zc) = (self.x[i][cw] & cm != 0, self.z[i][cw] & cm != 0);
+            let (xt, zt) = (self.x[i][tw] & tm != 0, self.z[i][tw] & tm != 0);
+            self.r[i] ^= xc && zt && !(xt ^ zc);
+            if xc {
+                self.x[i][tw] ^= tm;
+            }
+            if zt {
+                self.z[i][cw] ^= cm;
+            }
+        }
+    }
+
+    /// Row h = row i * row h.
+    ///
+    /// The phase adds up g(i, h) for every qubit, the power of i of the product of
+    /// the single-qubit Paulis, which is +1 for XY, YZ and ZX and -1 for the
+    /// reverse orders. We count both cases with bitwise operations on whole words.
+    fn rowsum(&mut self, h: usize, i: usize) {
+        let mut phase = 2 * (self.r[h] as i64 + self.r[i] as i64);
+        for w in 0..self.x[h].len() {
+            let (x1, z1, x2, z2) = (self.x[i][w], self.z[i][w], self.x[h][w], self.z[h][w]);
+            let plus = (x1 & z1 & !x2 & z2) | (x1 & !z1 & x2 & z2) | (!x1 & z1 & x2 & !z2);
+            let minus = (x1 & z1 & x2 & !z2) | (x1 & !z1 & !x2 & z2) | (!x1 & z1 & x2 & z2);
+            phase += plus.count_ones() as i64 - minus.count_ones() as i64;
+            self.x[h][w] ^= x1;
+            self.z[h][w] ^= z1;
+        }
+        self.r[h] = phase.rem_euclid(4) == 2;
+    }
+
+    /// Measures `qubit` in the Z basis, collapsing the state.
+    pub fn measure<R: Rng>(&mut self, qubit: usize, rng: &mut R) -> bool {
+        let n = self.number_of_qubits;
+        // A stabilizer anticommuting with Z makes the outcome random
+        match (n..2 * n).find(|&p| self.x_bit(p, qubit)) {
+            Some(p) => {
+                for i in 0..2 * n {
+                    if i != p && self.x_bit(i, qubit) {
+                        self.rowsum(i, p);
+                    }
+                }
+                self.x[p - n] = self.x[p].clone();
+                self.z[p - n] = self.z[p].clone();
+                self.r[p - n] = self.r[p];
+                let (word, mask) = bit(qubit);
+                for w in 0..self.x[p].len() {
+                    self.x[p][w] = 0;
+                    self.z[p][w] = 0;
+                }
+                self.z[p][word] = mask;
+                self.r[p] = rng.gen::<bool>();
+                self.r[p]
+            },
+            None => {
+                let scratch = 2 * n;
+                for w in 0..self.x[scratch].len() {
+                    self.x[scratch][w] = 0;
+                    self.z[scratch][w] = 0;
+                }
+                self.r[scratch] = false;
+                for i in 0..n {
+                    if self.x_bit(i, qubit) {
+                        self.rowsum(scratch, i + n);
+                    }
+                }
+                self.r[scratch]
+            },
+        }
+    }
+
+    /// Resets `qubit` to |0>.
+    pub fn reset<R: Rng>(&mut self, qubit: usize, rng: &mut R) {
+        if self.measure(qubit, rng) {
+            self.x(qubit);
+        }
+    }
+}
+
+/// Samples measurement outcomes of Clifford-only compiled circuits.
+pub struct StabilizerSimulator {
+    number_of_qubits: usize,
+    number_of_clbits: usize,
+    operations: Vec<CliffordOp>,
+    result: SimulationResult,
+    seed: Option<usize>,
+    endianness: Endianness,
+}
+
+impl StabilizerSimulator {
+    pub fn new(compiled_circuit: String) -> Result<StabilizerSimulator, SimulatorError> {
+        let circuit : serde_json::Value = match serde_json::from_str(compiled_circuit.as_str()) {
+            Ok(_circuit) => _circuit,
+            Err(err) => return Err(SimulatorError::Circuit(format!("Error: parsing compiled circuit!!: {}", err))),
+        };
+        StabilizerSimulator::from_json(circuit)
+    }
+
+    /// Same as `new()` but takes an already parsed compiled circuit. Fails if the
+    /// circuit is not Clifford, see `is_clifford()`.
+    pub fn from_json(circuit: serde_json::Value) -> Result<StabilizerSimulator, SimulatorError> {
+        let (number_of_qubits, number_of_clbits) = circuit_size(&circuit)?;
+        let endianness = Endianness::from_circuit(&circuit)?;
+        MemoryLimits::from_circuit(&circuit)?.check(number_of_qubits, stabilizer_memory(number_of_qubits))?;
+        let operations = match clifford_operations(&circuit)? {
+            Some(operations) => operations,
+            None => return Err(SimulatorError::Circuit("Error: Circuit is not Clifford!!".to_string())),
+        };
+        for op in operations.iter() {
+            let (qubits, clbit) = match *op {
+                CliffordOp::CX(control, target) => (vec![control, target], None),
+                CliffordOp::Measure(qubit, clbit) => (vec![qubit], Some(clbit)),
+                CliffordOp::H(qubit) | CliffordOp::S(qubit) | CliffordOp::Z(qubit) | CliffordOp::Reset(qubit) =>
+                    (vec![qubit], None),
+            };
+            if qubits.iter().any(|&qubit| qubit >= number_of_qubits) || matches!(clbit, Some(clbit) if clbit >= number_of_clbits) {
+                return Err(SimulatorError::Circuit(format!("Error: Operation {:?} out of the circuit registers!!", op)));
+            }
+        }
+
+        let result = SimulationResult::new(circuit["header"].clone());
+        Ok(StabilizerSimulator {
+            number_of_qubits: number_of_qubits,
+            number_of_clbits: number_of_clbits,
+            operations: operations,
+            result: result,
+            seed: None,
+            endianness: endianness,
+        })
+    }
+
+    /// Seeds the random number generator, to get reproducible counts.
+    pub fn set_seed(&mut self, seed: usize) {
+        self.seed = Some(seed);
+    }
+
+    /// Order of the bits in the counts, overriding the circuit config.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    fn apply<R: Rng>(tableau: &mut Tableau, op: &CliffordOp, clbits: &mut [bool], rng: &mut R) {
+        match *op {
+            CliffordOp::H(qubit) => tableau.h(qubit),
+            CliffordOp::S(qubit) => tableau.s(qubit),
+            CliffordOp::Z(qubit) => tableau.z(qubit),
+            CliffordOp::CX(control, target) => tableau.cx(control, target),
+            CliffordOp::Measure(qubit, clbit) => clbits[clbit] = tableau.measure(qubit, rng),
+            CliffordOp::Reset(qubit) => tableau.reset(qubit, rng),
+        }
+    }
+
+    /// Runs the circuit `shots` times, returning the counts of every classical
+    /// register value in data.counts.
+    pub fn run(&mut self, shots: usize) -> Result<SimulationResult, SimulatorError> {
+        let start = Instant::now();
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::from_seed(&[seed][..]),
+            None => match StdRng::new() {
+                Ok(rng) => rng,
+                Err(err) => return Err(SimulatorError::Circuit(format!("Error: Cannot initialize the random number generator!!: {}", err))),
+            },
+        };
+
+        // Everything before the first measurement or reset is deterministic, so we
+        // only simulate it once.
+        let first_random = self.operations.iter()
+            .position(|op| matches!(*op, CliffordOp::Measure(..) | CliffordOp::Reset(..)))
+            .unwrap_or(self.operations.len());
+        let mut prefix = Tableau::new(self.number_of_qubits);
+        let mut clbits = vec![false; self.number_of_clbits];
+        for op in self.operations[..first_random].iter() {
+            StabilizerSimulator::apply(&mut prefix, op, &mut clbits, &mut rng);
+        }
+
+        let mut counts : BTreeMap<String, usize> = BTreeMap::new();
+        for _ in 0..shots {
+            let mut tableau = prefix.clone();
+            let mut clbits = vec![false; self.number_of_clbits];
+            for op in self.operations[first_random..].iter() {
+                StabilizerSimulator::apply(&mut tableau, op, &mut clbits, &mut rng);
+            }
+            *counts.entry(self.endianness.bitstring(&clbits)).or_insert(0) += 1;
+        }
+        debug!("run: shots={} counts={:?}", shots, counts);
+
+        self.result.data.counts = Some(counts);
+        self.result.seed = self.seed;
+        self.result.done(start);
+        Ok(self.result.clone())
+    }
+}
+
+/// Samples the measurement counts of a compiled circuit, with the stabilizer
+/// simulator for Clifford circuits and from the dense state otherwise.
+///
+/// The dense fallback simulates the unitary of the circuit, so it only supports
+/// measurements at the end: no reset, and no gates on a qubit once measured.
+pub fn sample_counts(circuit: serde_json::Value, shots: usize, seed: usize) -> Result<SimulationResult, String> {
+    if is_clifford(&circuit) {
+        let mut simulator = StabilizerSimulator::from_json(circuit)?;
+        simulator.set_seed(seed);
+        return Ok(simulator.run(shots)?);
+    }
+
+    let start = Instant::now();
+    let (_, number_of_clbits) = circuit_size(&circuit)?;
+    let endianness = Endianness::from_circuit(&circuit)?;
+    let mut result = SimulationResult::new(circuit["header"].clone());
+    let mut measurements = Vec::new();
+    {
+        let operations = match circuit["operations"].as_array() {
+            Some(operations) => operations,
+            None => return Err("No operations field in the circuit!!".to_string())
+        };
+        for op in operations {
+            let qubits : Vec<u64> = op["qubits"].as_array().map_or(vec![], |qubits| qubits.iter().filter_map(|q| q.as_u64()).collect());
+            match op["name"].as_str() {
+                Some("measure") => match (qubits.first(), op["clbits"][0].as_u64()) {
+                    (Some(&qubit), Some(clbit)) if (clbit as usize) < number_of_clbits => measurements.push((qubit as usize, clbit as usize)),
+                    _ => return Err(format!("Error: Measure needs a qubit and a clbit: {}!!", op)),
+                },
+                Some("reset") => return Err("Error: Reset is not supported by the dense sampler!!".to_string()),
+                Some("barrier") => (),
+                _ => if measurements.iter().any(|&(qubit, _)| qubits.contains(&(qubit as u64))) {
+                    return Err("Error: Gates after a measurement are not supported by the dense sampler!!".to_string());
+                },
+            }
+        }
+    }
+
+    let mut us = UnitarySimulator::from_json(circuit)?;
+    if !us.run()?.is_done() {
+        return Err("Error: Simulation of the circuit failed!!".to_string());
+    }
+    let probabilities : Vec<f64> = us.unitary().column(0).iter().map(|amplitude| amplitude.norm_sqr()).collect();
+
+    let mut rng = StdRng::from_seed(&[seed][..]);
+    let mut counts : BTreeMap<String, usize> = BTreeMap::new();
+    for _ in 0..shots {
+        let mut sample = rng.gen::<f64>();
+        let mut state = probabilities.len() - 1;
+        for (index, probability) in probabilities.iter().enumerate() {
+            if sample < *probability {
+                state = index;
+                break;
+            }
+            sample -= *probability;
+        }
+        let mut clbits = vec![false; number_of_clbits];
+        for &(qubit, clbit) in measurements.iter() {
+            clbits[clbit] = (state >> qubit) & 1 == 1;
+        }
+        *counts.entry(endianness.bitstring(&clbits)).or_insert(0) += 1;
+    }
+
+    result.data.counts = Some(counts);
+    result.seed = Some(seed);
+    result.done(start);
+    Ok(result)
+}
+
+
+#[test]
+fn clifford_recognition_test() {
+    // H, S, X and a T gate
+    let h = json!({"name": "U", "qubits": [0], "params": ["pi/2", 0, "pi"]});
+    let s = json!({"name": "U", "qubits": [0], "params": [0, 0, "pi/2"]});
+    let x = json!({"name": "U", "qubits": [0], "params": ["pi", 0, "pi"]});
+    let t = json!({"name": "U", "qubits": [0], "params": [0, 0, "pi/4"]});
+    let circuit = |ops: Vec<serde_json::Value>| json!({"header": {"number_of_qubits": 2}, "operations": ops});
+
+    assert!(is_clifford(&circuit(vec![h.clone(), s.clone(), x.clone(),
+                                      json!({"name": "CX", "qubits": [0, 1]}),
+                                      json!({"name": "measure", "qubits": [0], "clbits": [0]})])));
+    assert!(!is_clifford(&circuit(vec![h.clone(), t.clone()])));
+    assert!(StabilizerSimulator::from_json(circuit(vec![t])).is_err());
+
+    // Clifford U gates between two H match the dense outcome probabilities, which
+    // also depend on the phases introduced by the gate
+    for k in 0..4 {
+        for theta in 0..4 {
+            let angle = |turns: i32| json!(turns as f64 * PI / 2f64);
+            let gate = json!({"name": "U", "qubits": [0], "params": [angle(theta), angle(k), angle(1 - k)]});
+            let ops = vec![h.clone(), gate, h.clone(), json!({"name": "measure", "qubits": [0], "clbits": [0]})];
+            let circuit = json!({"header": {"number_of_qubits": 1}, "operations": ops});
+            let dense = {
+                let mut us = UnitarySimulator::from_json(circuit.clone()).unwrap();
+                us.run().unwrap();
+                us.unitary().get(1, 0).norm_sqr()
+            };
+            let result = StabilizerSimulator::from_json(circuit).unwrap().run(200).unwrap();
+            let ones = *result.counts().unwrap().get("1").unwrap_or(&0) as f64 / 200f64;
+            assert!((ones - dense).abs() < 0.2, "theta={} k={}: {} != {}", theta, k, ones, dense);
+        }
+    }
+}
+
+#[test]
+fn stabilizer_simulator_test() {
+    // GHZ state on 300 qubits: all zeros or all ones
+    let n = 300;
+    let mut ops = vec![json!({"name": "U", "qubits": [0], "params": ["pi/2", 0, "pi"]})];
+    for qubit in 1..n {
+        ops.push(json!({"name": "CX", "qubits": [qubit - 1, qubit]}));
+    }
+    for qubit in 0..n {
+        ops.push(json!({"name": "measure", "qubits": [qubit], "clbits": [qubit]}));
+    }
+    let circuit = json!({"header": {"number_of_qubits": n, "number_of_clbits": n}, "operations": ops});
+    let mut simulator = StabilizerSimulator::from_json(circuit).unwrap();
+    simulator.set_seed(7);
+    let result = simulator.run(100).unwrap();
+    assert!(result.is_done());
+    assert_eq!(Some(7), result.seed);
+    let counts = result.counts().unwrap();
+    assert_eq!(2, counts.len());
+    let zeros = counts[&"0".repeat(n)];
+    assert_eq!(100, zeros + counts[&"1".repeat(n)]);
+    assert!(zeros > 20 && zeros < 80);
+
+    // Mid-circuit measurement and reset: X, measure, reset, measure gives "01"
+    let circuit = json!({
+        "header": {"number_of_qubits": 1, "number_of_clbits": 2},
+        "operations": [
+            {"name": "U", "qubits": [0], "params": ["pi", 0, "pi"]},
+            {"name": "measure", "qubits": [0], "clbits": [0]},
+            {"name": "reset", "qubits": [0]},
+            {"name": "measure", "qubits": [0], "clbits": [1]}
+        ]
+    });
+    let result = sample_counts(circuit.clone(), 10, 1).unwrap();
+    assert_eq!(json!({"01": 10}), result.to_json()["data"]["counts"]);
+    let mut simulator = StabilizerSimulator::from_json(circuit).unwrap();
+    simulator.set_endianness(Endianness::Big);
+    assert_eq!(json!({"10": 10}), simulator.run(10).unwrap().to_json()["data"]["counts"]);
+
+    // Y = i X Z on |+> keeps it an eigenstate of X: H Y H |0> = -|1>
+    let mut tableau = Tableau::new(1);
+    let mut rng = StdRng::from_seed(&[3][..]);
+    tableau.h(0);
+    tableau.y(0);
+    tableau.h(0);
+    assert!(tableau.measure(0, &mut rng));
+}
+
+#[test]
+fn dense_fallback_test() {
+    // Ry(2pi/3)|0> gives 1 with probability 3/4
+    let circuit = json!({
+        "header": {"number_of_qubits": 1},
+        "operations": [
+            {"name": "U", "qubits": [0], "params": ["2*pi/3", 0, 0]},
+            {"name": "measure", "qubits": [0], "clbits": [0]}
+        ]
+    });
+    assert!(!is_clifford(&circuit));
+    let result = sample_counts(circuit, 4000, 5).unwrap();
+    let ones = result.counts().unwrap()["1"] as f64 / 4000f64;
+    assert!((ones - 0.75).abs() < 0.05);
+
+    let circuit = json!({
+        "header": {"number_of_qubits": 1},
+        "operations": [
+            {"name": "measure", "qubits": [0], "clbits": [0]},
+            {"name": "U", "qubits": [0], "params": ["pi/3", 0, 0]}
+        ]
+    });
+    assert!(sample_counts(circuit, 10, 5).is_err());
+
+    for measure in [json!({"name": "measure", "qubits": [], "clbits": [0]}),
+                    json!({"name": "measure", "qubits": [0]}),
+                    json!({"name": "measure", "qubits": [0], "clbits": [3]})].iter() {
+        let circuit = json!({
+            "header": {"number_of_qubits": 1, "number_of_clbits": 1},
+            "operations": [{"name": "U", "qubits": [0], "params": ["pi/3", 0, 0]}, measure]
+        });
+        assert!(sample_counts(circuit, 10, 5).is_err());
+    }
+}
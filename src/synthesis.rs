@@ -0,0 +1,844 @@
+//! Unitary synthesis: from matrices back to the U/CX operations of compiled circuits.
+
+use std::f64::consts::PI;
+
+use complex::Complex;
+use gate::{Gate, UNITARY_TOLERANCE};
+use matrix::Matrix;
+use num::{Zero, One};
+use serde_json;
+use UnitarySimulator;
+
+/// Wraps an angle into (-pi, pi].
+fn wrap_angle(angle: f64) -> f64 {
+    let wrapped = angle - 2f64 * PI * (angle / (2f64 * PI)).round();
+    if wrapped <= -PI { wrapped + 2f64 * PI } else { wrapped }
+}
+
+/// Angles of a single-qubit gate `e^{i phase} U(theta, phi, lam)`.
+///
+/// As U(theta, phi, lam) = e^{i(phi+lam)/2} Rz(phi) Ry(theta) Rz(lam), these are
+/// also the ZYZ Euler angles of the gate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct U3Angles {
+    pub theta: f64,
+    pub phi: f64,
+    pub lam: f64,
+    pub phase: f64,
+}
+
+impl U3Angles {
+    /// The gate without the global phase, i.e. U(theta, phi, lam).
+    pub fn gate(&self) -> Gate<Complex> {
+        Gate::<Complex>::u(self.theta, self.phi, self.lam)
+    }
+
+    /// The compiled-circuit operation applying these angles on `qubit`.
+    pub fn to_operation(&self, qubit: usize) -> serde_json::Value {
+        json!({"name": "U", "qubits": [qubit], "params": [self.theta, self.phi, self.lam]})
+    }
+
+    /// The OpenQASM statement applying these angles on `qubit`, like "q[0]".
+    pub fn to_qasm(&self, qubit: &str) -> String {
+        format!("U({:e},{:e},{:e}) {};", self.theta, self.phi, self.lam, qubit)
+    }
+}
+
+/// Finds the U3 angles and global phase of an arbitrary 2x2 unitary, so that
+/// `gate == e^{i phase} U(theta, phi, lam)`.
+///
+/// With gate = [[a, b], [c, d]], U gives |a| = |d| = cos(theta/2) and
+/// |b| = |c| = sin(theta/2), and the phases are read from the larger entries
+/// so the result is accurate even when theta is close to 0 or pi.
+pub fn u3_from_unitary(gate: &Gate<Complex>) -> Result<U3Angles, String> {
+    let m = &gate.matrix;
+    if m.size() != 2 {
+        return Err(format!("Error: Expected a single-qubit gate, got a {}x{} matrix!!", m.size(), m.size()));
+    }
+    if !m.is_unitary(UNITARY_TOLERANCE) {
+        return Err("Error: Gate is not unitary!!".to_string());
+    }
+
+    let (a, b, c, d) = (*m.get(0, 0), *m.get(0, 1), *m.get(1, 0), *m.get(1, 1));
+    let theta = 2f64 * c.abs().atan2(a.abs());
+    // For unitaries arg(a) + arg(d) == arg(c) + arg(-b) == arg(det)
+    let (phase, phi, lam) = if a.abs() >= c.abs() {
+        let phase = a.arg();
+        (phase, c.arg() - phase, d.arg() - c.arg())
+    } else {
+        let phase = c.arg() + (-b).arg() - d.arg();
+        (phase, c.arg() - phase, (-b).arg() - phase)
+    };
+
+    Ok(U3Angles {
+        theta: theta,
+        phi: wrap_angle(phi),
+        lam: wrap_angle(lam),
+        phase: wrap_angle(phase),
+    })
+}
+
+/// Result of synthesizing a unitary: `unitary == e^{i phase} * circuit(operations)`.
+#[derive(Debug, Clone)]
+pub struct Synthesis {
+    /// U and CX operations, in the compiled-circuit format.
+    pub operations: Vec<serde_json::Value>,
+    /// Global phase, in radians.
+    pub phase: f64,
+}
+
+impl Synthesis {
+    /// Number of CX gates in the synthesized circuit.
+    pub fn cx_count(&self) -> usize {
+        self.operations.iter().filter(|op| op["name"] == "CX").count()
+    }
+
+    /// Same circuit with qubit `k` relabeled as `qubits[k]`.
+    pub fn on_qubits(&self, qubits: &[usize]) -> Synthesis {
+        let operations = self.operations.iter().map(|op| {
+            let mut op = op.clone();
+            let relabeled : Vec<usize> = op["qubits"].as_array().unwrap().iter()
+                .map(|qubit| qubits[qubit.as_u64().unwrap() as usize])
+                .collect();
+            op["qubits"] = json!(relabeled);
+            op
+        }).collect();
+        Synthesis {
+            operations: operations,
+            phase: self.phase,
+        }
+    }
+
+    /// A compiled circuit with the synthesized operations.
+    pub fn to_circuit(&self, number_of_qubits: usize) -> serde_json::Value {
+        json!({
+            "header": {"number_of_qubits": number_of_qubits},
+            "operations": self.operations.clone()
+        })
+    }
+}
+
+/// Collects single-qubit matrices and CX gates, fusing consecutive single-qubit
+/// gates on the same qubit into one U operation.
+struct Emitter {
+    operations: Vec<serde_json::Value>,
+    pending: Vec<Option<Matrix<Complex>>>,
+}
+
+impl Emitter {
+    fn new(number_of_qubits: usize) -> Emitter {
+        Emitter {
+            operations: Vec::new(),
+            pending: vec![None; number_of_qubits],
+        }
+    }
+
+    /// Applies the 2x2 unitary `matrix` on `qubit`, after everything emitted so far.
+    fn single(&mut self, qubit: usize, matrix: &Matrix<Complex>) {
+        let fused = match self.pending[qubit].take() {
+            Some(previous) => matrix * &previous,
+            None => matrix.clone(),
+        };
+        self.pending[qubit] = Some(fused);
+    }
+
+    fn flush(&mut self, qubit: usize) -> Result<(), String> {
+        if let Some(matrix) = self.pending[qubit].take() {
+            // Identities (up to a phase) are dropped, the phase is fixed at the end.
+            if !matrix.equiv_up_to_global_phase(&Matrix::identity(2), 1e-12) {
+                let angles = u3_from_unitary(&Gate::new(2, matrix))?;
+                self.operations.push(angles.to_operation(qubit));
+            }
+        }
+        Ok(())
+    }
+
+    fn cx(&mut self, control: usize, target: usize) -> Result<(), String> {
+        self.flush(control)?;
+        self.flush(target)?;
+        // CX gates with the same target commute, so we can cancel this one with
+        // a previous CX(control, target) if only those are in between.
+        for k in (0..self.operations.len()).rev() {
+            let op = &self.operations[k];
+            let qubits = op["qubits"].as_array().unwrap();
+            if op["name"] == "CX" && qubits[1] == json!(target) {
+                if qubits[0] == json!(control) {
+                    self.operations.remove(k);
+                    return Ok(());
+                }
+            } else if qubits.contains(&json!(control)) || qubits.contains(&json!(target)) {
+                break;
+            }
+        }
+        self.operations.push(json!({"name": "CX", "qubits": [control, target]}));
+        Ok(())
+    }
+
+    /// Applies a synthesized circuit, with its qubit `k` on `qubits[k]`. The phase
+    /// is dropped, as `finish()` recomputes it.
+    fn append(&mut self, synthesis: &Synthesis, qubits: &[usize]) -> Result<(), String> {
+        for op in synthesis.operations.iter() {
+            let operands : Vec<usize> = op["qubits"].as_array().unwrap().iter()
+                .map(|qubit| qubits[qubit.as_u64().unwrap() as usize])
+                .collect();
+            if op["name"] == "CX" {
+                self.cx(operands[0], operands[1])?;
+            } else {
+                let params : Vec<f64> = op["params"].as_array().unwrap().iter()
+                    .map(|param| param.as_f64().unwrap())
+                    .collect();
+                self.single(operands[0], &Gate::<Complex>::u(params[0], params[1], params[2]).matrix);
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits the pending gates and computes the global phase against `unitary`,
+    /// the matrix we are synthesizing.
+    fn finish(mut self, unitary: &Matrix<Complex>) -> Result<Synthesis, String> {
+        for qubit in 0..self.pending.len() {
+            self.flush(qubit)?;
+        }
+        let circuit = simulate(&self.operations, self.pending.len())?;
+        Ok(Synthesis {
+            phase: circuit.global_phase_to(unitary),
+            operations: self.operations,
+        })
+    }
+}
+
+/// Unitary of a list of operations on `number_of_qubits` qubits.
+fn simulate(operations: &[serde_json::Value], number_of_qubits: usize) -> Result<Matrix<Complex>, String> {
+    let circuit = json!({
+        "header": {"number_of_qubits": number_of_qubits},
+        "operations": operations
+    });
+    let mut us = UnitarySimulator::from_json(circuit)?;
+    us.run()?;
+    Ok(us.unitary().clone())
+}
+
+fn rz(angle: f64) -> Matrix<Complex> {
+    Matrix::new_from_row_slice(&[Complex::new_euler(1f64, -angle / 2f64), Complex::zero(),
+                                 Complex::zero(), Complex::new_euler(1f64, angle / 2f64)])
+}
+
+fn ry(angle: f64) -> Matrix<Complex> {
+    let (c, s) = ((angle / 2f64).cos(), (angle / 2f64).sin());
+    Matrix::new_from_row_slice(&[Complex::new(c, 0f64), Complex::new(-s, 0f64),
+                                 Complex::new(s, 0f64), Complex::new(c, 0f64)])
+}
+
+/// The magic basis (as columns): |00> + |11>, i(|01> + |10>), |01> - |10>,
+/// i(|00> - |11>), normalized. In this basis local gates SU(2) x SU(2) are real
+/// orthogonal matrices and XX, YY and ZZ are diagonal.
+fn magic_basis() -> Matrix<Complex> {
+    let s = 0.5f64.sqrt();
+    let (o, r, i) = (Complex::zero(), Complex::new(s, 0f64), Complex::new(0f64, s));
+    Matrix::new_from_row_slice(&[
+        r, o, o, i,
+        o, i, r, o,
+        o, i, -r, o,
+        r, o, o, -i,
+    ])
+}
+
+/// Splits a 4x4 matrix `m` into `high x low` (high acts on the most significant
+/// qubit), returning the factors and the residual `|| m - high x low ||`.
+fn tensor_factor(m: &Matrix<Complex>) -> (Matrix<Complex>, Matrix<Complex>, f64) {
+    let block = |i: usize, j: usize| Matrix::new_from_row_slice(&[
+        *m.get(2 * i, 2 * j), *m.get(2 * i, 2 * j + 1),
+        *m.get(2 * i + 1, 2 * j), *m.get(2 * i + 1, 2 * j + 1)]);
+
+    // The largest block is high_ij * low, with |high_ij| >= 1/2
+    let mut best = (0, 0);
+    for i in 0..2 {
+        for j in 0..2 {
+            if block(i, j).frobenius_norm() > block(best.0, best.1).frobenius_norm() {
+                best = (i, j);
+            }
+        }
+    }
+    let largest = block(best.0, best.1);
+    let low = largest.scale(Complex::one() / largest.frobenius_norm() * 2f64.sqrt());
+    let mut high = Matrix::<Complex>::new(2);
+    for i in 0..2 {
+        for j in 0..2 {
+            // low is unitary, so tr(low^dagger high_ij low) / 2 = high_ij
+            high.set(i, j, &((&low.adjoint() * &block(i, j)).trace() / 2f64));
+        }
+    }
+
+    let mut product = Matrix::<Complex>::new(4);
+    for i in 0..4 {
+        for j in 0..4 {
+            product.set(i, j, &(*high.get(i / 2, j / 2) * *low.get(i % 2, j % 2)));
+        }
+    }
+    let residual = (m - &product).frobenius_norm();
+    (high, low, residual)
+}
+
+/// Decomposes a two-qubit unitary into single-qubit U gates and the fewest CX
+/// gates it needs (at most three), using the KAK (Cartan) decomposition
+///
+/// ```text
+/// U = e^{i phase} (A1 x A0) exp(i(a XX + b YY + c ZZ)) (B1 x B0)
+/// ```
+///
+/// The matrix index is `2 * q1 + q0` (same order as the simulator), where `qubit0`
+/// and `qubit1` are the circuit qubits for q0 and q1.
+pub fn two_qubit_decomposition(unitary: &Matrix<Complex>, qubit0: usize, qubit1: usize) -> Result<Synthesis, String> {
+    if unitary.size() != 4 {
+        return Err(format!("Error: Expected a two-qubit unitary, got a {}x{} matrix!!", unitary.size(), unitary.size()));
+    }
+    if !unitary.is_unitary(UNITARY_TOLERANCE) {
+        return Err("Error: Matrix is not unitary!!".to_string());
+    }
+    if qubit0 == qubit1 {
+        return Err("Error: Two-qubit decomposition needs two different qubits!!".to_string());
+    }
+    Ok(kak(unitary)?.on_qubits(&[qubit0, qubit1]))
+}
+
+/// KAK decomposition of a 4x4 unitary on qubits 0 and 1.
+fn kak(unitary: &Matrix<Complex>) -> Result<Synthesis, String> {
+    let (qubit0, qubit1) = (0, 1);
+    let mut emitter = Emitter::new(2);
+
+    // Local gates don't need any CX
+    let (high, low, residual) = tensor_factor(unitary);
+    if residual < 1e-12 {
+        emitter.single(qubit0, &low);
+        emitter.single(qubit1, &high);
+        return emitter.finish(unitary);
+    }
+
+    // Work in SU(4) and in the magic basis
+    let special = unitary.scale(Complex::new_euler(1f64, -unitary.determinant().arg() / 4f64));
+    let magic = magic_basis();
+    let magic_unitary = &(&magic.adjoint() * &special) * &magic;
+
+    // M = U^T U is symmetric and unitary, so its real and imaginary parts are
+    // commuting real symmetric matrices: diagonalize both with a real orthogonal P.
+    let m = &magic_unitary.transpose() * &magic_unitary;
+    let (mut real, mut imaginary) = (Matrix::<Complex>::new(4), Matrix::<Complex>::new(4));
+    for i in 0..4 {
+        for j in 0..4 {
+            let value = (*m.get(i, j) + *m.get(j, i)) / 2f64;
+            real.set(i, j, &Complex::new(value.re(), 0f64));
+            imaginary.set(i, j, &Complex::new(value.im(), 0f64));
+        }
+    }
+    let vectors = real.eigh_commuting(&imaginary)?;
+    let mut p = Matrix::<Complex>::new(4);
+    for i in 0..4 {
+        for j in 0..4 {
+            p.set(i, j, &Complex::new(vectors.get(i, j).re(), 0f64));
+        }
+    }
+    if p.determinant().re() < 0f64 {
+        for i in 0..4 {
+            let value = -*p.get(i, 0);
+            p.set(i, 0, &value);
+        }
+    }
+
+    // P^T M P = diag(e^{2 i theta}), pick the square roots so that K1 is in SO(4)
+    let diagonal = &(&p.transpose() * &m) * &p;
+    let mut thetas : Vec<f64> = (0..4).map(|k| diagonal.get(k, k).arg() / 2f64).collect();
+    if Complex::new_euler(1f64, thetas.iter().sum::<f64>()).re() < 0f64 {
+        thetas[0] += PI;
+    }
+    let mut inverse_sqrt = Matrix::<Complex>::new(4);
+    for (k, theta) in thetas.iter().enumerate() {
+        inverse_sqrt.set(k, k, &Complex::new_euler(1f64, -theta));
+    }
+    let k1 = &(&magic_unitary * &p) * &inverse_sqrt;
+
+    // Back to the computational basis, where K1 and P^T are local gates
+    let left = &(&magic * &k1) * &magic.adjoint();
+    let right = &(&magic * &p.transpose()) * &magic.adjoint();
+    let (a1, a0, left_residual) = tensor_factor(&left);
+    let (b1, b0, right_residual) = tensor_factor(&right);
+    if left_residual > 1e-8 || right_residual > 1e-8 {
+        return Err(format!("Error: KAK decomposition failed, residuals {} {}!!", left_residual, right_residual));
+    }
+
+    // theta_k = a x_k + b y_k + c z_k (+ a global phase), where x, y and z are the
+    // eigenvalues of XX, YY and ZZ in the magic basis
+    let (t0, t1, t2, t3) = (thetas[0], thetas[1], thetas[2], thetas[3]);
+    let a = (t0 + t1 - t2 - t3) / 4f64;
+    let b = (-t0 + t1 - t2 + t3) / 4f64;
+    let c = (t0 - t1 - t2 + t3) / 4f64;
+    debug!("two_qubit_decomposition: a={} b={} c={}", a, b, c);
+
+    emitter.single(qubit0, &b0);
+    emitter.single(qubit1, &b1);
+    interaction(&mut emitter, a, b, c)?;
+    emitter.single(qubit0, &a0);
+    emitter.single(qubit1, &a1);
+    emitter.finish(unitary)
+}
+
+/// exp(i x P) = cos(x) I + i sin(x) P, for a Pauli matrix P.
+fn exp_pauli(pauli: &Matrix<Complex>, x: f64) -> Matrix<Complex> {
+    &Matrix::identity(2).scale(Complex::new(x.cos(), 0f64)) + &pauli.scale(Complex::new(0f64, x.sin()))
+}
+
+/// Emits exp(i(a XX + b YY + c ZZ)) on qubits 0 and 1, up to a global phase, with
+/// the fewest CX gates.
+///
+/// Coordinates that are multiples of pi/2 only contribute local gates, as
+/// exp(i k pi/2 PP) = i^k P^k x P^k. With at most two coordinates left, a local
+/// Clifford C maps their Paulis onto X and Z, and
+///
+/// ```text
+/// exp(i(p XX + q ZZ)) = CX10 (exp(i p X) x exp(i q Z)) CX10
+/// ```
+///
+/// A single coordinate of pi/4 (mod pi/2) is a CX up to local gates. Otherwise
+/// three CX are needed (Shende, Markov and Bullock, Phys. Rev. A 69, 062321, 2004).
+fn interaction(emitter: &mut Emitter, a: f64, b: f64, c: f64) -> Result<(), String> {
+    let (qubit0, qubit1) = (0, 1);
+    let s = 0.5f64.sqrt();
+    let (o, l, i) = (Complex::zero(), Complex::one(), Complex::i());
+    let (r, ri) = (Complex::new(s, 0f64), Complex::new(0f64, s));
+    let paulis = [
+        Matrix::new_from_row_slice(&[o, l, l, o]),
+        Matrix::new_from_row_slice(&[o, -i, i, o]),
+        Matrix::new_from_row_slice(&[l, o, o, -l]),
+    ];
+    let hadamard = Matrix::new_from_row_slice(&[r, r, r, -r]);
+    // Rx(pi/2) maps Y to Z (and X to itself), S^dagger maps Y to X (and Z to itself)
+    let rx = Matrix::new_from_row_slice(&[r, -ri, -ri, r]);
+    let s_adjoint = Matrix::new_from_row_slice(&[l, o, o, -i]);
+    // x = offset + k pi/2, if it is
+    let turns = |x: f64, offset: f64| {
+        let k = ((x - offset) / (PI / 2f64)).round();
+        if (x - offset - k * PI / 2f64).abs() < 1e-10 { Some(k as i64) } else { None }
+    };
+
+    let mut paulis_left = Vec::new();
+    let mut coordinates_left = Vec::new();
+    let mut locals = Vec::new();
+    for (k, &x) in [a, b, c].iter().enumerate() {
+        match turns(x, 0f64) {
+            Some(n) if n % 2 != 0 => locals.push(k),
+            Some(_) => (),
+            None => {
+                paulis_left.push(k);
+                coordinates_left.push(x);
+            },
+        }
+    }
+
+    if paulis_left.len() == 3 {
+        debug!("interaction: 3 CX");
+        // Vatan and Williams, Phys. Rev. A 69, 032315, 2004
+        emitter.single(qubit1, &rz(-PI / 2f64));
+        emitter.cx(qubit1, qubit0)?;
+        emitter.single(qubit0, &rz(PI / 2f64 - 2f64 * c));
+        emitter.single(qubit1, &ry(2f64 * a - PI / 2f64));
+        emitter.cx(qubit0, qubit1)?;
+        emitter.single(qubit1, &ry(PI / 2f64 - 2f64 * b));
+        emitter.cx(qubit1, qubit0)?;
+        emitter.single(qubit0, &rz(PI / 2f64));
+    } else if !paulis_left.is_empty() {
+        let clifford = match paulis_left.as_slice() {
+            [0] => hadamard.clone(),
+            [1] | [0, 1] => rx,
+            [1, 2] => s_adjoint,
+            _ => Matrix::identity(2),
+        };
+        let (p, q) = match coordinates_left.as_slice() {
+            [q] => (0f64, *q),
+            _ => (coordinates_left[0], coordinates_left[1]),
+        };
+        emitter.single(qubit0, &clifford);
+        emitter.single(qubit1, &clifford);
+        match (p == 0f64, turns(q, PI / 4f64)) {
+            (true, Some(n)) => {
+                debug!("interaction: 1 CX");
+                // exp(i pi/4 ZZ) = e^{-i pi/4} CZ (exp(i pi/4 Z) x exp(i pi/4 Z))
+                emitter.single(qubit0, &exp_pauli(&paulis[2], PI / 4f64));
+                emitter.single(qubit1, &exp_pauli(&paulis[2], PI / 4f64));
+                emitter.single(qubit0, &hadamard);
+                emitter.cx(qubit1, qubit0)?;
+                emitter.single(qubit0, &hadamard);
+                if n % 2 != 0 {
+                    emitter.single(qubit0, &paulis[2]);
+                    emitter.single(qubit1, &paulis[2]);
+                }
+            },
+            _ => {
+                debug!("interaction: 2 CX");
+                emitter.cx(qubit1, qubit0)?;
+                emitter.single(qubit1, &exp_pauli(&paulis[0], p));
+                emitter.single(qubit0, &exp_pauli(&paulis[2], q));
+                emitter.cx(qubit1, qubit0)?;
+            },
+        }
+        emitter.single(qubit0, &clifford.adjoint());
+        emitter.single(qubit1, &clifford.adjoint());
+    }
+    for k in locals {
+        emitter.single(qubit0, &paulis[k]);
+        emitter.single(qubit1, &paulis[k]);
+    }
+    Ok(())
+}
+
+/// Synthesizes an arbitrary n-qubit unitary with the quantum Shannon decomposition
+/// (Shende, Bullock and Markov, IEEE TCAD 25, 1000, 2006).
+///
+/// The cosine-sine decomposition splits the unitary into a multiplexed Ry on the
+/// most significant qubit between two block-diagonal unitaries, which in turn are
+/// demultiplexed into a multiplexed Rz between two unitaries on one qubit less.
+/// The recursion ends with `two_qubit_decomposition()`, giving 24 CX for three
+/// qubits, 120 for four and 528 for five. This is exponential in the number of
+/// qubits, in practice it is meant for up to 5 or 6 qubits.
+pub fn quantum_shannon_decomposition(unitary: &Matrix<Complex>) -> Result<Synthesis, String> {
+    let size = unitary.size();
+    if size < 2 || !size.is_power_of_two() {
+        return Err(format!("Error: Unitary size {} is not a power of two!!", size));
+    }
+    if !unitary.is_unitary(UNITARY_TOLERANCE) {
+        return Err("Error: Matrix is not unitary!!".to_string());
+    }
+    let number_of_qubits = size.trailing_zeros() as usize;
+    let qubits : Vec<usize> = (0..number_of_qubits).collect();
+    let mut emitter = Emitter::new(number_of_qubits);
+    qsd(&mut emitter, unitary, &qubits)?;
+    emitter.finish(unitary)
+}
+
+/// Emits `unitary`, with its bit `k` on `qubits[k]`, up to a global phase.
+fn qsd(emitter: &mut Emitter, unitary: &Matrix<Complex>, qubits: &[usize]) -> Result<(), String> {
+    match qubits.len() {
+        1 => {
+            emitter.single(qubits[0], unitary);
+            return Ok(());
+        },
+        2 => return emitter.append(&kak(unitary)?, qubits),
+        _ => (),
+    }
+
+    // Cosine-sine decomposition:
+    //   [U00 U01]   [L0   ] [C -S] [R0   ]
+    //   [U10 U11] = [   L1] [S  C] [   R1]
+    let half = unitary.size() / 2;
+    let (u00, u01) = (submatrix(unitary, 0, 0, half), submatrix(unitary, 0, half, half));
+    let (u10, u11) = (submatrix(unitary, half, 0, half), submatrix(unitary, half, half, half));
+
+    // U00^dagger U00 = R0^dagger C^2 R0, then the columns of U00 R0^dagger and
+    // U10 R0^dagger are the ones of L0 and L1 scaled by the cosines and sines.
+    let (_, r0_adjoint) = (&u00.adjoint() * &u00).eigh()?;
+    let (l0, cosines) = normalize_columns(&(&u00 * &r0_adjoint));
+    let (l1, sines) = normalize_columns(&(&u10 * &r0_adjoint));
+
+    // R1 from U11 = L1 C R1 or U01 = -L0 S R1, whichever is better conditioned
+    let (l1_u11, l0_u01) = (&l1.adjoint() * &u11, &l0.adjoint() * &u01);
+    let mut r1 = Matrix::<Complex>::new(half);
+    for k in 0..half {
+        for j in 0..half {
+            let value = if cosines[k] >= sines[k] {
+                *l1_u11.get(k, j) / cosines[k]
+            } else {
+                -*l0_u01.get(k, j) / sines[k]
+            };
+            r1.set(k, j, &value);
+        }
+    }
+
+    let (target, controls) = (qubits[qubits.len() - 1], &qubits[..qubits.len() - 1]);
+    let angles : Vec<f64> = cosines.iter().zip(sines.iter())
+        .map(|(c, s)| 2f64 * s.atan2(*c))
+        .collect();
+    demultiplex(emitter, &r0_adjoint.adjoint(), &r1, qubits)?;
+    uniformly_controlled(emitter, ry, &angles, controls, target, false)?;
+    demultiplex(emitter, &l0, &l1, qubits)
+}
+
+/// Emits the block-diagonal unitary `u0 (+) u1`, selected by the last of `qubits`,
+/// as `(I x V) (D (+) D^dagger) (I x W)`, where `u0 u1^dagger = V D^2 V^dagger`.
+fn demultiplex(emitter: &mut Emitter, u0: &Matrix<Complex>, u1: &Matrix<Complex>, qubits: &[usize]) -> Result<(), String> {
+    let (eigenvalues, v) = (u0 * &u1.adjoint()).eig_unitary()?;
+    let mut d = Matrix::<Complex>::new(u0.size());
+    let mut angles = Vec::with_capacity(u0.size());
+    for (k, eigenvalue) in eigenvalues.iter().enumerate() {
+        d.set(k, k, &Complex::new_euler(1f64, eigenvalue.arg() / 2f64));
+        // diag(d, d*) = Rz(-2 arg(d))
+        angles.push(-eigenvalue.arg());
+    }
+    let w = &(&d * &v.adjoint()) * u1;
+
+    let (target, low) = (qubits[qubits.len() - 1], &qubits[..qubits.len() - 1]);
+    qsd(emitter, &w, low)?;
+    uniformly_controlled(emitter, rz, &angles, low, target, false)?;
+    qsd(emitter, &v, low)
+}
+
+/// Emits a rotation on `target` by `angles[k]`, where `k` is the state of the
+/// `controls` (with `controls[j]` as bit `j`), using 2^len(controls) CX:
+/// UCR(a) = CX UCR((a0 - a1) / 2) CX UCR((a0 + a1) / 2) on the last control.
+///
+/// The gates of a uniformly controlled rotation commute, so the second half is
+/// emitted in reverse order (`reversed`) and the CX gates in the middle cancel.
+fn uniformly_controlled(emitter: &mut Emitter, rotation: fn(f64) -> Matrix<Complex>, angles: &[f64],
+                        controls: &[usize], target: usize, reversed: bool) -> Result<(), String> {
+    if controls.is_empty() {
+        emitter.single(target, &rotation(angles[0]));
+        return Ok(());
+    }
+
+    let half = angles.len() / 2;
+    let (control, rest) = (controls[controls.len() - 1], &controls[..controls.len() - 1]);
+    let sums : Vec<f64> = (0..half).map(|k| (angles[k] + angles[k + half]) / 2f64).collect();
+    let differences : Vec<f64> = (0..half).map(|k| (angles[k] - angles[k + half]) / 2f64).collect();
+    // X R(a) X = R(-a) for both Ry and Rz
+    if reversed {
+        emitter.cx(control, target)?;
+        uniformly_controlled(emitter, rotation, &differences, rest, target, false)?;
+        emitter.cx(control, target)?;
+        uniformly_controlled(emitter, rotation, &sums, rest, target, true)
+    } else {
+        uniformly_controlled(emitter, rotation, &sums, rest, target, false)?;
+        emitter.cx(control, target)?;
+        uniformly_controlled(emitter, rotation, &differences, rest, target, true)?;
+        emitter.cx(control, target)
+    }
+}
+
+fn submatrix(m: &Matrix<Complex>, row: usize, column: usize, size: usize) -> Matrix<Complex> {
+    let mut block = Matrix::<Complex>::new(size);
+    for i in 0..size {
+        for j in 0..size {
+            block.set(i, j, m.get(row + i, column + j));
+        }
+    }
+    block
+}
+
+/// Normalizes the (orthogonal) columns of `m`, returning the unitary and the norms.
+///
+/// The columns with a norm below 1/sqrt(2) are orthonormalized against the others
+/// with Gram-Schmidt, and replaced by a basis vector if they are too small to
+/// give a direction.
+fn normalize_columns(m: &Matrix<Complex>) -> (Matrix<Complex>, Vec<f64>) {
+    let size = m.size();
+    let norm = |v: &[Complex]| v.iter().map(|x| x.norm_sqr()).sum::<f64>().sqrt();
+    let columns : Vec<Vec<Complex>> = (0..size).map(|k| m.column(k)).collect();
+    let norms : Vec<f64> = columns.iter().map(|column| norm(column)).collect();
+
+    let mut result : Vec<Option<Vec<Complex>>> = columns.iter().zip(norms.iter())
+        .map(|(column, &n)| if n >= 0.5f64.sqrt() {
+            Some(column.iter().map(|x| *x / n).collect())
+        } else {
+            None
+        })
+        .collect();
+    for k in 0..size {
+        if result[k].is_some() {
+            continue;
+        }
+        let done : Vec<Vec<Complex>> = result.iter().filter_map(|column| column.clone()).collect();
+        let orthogonalize = |mut v: Vec<Complex>| {
+            // Twice, for numerical stability
+            for _ in 0..2 {
+                for d in done.iter() {
+                    let overlap = d.iter().zip(v.iter()).fold(Complex::zero(), |acc, (x, y)| acc + x.conj() * *y);
+                    for (x, y) in v.iter_mut().zip(d.iter()) {
+                        *x -= overlap * *y;
+                    }
+                }
+            }
+            v
+        };
+
+        let mut candidate = orthogonalize(columns[k].iter().map(|x| *x / norms[k].max(1e-300)).collect());
+        if norm(&candidate) < 0.5f64 {
+            candidate = (0..size)
+                .map(|j| orthogonalize((0..size).map(|i| if i == j { Complex::one() } else { Complex::zero() }).collect()))
+                .fold(vec![Complex::zero(); size], |best, v| if norm(&v) > norm(&best) { v } else { best });
+        }
+        let n = norm(&candidate);
+        result[k] = Some(candidate.iter().map(|x| *x / n).collect());
+    }
+
+    let mut unitary = Matrix::<Complex>::new(size);
+    for (k, column) in result.iter().enumerate() {
+        for (i, value) in column.as_ref().unwrap().iter().enumerate() {
+            unitary.set(i, k, value);
+        }
+    }
+    (unitary, norms)
+}
+
+#[cfg(test)]
+fn assert_u3_roundtrip(gate: &Gate<Complex>) {
+    let angles = u3_from_unitary(gate).unwrap();
+    let rebuilt = angles.gate().matrix.scale(Complex::new_euler(1f64, angles.phase));
+    assert!(rebuilt.max_deviation(&gate.matrix) < 1e-12, "{:?} {}", angles, gate);
+}
+
+#[test]
+fn u3_from_unitary_test() {
+    use linalg::test_unitary;
+    use num::{Zero, One};
+
+    for seed in 0..20 {
+        assert_u3_roundtrip(&Gate::new(2, test_unitary(2, seed)));
+    }
+
+    // theta == 0 and theta == pi, where half of the entries vanish
+    assert_u3_roundtrip(&Gate::<Complex>::u(0f64, 0.3f64, 1.1f64));
+    assert_u3_roundtrip(&Gate::<Complex>::u(PI, -2f64, 0.7f64));
+    assert_u3_roundtrip(&Gate::<Complex>::u(1e-9f64, 2f64, -3f64));
+    assert_u3_roundtrip(&Gate::from_slice(&[Complex::zero(), Complex::i(), Complex::i(), Complex::zero()]));
+    assert_u3_roundtrip(&Gate::from_slice(&[-Complex::one(), Complex::zero(), Complex::zero(), Complex::i()]));
+
+    let angles = u3_from_unitary(&Gate::<Complex>::u(0.5f64, 0.25f64, -1f64)).unwrap();
+    assert!((angles.theta - 0.5f64).abs() < 1e-12);
+    assert!((angles.phi - 0.25f64).abs() < 1e-12);
+    assert!((angles.lam + 1f64).abs() < 1e-12);
+    assert!(angles.phase.abs() < 1e-12);
+    assert_eq!(json!({"name": "U", "qubits": [3], "params": [angles.theta, angles.phi, angles.lam]}),
+               angles.to_operation(3));
+
+    assert!(u3_from_unitary(&Gate::from_slice(&[Complex::one(), Complex::one(), Complex::zero(), Complex::one()])).is_err());
+    assert!(u3_from_unitary(&Gate::<Complex>::new(4, ::matrix::Matrix::identity(4))).is_err());
+}
+
+#[cfg(test)]
+fn assert_synthesis(unitary: &Matrix<Complex>, synthesis: &Synthesis, number_of_qubits: usize) {
+    let circuit = simulate(&synthesis.operations, number_of_qubits).unwrap();
+    let rebuilt = circuit.scale(Complex::new_euler(1f64, synthesis.phase));
+    assert!(rebuilt.max_deviation(unitary) < 1e-9, "deviation {}", rebuilt.max_deviation(unitary));
+}
+
+#[test]
+fn two_qubit_decomposition_test() {
+    use linalg::test_unitary;
+
+    for seed in 0..10 {
+        let unitary = test_unitary(4, seed);
+        let synthesis = two_qubit_decomposition(&unitary, 0, 1).unwrap();
+        assert_eq!(3, synthesis.cx_count());
+        assert_synthesis(&unitary, &synthesis, 2);
+    }
+
+    // CX and SWAP are in the boundaries of the Weyl chamber
+    let cx = m_real![1, 0, 0, 0; 0, 0, 0, 1; 0, 0, 1, 0; 0, 1, 0, 0];
+    let synthesis = two_qubit_decomposition(&cx, 0, 1).unwrap();
+    assert_eq!(1, synthesis.cx_count());
+    assert_synthesis(&cx, &synthesis, 2);
+    let swap = m_real![1, 0, 0, 0; 0, 0, 1, 0; 0, 1, 0, 0; 0, 0, 0, 1];
+    let synthesis = two_qubit_decomposition(&swap, 0, 1).unwrap();
+    assert_eq!(3, synthesis.cx_count());
+    assert_synthesis(&swap, &synthesis, 2);
+
+    // Fewer CX when some of the coordinates vanish, with random local gates around
+    let locals = |seed: u64| test_unitary(2, seed).kronecker(&test_unitary(2, seed + 1));
+    let canonical = |a: f64, b: f64, c: f64| {
+        let (x, y, z) = (m_real![0, 1; 1, 0], Matrix::new_from_row_slice(&[Complex::zero(), -Complex::i(), Complex::i(), Complex::zero()]),
+                         m_real![1, 0; 0, -1]);
+        let generator = &(&x.kronecker(&x).scale(Complex::new(a, 0f64)) + &y.kronecker(&y).scale(Complex::new(b, 0f64))) +
+                        &z.kronecker(&z).scale(Complex::new(c, 0f64));
+        generator.scale(Complex::i()).expm()
+    };
+    for &((a, b, c), cx_count) in [((PI / 4f64, 0f64, 0f64), 1), ((0f64, -PI / 4f64, 0f64), 1), ((0f64, 0f64, 3f64 * PI / 4f64), 1),
+                                   ((0.3f64, 0f64, 0f64), 2), ((0.3f64, 0.2f64, 0f64), 2), ((0f64, 0.3f64, -0.5f64), 2),
+                                   ((0.7f64, PI / 2f64, -0.1f64), 2), ((PI / 4f64, PI / 4f64, 0f64), 2),
+                                   ((0.3f64, 0.2f64, 0.1f64), 3)].iter() {
+        for seed in 0..3 {
+            let unitary = &(&locals(2 * seed) * &canonical(a, b, c)) * &locals(2 * seed + 7);
+            let synthesis = two_qubit_decomposition(&unitary, 0, 1).unwrap();
+            assert_eq!(cx_count, synthesis.cx_count(), "({}, {}, {})", a, b, c);
+            assert_synthesis(&unitary, &synthesis, 2);
+        }
+    }
+
+    // M = U^T U with eigenvalues 1 and e^{2i atan(0.618)}, which used to collide
+    // when diagonalizing Re(M) + 0.618 Im(M)
+    let mut rotation = Matrix::<Complex>::identity(4);
+    for &(i, j, angle) in [(0, 1, 0.4f64), (1, 2, -1.1f64), (2, 3, 0.7f64), (0, 3, 2f64)].iter() {
+        let mut givens = Matrix::<Complex>::identity(4);
+        givens.set(i, i, &Complex::new(angle.cos(), 0f64));
+        givens.set(j, j, &Complex::new(angle.cos(), 0f64));
+        givens.set(i, j, &Complex::new(-angle.sin(), 0f64));
+        givens.set(j, i, &Complex::new(angle.sin(), 0f64));
+        rotation = &rotation * &givens;
+    }
+    let w = 0.6180339887498949f64.atan();
+    let mut diagonal = Matrix::<Complex>::new(4);
+    for (k, &angle) in [0f64, w, 0.3f64, -0.3f64 - w].iter().enumerate() {
+        diagonal.set(k, k, &Complex::new_euler(1f64, angle));
+    }
+    let magic = magic_basis();
+    let unitary = &(&(&magic * &diagonal) * &rotation.transpose()) * &magic.adjoint();
+    assert_synthesis(&unitary, &two_qubit_decomposition(&unitary, 0, 1).unwrap(), 2);
+
+    // Local gates don't need CX
+    let local = test_unitary(2, 1).kronecker(&test_unitary(2, 2));
+    let synthesis = two_qubit_decomposition(&local, 0, 1).unwrap();
+    assert_eq!(0, synthesis.cx_count());
+    assert_synthesis(&local, &synthesis, 2);
+
+    // Qubits other than 0 and 1: the decomposition of a SWAP is still a SWAP
+    let synthesis = two_qubit_decomposition(&swap, 2, 0).unwrap();
+    assert_eq!(json!(3), synthesis.to_circuit(3)["header"]["number_of_qubits"]);
+    let mut swap_02 = Matrix::<Complex>::new(8);
+    for x in 0..8 {
+        swap_02.set((x & 0b010) | ((x & 1) << 2) | (x >> 2), x, &Complex::one());
+    }
+    assert_synthesis(&swap_02, &synthesis, 3);
+
+    assert!(two_qubit_decomposition(&Matrix::identity(2), 0, 1).is_err());
+    assert!(two_qubit_decomposition(&cx, 1, 1).is_err());
+}
+
+#[test]
+fn quantum_shannon_decomposition_test() {
+    use linalg::test_unitary;
+
+    for &(size, seed) in [(2, 3), (4, 4), (8, 5), (8, 6), (16, 7)].iter() {
+        let unitary = test_unitary(size, seed);
+        let synthesis = quantum_shannon_decomposition(&unitary).unwrap();
+        assert_synthesis(&unitary, &synthesis, size.trailing_zeros() as usize);
+    }
+    assert_eq!(24, quantum_shannon_decomposition(&test_unitary(8, 8)).unwrap().cx_count());
+
+    // Toffoli: cosines and sines are all 0 or 1
+    let mut toffoli = Matrix::<Complex>::identity(8);
+    toffoli.set(3, 3, &Complex::zero());
+    toffoli.set(7, 7, &Complex::zero());
+    toffoli.set(3, 7, &Complex::one());
+    toffoli.set(7, 3, &Complex::one());
+    assert_synthesis(&toffoli, &quantum_shannon_decomposition(&toffoli).unwrap(), 3);
+
+    // u0 u1^dagger with eigenvalues 1 and e^{2i atan(0.618)}, which used to
+    // collide in eig_unitary
+    let phi = 2f64 * 0.6180339887498949f64.atan();
+    let mut diagonal = Matrix::<Complex>::new(4);
+    for (k, &angle) in [0f64, phi, 0.5f64, PI].iter().enumerate() {
+        diagonal.set(k, k, &Complex::new_euler(1f64, angle));
+    }
+    let v = test_unitary(4, 10);
+    let u1 = test_unitary(4, 9);
+    let u0 = &(&(&v * &diagonal) * &v.adjoint()) * &u1;
+    let mut multiplexed = Matrix::<Complex>::new(8);
+    for i in 0..4 {
+        for j in 0..4 {
+            multiplexed.set(i, j, u0.get(i, j));
+            multiplexed.set(i + 4, j + 4, u1.get(i, j));
+        }
+    }
+    let mut emitter = Emitter::new(3);
+    demultiplex(&mut emitter, &u0, &u1, &[0, 1, 2]).unwrap();
+    assert_synthesis(&multiplexed, &emitter.finish(&multiplexed).unwrap(), 3);
+    assert_synthesis(&multiplexed, &quantum_shannon_decomposition(&multiplexed).unwrap(), 3);
+
+    assert!(quantum_shannon_decomposition(&Matrix::identity(3)).is_err());
+    assert!(quantum_shannon_decomposition(&m_real![1, 1; 0, 1]).is_err());
+}